@@ -0,0 +1,185 @@
+; Verify decimal mode behavior of the NMOS 6502 - Bruce Clark's test (public domain),
+; as used by Klaus Dormann's 6502_decimal_test. Every pair of operands is added and
+; subtracted with both values of the carry, and the accumulator, N, V, Z and C are
+; compared with the results predicted using binary arithmetic.
+;
+; Start at $0200. It ends in a JMP to itself with ERROR ($0B) = 0 when the test passed,
+; 1 when it failed - then N1, N2 and Y (the carry) are the failing operands.
+;
+; Assemble with the built-in assembler (src/assembler.rs) into 6502_decimal_test.mem.
+
+AR      = $00       ; predicted accumulator
+CF      = $01       ; predicted carry
+DA      = $02       ; actual accumulator in decimal mode
+DNVZC   = $03       ; actual flags in decimal mode
+HA      = $04       ; accumulator of the binary operation
+HNVZC   = $05       ; flags of the binary operation
+N1      = $06
+N1H     = $07
+N1L     = $08
+N2      = $09
+N2L     = $0A
+ERROR   = $0B
+NF      = $0C       ; predicted N
+VF      = $0D       ; predicted V
+ZF      = $0E       ; predicted Z
+N2H     = $0F       ; and $10
+
+        .org $0200
+START:  jsr TEST
+DONE:   jmp DONE
+
+TEST:   ldy #1      ; loop through the carry values
+        sty ERROR   ; 1 until the test passes
+        lda #0
+        sta N1
+        sta N2
+LOOP1:  lda N2      ; N2L = N2 & $0F
+        and #$0F
+        sta N2L
+        lda N2      ; N2H = N2 & $F0
+        and #$F0
+        sta N2H
+        ora #$0F    ; N2H+1 = (N2 & $F0) + $0F
+        sta N2H+1
+LOOP2:  lda N1      ; N1L = N1 & $0F
+        and #$0F
+        sta N1L
+        lda N1      ; N1H = N1 & $F0
+        and #$F0
+        sta N1H
+        jsr ADD
+        jsr A6502
+        jsr COMPARE
+        bne FAILED
+        jsr SUB
+        jsr S6502
+        jsr COMPARE
+        bne FAILED
+        inc N1      ; all 256 values of N1
+        bne LOOP2
+        inc N2      ; all 256 values of N2
+        bne LOOP1
+        dey         ; both values of the carry
+        bpl LOOP1
+        lda #0      ; passed
+        sta ERROR
+FAILED: rts
+
+; The actual decimal result, the binary result, and the predicted accumulator, C and V.
+ADD:    sed
+        cpy #1      ; carry set when Y = 1
+        lda N1
+        adc N2
+        sta DA
+        php
+        pla
+        sta DNVZC
+        cld
+        cpy #1
+        lda N1
+        adc N2
+        sta HA
+        php
+        pla
+        sta HNVZC
+        cpy #1
+        lda N1L
+        adc N2L
+        cmp #$0A
+        ldx #0
+        bcc A1
+        inx
+        adc #5      ; add 6 (carry is set)
+        and #$0F
+        sec
+A1:     ora N1H
+        adc N2H,x   ; N2 & $F0, or (N2 & $F0) + $0F + 1 (carry is set) after a digit carry
+        php
+        bcs A2
+        cmp #$A0
+        bcc A3
+A2:     adc #$5F    ; add $60 (carry is set)
+        sec
+A3:     sta AR
+        php
+        pla
+        sta CF
+        pla         ; all the flags after adding the high digits, V among them
+        sta VF
+        rts
+
+; The actual decimal result and the binary result of N1 - N2.
+SUB:    sed
+        cpy #1
+        lda N1
+        sbc N2
+        sta DA
+        php
+        pla
+        sta DNVZC
+        cld
+        cpy #1
+        lda N1
+        sbc N2
+        sta HA
+        php
+        pla
+        sta HNVZC
+        rts
+
+; The predicted accumulator of SBC on the 6502.
+SUB1:   cpy #1
+        lda N1L
+        sbc N2L
+        ldx #0
+        bcs S11
+        inx
+        sbc #5      ; subtract 6 (carry is clear)
+        and #$0F
+        clc
+S11:    ora N1H
+        sbc N2H,x   ; N2 & $F0, or (N2 & $F0) + $0F + 1 (carry is clear) after a digit borrow
+        bcs S12
+        sbc #$5F    ; subtract $60 (carry is clear)
+S12:    sta AR
+        rts
+
+; Z flag set when the actual results match the predicted ones.
+COMPARE:
+        lda DA
+        cmp AR
+        bne C1
+        lda DNVZC
+        eor NF
+        and #$80    ; N
+        bne C1
+        lda DNVZC
+        eor VF
+        and #$40    ; V
+        bne C1
+        lda DNVZC
+        eor ZF
+        and #$02    ; Z
+        bne C1
+        lda DNVZC
+        eor CF
+        and #$01    ; C
+C1:     rts
+
+; The 6502 takes N from the high digits before the decimal adjustment, and Z from the
+; binary result.
+A6502:  lda VF
+        sta NF
+        lda HNVZC
+        sta ZF
+        rts
+
+; The flags of SBC are the binary ones.
+S6502:  jsr SUB1
+        lda HNVZC
+        sta NF
+        sta VF
+        sta ZF
+        sta CF
+        rts
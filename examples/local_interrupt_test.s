@@ -0,0 +1,168 @@
+; Local interrupt test of this project - not Klaus Dormann's 6502_interrupt_test, only
+; modelled after it: IRQ, NMI and BRK driven through a feedback port at $BFFC - writing
+; bit 0 holds the IRQ line, a rising edge of bit 1 triggers an NMI.
+;
+; Start at $0400 (the cold reset). The number of the current test is kept at $0200,
+; any failure ends in a JMP to FAILED, the success in a JMP to SUCCESS.
+;
+; Assemble with the built-in assembler (src/assembler.rs) into local_interrupt_test.mem.
+
+PORT        = $BFFC
+TEST_CASE   = $0200
+
+IRQ_COUNT   = $10
+BRK_COUNT   = $11
+NMI_COUNT   = $12
+IRQ_P       = $13   ; P pushed by the last IRQ or BRK
+NMI_P       = $14   ; P pushed by the last NMI
+
+        .org $0400
+START:  cld
+        ldx #$FF
+        txs
+        lda #0
+        sta PORT
+
+; 1: a held IRQ line is ignored while I is set
+        inc TEST_CASE
+        sei
+        lda #1
+        sta PORT
+        nop
+        nop
+        lda IRQ_COUNT
+        bne FAILED
+        lda #0
+        sta PORT
+
+; 2: an IRQ is serviced once I is clear, pushing P with B clear and I clear
+        inc TEST_CASE
+        cli
+        lda #1
+        sta PORT    ; the handler releases the line
+        nop
+        sei
+        lda IRQ_COUNT
+        cmp #1
+        bne FAILED
+        lda IRQ_P
+        and #$34    ; B, the unused bit and I
+        cmp #$20
+        bne FAILED
+
+; 3: RTI restores I
+        inc TEST_CASE
+        cli
+        lda #1
+        sta PORT
+        nop
+        php
+        pla
+        and #$04
+        bne FAILED
+        sei
+        lda IRQ_COUNT
+        cmp #2
+        bne FAILED
+
+; 4: an NMI is serviced even with I set, pushing P with B clear
+        inc TEST_CASE
+        lda #2
+        sta PORT
+        nop
+        lda NMI_COUNT
+        cmp #1
+        bne FAILED
+        lda NMI_P
+        and #$34
+        cmp #$24
+        bne FAILED
+        jmp TEST5
+
+FAILED: jmp FAILED  ; in the middle, for the branches to reach it
+
+; 5: NMI is edge triggered - a line held high does not trigger it again
+TEST5:  inc TEST_CASE
+        nop
+        nop
+        nop
+        lda NMI_COUNT
+        cmp #1
+        bne FAILED
+        lda #0
+        sta PORT
+        lda #2
+        sta PORT
+        nop
+        lda NMI_COUNT
+        cmp #2
+        bne FAILED
+        lda #0
+        sta PORT
+
+; 6: BRK goes through the IRQ vector with B set, and returns past its signature byte
+        inc TEST_CASE
+        ldx #0
+        brk
+        .byte $E8   ; INX, executed when the return address is off by one
+        cpx #0
+        bne FAILED
+        lda BRK_COUNT
+        cmp #1
+        bne FAILED
+        lda IRQ_P
+        and #$10
+        beq FAILED
+
+; 7: NMI and IRQ at the same time - both are serviced
+        inc TEST_CASE
+        cli
+        lda #3
+        sta PORT
+        nop
+        nop
+        sei
+        lda NMI_COUNT
+        cmp #3
+        bne FAILED
+        lda IRQ_COUNT
+        cmp #3
+        bne FAILED
+
+SUCCESS:
+        jmp SUCCESS
+
+IRQ:    pha
+        txa
+        pha
+        tsx
+        lda $0103,x ; the pushed P
+        sta IRQ_P
+        and #$10
+        bne IRQ_BRK
+        inc IRQ_COUNT
+        lda #0      ; release the line
+        sta PORT
+        jmp IRQ_END
+IRQ_BRK:
+        inc BRK_COUNT
+IRQ_END:
+        pla
+        tax
+        pla
+        rti
+
+NMI:    pha
+        txa
+        pha
+        tsx
+        lda $0103,x
+        sta NMI_P
+        inc NMI_COUNT
+        pla
+        tax
+        pla
+        rti
+
+        .org $FFFA
+        .word NMI, START, IRQ
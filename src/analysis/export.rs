@@ -0,0 +1,442 @@
+// Exports a range of memory as source which reassembles to the same bytes.
+//
+// Code comes from the control flow analysis, everything else is emitted as bytes,
+// with runs of printable characters quoted as ATASCII strings. Branch, jump and
+// absolute operand targets inside of the range get labels; targets in the middle
+// of an instruction are written relative to the label of that instruction.
+// Absolute operands below $0100 are forced with `a:` (the `.w` size suffix of the
+// mnemonic for MADS), otherwise the assembler would pick the shorter zero page encoding.
+//
+// Known symbols replace the generated labels and label the lines they point at,
+// the ones used outside of the range are defined as equates at the top. Stores use
+// the write names of hardware registers, the rest the read names.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+
+use super::decode;
+use super::flow::ByteKind;
+use super::flow::Flow;
+use super::Instruction;
+use crate::assembler::assemble;
+use crate::assembler::AssemblyError;
+use crate::cpu::Addressing;
+use crate::cpu::OPCODE_INFO;
+use crate::symbols::Direction;
+use crate::symbols::Symbols;
+
+const BYTES_PER_LINE: usize = 8;
+const MIN_STRING: usize = 4;
+
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Syntax {
+    Ca65,
+    Mads
+}
+
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub enum VerifyError {
+    Assembly(AssemblyError),
+    Missing(u16),                                  // range byte the source does not produce
+    Mismatch { address: u16, expected: u8, found: u8 },
+    Outside(u16)                                   // source produces a byte outside of the range
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifyError::Assembly(error) => write!(f, "{}", error),
+            VerifyError::Missing(address) => write!(f, "${:04X} is not assembled", address),
+            VerifyError::Mismatch { address, expected, found } => write!(f, "${:04X} assembles to ${:02X}, expected ${:02X}", address, found, expected),
+            VerifyError::Outside(address) => write!(f, "${:04X} is assembled, outside of the range", address)
+        }
+    }
+}
+
+impl Error for VerifyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            VerifyError::Assembly(error) => Some(error),
+            _ => None
+        }
+    }
+}
+
+// The symbol's name when the assembler can take it as a label for exactly this address.
+fn symbol_name(symbols: &Symbols, address: u16, direction: Direction) -> Option<&str> {
+    let name = symbols.name_for(address, direction)?;
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') &&
+        name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') &&
+        !matches!(name.to_ascii_lowercase().as_str(), "a" | "x" | "y" | "org" | "dta") &&
+        !OPCODE_INFO.iter().any(|info| info.map(|info| info.name.eq_ignore_ascii_case(name)).unwrap_or(false));
+    let unique = symbols.symbol(name).map(|symbol| symbol.address) == Some(address);
+
+    if valid && unique { Some(name) } else { None }
+}
+
+// Characters which are the same in ATASCII and ASCII, without the quotes - $60 is
+// a diamond in ATASCII.
+fn printable(byte: u8) -> bool {
+    ((0x20..=0x5f).contains(&byte) || (0x61..=0x7a).contains(&byte)) && byte != b'"' && byte != b'\''
+}
+
+// Absolute addressing of a zero page address, which the assembler has to be told about.
+fn forced_absolute(instruction: &Instruction) -> bool {
+    let absolute = matches!(instruction.info.mode, Addressing::Absolute | Addressing::AbsoluteX | Addressing::AbsoluteY);
+    absolute && instruction.operand < 0x100
+}
+
+struct Exporter<'a> {
+    memory: &'a [u8],
+    syntax: Syntax,
+    symbols: &'a Symbols,
+    range: RangeInclusive<u16>,
+    instructions: BTreeMap<u16, Instruction>,
+    owners: BTreeMap<u16, u16>, // operand byte to its instruction
+    labels: BTreeSet<u16>
+}
+
+impl<'a> Exporter<'a> {
+    fn label(&self, address: u16) -> String {
+        match symbol_name(self.symbols, address, Direction::Both) {
+            Some(name) => name.to_string(),
+            None => format!("L_{:04X}", address)
+        }
+    }
+
+    fn reference(&self, address: u16, direction: Direction) -> String {
+        match self.owners.get(&address) {
+            Some(&owner) => format!("{}+{}", self.label(owner), address - owner),
+            None if self.labels.contains(&address) => self.label(address),
+            None => match symbol_name(self.symbols, address, direction) {
+                Some(name) if !self.range.contains(&address) => name.to_string(),
+                _ => format!("${:04X}", address)
+            }
+        }
+    }
+
+    // Zero page operands take only equates, labels inside of the range may be defined
+    // after their use, too late for the assembler to pick the zero page encoding.
+    fn zero_page(&self, address: u16, direction: Direction) -> String {
+        match symbol_name(self.symbols, address, direction) {
+            Some(name) if !self.range.contains(&address) => name.to_string(),
+            _ => format!("${:02X}", address)
+        }
+    }
+
+    fn operand(&self, instruction: &Instruction) -> String {
+        let operand = instruction.operand;
+        let direction = instruction.direction();
+        let absolute = || {
+            let prefix = if forced_absolute(instruction) && self.syntax == Syntax::Ca65 { "a:" } else { "" };
+            format!("{}{}", prefix, self.reference(operand, direction))
+        };
+
+        match instruction.info.mode {
+            Addressing::Implied | Addressing::Accumulator => String::new(),
+            Addressing::Immediate => format!("#${:02X}", operand),
+            Addressing::Relative => self.reference(instruction.target().unwrap(), direction),
+            Addressing::ZeroPage => self.zero_page(operand, direction),
+            Addressing::ZeroPageX => format!("{},x", self.zero_page(operand, direction)),
+            Addressing::ZeroPageY => format!("{},y", self.zero_page(operand, direction)),
+            Addressing::Absolute => absolute(),
+            Addressing::AbsoluteX => format!("{},x", absolute()),
+            Addressing::AbsoluteY => format!("{},y", absolute()),
+            Addressing::Indirect => format!("({})", self.reference(operand, direction)),
+            Addressing::IndirectX => format!("({},x)", self.zero_page(operand, direction)),
+            Addressing::IndirectY => format!("({}),y", self.zero_page(operand, direction))
+        }
+    }
+
+    fn line(&self, text: &mut String, address: u16, statement: &str) {
+        let name = if self.labels.contains(&address) { self.label(address) } else { String::new() };
+        let name = match (self.syntax, name.is_empty()) {
+            (Syntax::Ca65, false) => name + ":",
+            _ => name
+        };
+        writeln!(text, "{:<8}{}", name, statement).unwrap();
+    }
+
+    fn data(&self, text: &mut String, start: u16, bytes: &[u8]) {
+        let directive = match self.syntax {
+            Syntax::Ca65 => ".byte",
+            Syntax::Mads => "dta"
+        };
+
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let string = bytes[offset..].iter().take_while(|&&byte| printable(byte)).count();
+            let (count, items) = if string >= MIN_STRING {
+                let characters = String::from_utf8_lossy(&bytes[offset..offset + string]).into_owned();
+                let item = match self.syntax {
+                    Syntax::Ca65 => format!("\"{}\"", characters),
+                    Syntax::Mads => format!("c'{}'", characters)
+                };
+                (string, item)
+            } else {
+                // stop before the next string, so that it gets quoted
+                let count = (offset..bytes.len())
+                    .find(|&index| index - offset >= BYTES_PER_LINE || bytes[index..].iter().take_while(|&&byte| printable(byte)).count() >= MIN_STRING)
+                    .unwrap_or(bytes.len()) - offset;
+                let items: Vec<String> = bytes[offset..offset + count].iter().map(|byte| format!("${:02X}", byte)).collect();
+                (count, items.join(","))
+            };
+
+            self.line(text, start.wrapping_add(offset as u16), &format!("{} {}", directive, items));
+            offset += count;
+        }
+    }
+}
+
+// Source for the range, with code where the flow analysis found it.
+pub fn export(memory: &[u8], range: RangeInclusive<u16>, flow: &Flow, syntax: Syntax, symbols: &Symbols) -> String {
+    let start = *range.start() as usize;
+    let end = *range.end() as usize;
+
+    let mut exporter = Exporter {
+        memory: memory,
+        syntax: syntax,
+        symbols: symbols,
+        range: range.clone(),
+        instructions: BTreeMap::new(),
+        owners: BTreeMap::new(),
+        labels: BTreeSet::new()
+    };
+
+    // instructions which lie completely inside of the range and overlap nothing
+    for address in start..=end {
+        if flow.kinds[address] != ByteKind::Code {
+            continue;
+        }
+        if let Some(instruction) = decode(memory, address as u16) {
+            let last = address + instruction.info.length as usize - 1;
+            if last <= end && (address + 1..=last).all(|operand| flow.kinds[operand] == ByteKind::Operand) {
+                for operand in address + 1..=last {
+                    exporter.owners.insert(operand as u16, address as u16);
+                }
+                exporter.instructions.insert(address as u16, instruction);
+            }
+        }
+    }
+
+    let mut targets = BTreeSet::new();
+    let mut equates = BTreeSet::new();
+    for instruction in exporter.instructions.values() {
+        let address = match instruction.info.mode {
+            Addressing::Relative => instruction.target().unwrap(),
+            Addressing::Implied | Addressing::Accumulator | Addressing::Immediate => continue,
+            _ => instruction.operand
+        };
+
+        if !range.contains(&address) {
+            if let Some(name) = symbol_name(symbols, address, instruction.direction()) {
+                equates.insert((address, name));
+            }
+        } else if instruction.info.length == 3 || instruction.is_branch() {
+            targets.insert(address);
+        }
+    }
+    exporter.labels = targets.into_iter()
+        .map(|target| *exporter.owners.get(&target).unwrap_or(&target))
+        .collect();
+    let named: Vec<u16> = symbols.iter()
+        .map(|symbol| symbol.address)
+        .filter(|address| range.contains(address) && !exporter.owners.contains_key(address) && symbol_name(symbols, *address, Direction::Both).is_some())
+        .collect();
+    exporter.labels.extend(named);
+
+    let mut text = String::new();
+    writeln!(text, "; ${:04X}-${:04X}", start, end).unwrap();
+    for (address, name) in equates.iter() {
+        if *address < 0x100 {
+            writeln!(text, "{} = ${:02X}", name, address).unwrap();
+        } else {
+            writeln!(text, "{} = ${:04X}", name, address).unwrap();
+        }
+    }
+    match syntax {
+        Syntax::Ca65 => writeln!(text, "        .org ${:04X}", start).unwrap(),
+        Syntax::Mads => writeln!(text, "        org ${:04X}", start).unwrap()
+    }
+
+    let mut address = start;
+    while address <= end {
+        if let Some(instruction) = exporter.instructions.get(&(address as u16)) {
+            let suffix = if syntax == Syntax::Mads && forced_absolute(instruction) { ".w" } else { "" };
+            let statement = format!("{}{} {}", instruction.info.name.to_ascii_lowercase(), suffix, exporter.operand(instruction));
+            exporter.line(&mut text, address as u16, statement.trim_end());
+            address += instruction.info.length as usize;
+            continue;
+        }
+
+        // data up to the next instruction or label
+        let data_end = (address + 1..=end)
+            .find(|&next| exporter.instructions.contains_key(&(next as u16)) || exporter.labels.contains(&(next as u16)))
+            .unwrap_or(end + 1);
+        exporter.data(&mut text, address as u16, &exporter.memory[address..data_end]);
+        address = data_end;
+    }
+
+    return text;
+}
+
+// Assembles the source and checks that it produces exactly the bytes of the range.
+pub fn verify(memory: &[u8], range: RangeInclusive<u16>, source: &str) -> Result<(), VerifyError> {
+    let assembly = assemble(source).map_err(VerifyError::Assembly)?;
+
+    let mut produced = BTreeMap::new();
+    for chunk in assembly.chunks.iter() {
+        for (offset, &byte) in chunk.bytes.iter().enumerate() {
+            produced.insert(chunk.address.wrapping_add(offset as u16), byte);
+        }
+    }
+
+    for address in range.clone() {
+        match produced.remove(&address) {
+            Some(found) if found != memory[address as usize] => {
+                return Err(VerifyError::Mismatch { address: address, expected: memory[address as usize], found: found });
+            },
+            Some(_) => {},
+            None => return Err(VerifyError::Missing(address))
+        }
+    }
+
+    match produced.keys().next() {
+        Some(&address) => Err(VerifyError::Outside(address)),
+        None => Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::export;
+    use super::printable;
+    use super::verify;
+    use super::Syntax;
+    use super::VerifyError;
+    use crate::analysis::flow::analyse;
+    use crate::analysis::flow::Options;
+    use crate::symbols::Symbols;
+    use std::error::Error;
+    use std::fs;
+
+    fn memory() -> Vec<u8> {
+        let mut memory = vec![0; 65536];
+        // $0600: LDX #$00
+        // $0602: LDA $0613,X
+        // $0605: STA $0080
+        // $0608: BEQ $060E
+        // $060A: INX
+        // $060B: JMP $0602
+        // $060E: JMP ($0611)
+        // $0611: .word $0600
+        // $0613: "HELLO", $9B
+        memory[0x0600..0x0619].copy_from_slice(&[
+            0xa2, 0x00, 0xbd, 0x13, 0x06, 0x8d, 0x80, 0x00, 0xf0, 0x04, 0xe8, 0x4c, 0x02, 0x06,
+            0x6c, 0x11, 0x06, 0x00, 0x06, b'H', b'E', b'L', b'L', b'O', 0x9b
+        ]);
+        memory
+    }
+
+    #[test]
+    fn test_export() {
+        let memory = memory();
+        let flow = analyse(&memory, &Options { entries: vec![0x0600], ..Default::default() });
+
+        assert_eq!(export(&memory, 0x0600..=0x0618, &flow, Syntax::Ca65, &Symbols::new()), "\
+; $0600-$0618
+        .org $0600
+        ldx #$00
+L_0602: lda L_0613,x
+        sta a:$0080
+        beq L_060E
+        inx
+        jmp L_0602
+L_060E: jmp (L_0611)
+L_0611: .byte $00,$06
+L_0613: .byte \"HELLO\"
+        .byte $9B
+");
+
+        let mads = export(&memory, 0x0600..=0x0618, &flow, Syntax::Mads, &Symbols::new());
+        assert!(mads.contains("\n        org $0600\n"));
+        assert!(mads.contains("\nL_0613  dta c'HELLO'\n"));
+        assert!(mads.contains("\n        sta.w $0080\n"));
+
+        for syntax in [Syntax::Ca65, Syntax::Mads].iter() {
+            assert_eq!(verify(&memory, 0x0600..=0x0618, &export(&memory, 0x0600..=0x0618, &flow, *syntax, &Symbols::new())), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_export_with_symbols() {
+        let memory = memory();
+        let flow = analyse(&memory, &Options { entries: vec![0x0600], ..Default::default() });
+        let mut symbols = Symbols::new();
+        symbols.parse("loop = $0602\nscreen = $0080\ntext = $0613\n").unwrap();
+
+        let source = export(&memory, 0x0600..=0x0618, &flow, Syntax::Ca65, &symbols);
+        assert!(source.starts_with("; $0600-$0618\nscreen = $80\n        .org $0600\n"));
+        assert!(source.contains("\nloop:   lda text,x\n        sta a:screen\n"));
+        assert!(source.contains("\n        jmp loop\n"));
+        assert_eq!(verify(&memory, 0x0600..=0x0618, &source), Ok(()));
+
+        // stores take the write names of the registers
+        let source = export(&memory, 0x0600..=0x0618, &flow, Syntax::Ca65, &Symbols::atari());
+        assert!(source.contains("\n        sta a:$0080\n"));
+        let mut memory = memory;
+        memory[0x0606..0x0608].copy_from_slice(&[0x00, 0xd0]); // STA $D000
+        memory[0x0603..0x0605].copy_from_slice(&[0x00, 0xd0]); // LDA $D000,X
+        let source = export(&memory, 0x0600..=0x0618, &flow, Syntax::Ca65, &Symbols::atari());
+        assert!(source.starts_with("; $0600-$0618\nHPOSP0 = $D000\nM0PF = $D000\n"));
+        assert!(source.contains("\nL_0602: lda M0PF,x\n        sta HPOSP0\n"));
+        assert_eq!(verify(&memory, 0x0600..=0x0618, &source), Ok(()));
+    }
+
+    #[test]
+    fn test_printable() {
+        assert!(printable(b' ') && printable(b'_') && printable(b'a') && printable(b'z'));
+        assert!(!printable(0x60) && !printable(b'{') && !printable(b'"') && !printable(0x9b));
+    }
+
+    #[test]
+    fn test_export_cuts_instructions_at_range_end() {
+        let memory = memory();
+        let flow = analyse(&memory, &Options { entries: vec![0x0600], ..Default::default() });
+
+        // the range ends inside of LDA, which becomes data; the branch target lies outside
+        let source = export(&memory, 0x0602..=0x0609, &flow, Syntax::Ca65, &Symbols::new());
+        assert!(source.contains("        beq $060E\n"));
+        assert_eq!(verify(&memory, 0x0602..=0x0609, &source), Ok(()));
+        assert_eq!(verify(&memory, 0x0602..=0x060a, &source), Err(VerifyError::Missing(0x060a)));
+
+        // usable as std errors
+        let error: Box<dyn Error> = Box::new(verify(&memory, 0x0602..=0x060a, &source).unwrap_err());
+        assert_eq!(error.to_string(), "$060A is not assembled");
+        let error = verify(&memory, 0x0600..=0x0600, "        lda #").unwrap_err();
+        assert_eq!(error.to_string(), error.source().unwrap().to_string());
+    }
+
+    #[test]
+    fn test_os_rom_round_trip() {
+        let mut memory = fs::read("examples/atarixl.mem").unwrap();
+        memory.resize(65536, 0);
+        let flow = analyse(&memory, &Options { vectors: true, ..Default::default() });
+
+        for syntax in [Syntax::Ca65, Syntax::Mads].iter() {
+            let source = export(&memory, 0xc000..=0xffff, &flow, *syntax, &Symbols::new());
+            assert_eq!(verify(&memory, 0xc000..=0xffff, &source), Ok(()));
+        }
+
+        let source = export(&memory, 0xc000..=0xffff, &flow, Syntax::Ca65, &Symbols::atari());
+        assert!(source.contains("\n        sta WSYNC\n"));
+        assert!(source.contains("\nCIOV:   jmp "));
+        assert_eq!(verify(&memory, 0xc000..=0xffff, &source), Ok(()));
+    }
+}
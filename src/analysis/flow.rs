@@ -0,0 +1,394 @@
+// Recursive descent control flow analysis - separates code from data in a memory image.
+//
+// The walk starts at the reset, NMI and IRQ vectors and the given entry points, and
+// follows branches, JMP and JSR. Indirect jumps are followed only through known jump
+// tables (lists of little endian code addresses); any other one ends the flow. Bytes
+// the code reads or writes by absolute address are data, the rest stays unknown.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use super::decode;
+use super::Instruction;
+use crate::cpu::AccessClass;
+use crate::cpu::Addressing;
+use crate::symbols::Symbols;
+
+const NMI_VECTOR: u16 = 0xfffa;
+const RESET_VECTOR: u16 = 0xfffc;
+const IRQ_VECTOR: u16 = 0xfffe;
+
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ByteKind {
+    Unknown,
+    Code,    // opcode
+    Operand, // operand of an instruction
+    Data
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct JumpTable {
+    pub address: u16,
+    pub count: usize // number of addresses
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    pub entries: Vec<u16>,
+    pub tables: Vec<JumpTable>,
+    pub vectors: bool // start at the reset, NMI and IRQ vectors too
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub start: u16,
+    pub end: u16, // address after the last instruction
+    pub successors: Vec<u16>
+}
+
+pub struct Flow {
+    pub kinds: Vec<ByteKind>,
+    pub blocks: BTreeMap<u16, Block>,
+    pub calls: BTreeMap<u16, BTreeSet<u16>>, // routine entry to the routines it calls
+    pub invalid: BTreeSet<u16>,             // unknown opcodes the flow ran into
+    pub overlaps: BTreeSet<u16>             // instructions starting inside of other instructions
+}
+
+fn word(memory: &[u8], address: u16) -> u16 {
+    u16::from_le_bytes([memory[address as usize], memory[address.wrapping_add(1) as usize]])
+}
+
+// All the places the instruction may continue at.
+fn successors(instruction: &Instruction, memory: &[u8], tables: &[JumpTable]) -> Vec<u16> {
+    let mut next = Vec::new();
+
+    if let Some(target) = instruction.target() {
+        next.push(target);
+    }
+
+    if instruction.info.name == "JMP" && instruction.info.mode == Addressing::Indirect {
+        for table in tables.iter() {
+            let end = table.address as usize + table.count * 2;
+            if (table.address as usize..end).contains(&(instruction.operand as usize)) {
+                next.extend((0..table.count).map(|index| word(memory, table.address.wrapping_add(index as u16 * 2))));
+            }
+        }
+    }
+
+    if !instruction.ends_flow() {
+        next.push(instruction.next());
+    }
+
+    return next;
+}
+
+pub fn analyse(memory: &[u8], options: &Options) -> Flow {
+    let mut kinds = vec![ByteKind::Unknown; memory.len()];
+    let mut instructions: BTreeMap<u16, Instruction> = BTreeMap::new();
+    let mut invalid = BTreeSet::new();
+    let mut overlaps = BTreeSet::new();
+
+    let mut routines: BTreeSet<u16> = options.entries.iter().cloned().collect();
+    if options.vectors {
+        routines.extend([NMI_VECTOR, RESET_VECTOR, IRQ_VECTOR].iter().map(|&vector| word(memory, vector)));
+    }
+    for table in options.tables.iter() {
+        for index in 0..table.count {
+            let address = table.address.wrapping_add(index as u16 * 2);
+            kinds[address as usize] = ByteKind::Data;
+            kinds[address.wrapping_add(1) as usize] = ByteKind::Data;
+            routines.insert(word(memory, address));
+        }
+    }
+
+    let mut pending: Vec<u16> = routines.iter().cloned().collect();
+
+    while let Some(address) = pending.pop() {
+        if instructions.contains_key(&address) || invalid.contains(&address) {
+            continue;
+        }
+
+        let instruction = match decode(memory, address) {
+            Some(instruction) => instruction,
+            None => {
+                invalid.insert(address);
+                continue;
+            }
+        };
+
+        if instruction.info.name == "JSR" {
+            routines.insert(instruction.operand);
+        }
+
+        pending.extend(successors(&instruction, memory, &options.tables));
+        instructions.insert(address, instruction);
+    }
+
+    // code first, so data references do not overwrite it
+    for instruction in instructions.values() {
+        for offset in 0..instruction.info.length as u16 {
+            let address = instruction.address.wrapping_add(offset) as usize;
+            if kinds[address] != ByteKind::Unknown {
+                overlaps.insert(instruction.address);
+            }
+            kinds[address] = if offset == 0 { ByteKind::Code } else { ByteKind::Operand };
+        }
+    }
+
+    for instruction in instructions.values() {
+        let data = matches!(instruction.info.class, AccessClass::Read | AccessClass::Write | AccessClass::ReadModifyWrite);
+        let addressed = matches!(instruction.info.mode,
+            Addressing::ZeroPage | Addressing::ZeroPageX | Addressing::ZeroPageY |
+            Addressing::Absolute | Addressing::AbsoluteX | Addressing::AbsoluteY
+        );
+
+        if data && addressed && kinds[instruction.operand as usize] == ByteKind::Unknown {
+            kinds[instruction.operand as usize] = ByteKind::Data;
+        }
+    }
+
+    let blocks = blocks(&instructions, &routines, memory, &options.tables);
+    let calls = calls(&instructions, &blocks, &routines);
+
+    return Flow {
+        kinds: kinds,
+        blocks: blocks,
+        calls: calls,
+        invalid: invalid,
+        overlaps: overlaps
+    };
+}
+
+fn blocks(instructions: &BTreeMap<u16, Instruction>, routines: &BTreeSet<u16>, memory: &[u8], tables: &[JumpTable]) -> BTreeMap<u16, Block> {
+    let mut leaders: BTreeSet<u16> = routines.iter().filter(|address| instructions.contains_key(address)).cloned().collect();
+
+    for instruction in instructions.values() {
+        if instruction.is_branch() || instruction.ends_flow() || instruction.info.name == "JSR" {
+            leaders.extend(successors(instruction, memory, tables).into_iter().filter(|address| instructions.contains_key(address)));
+        }
+    }
+
+    let mut blocks = BTreeMap::new();
+
+    for &start in leaders.iter() {
+        let mut instruction = &instructions[&start];
+
+        loop {
+            let next = instruction.next();
+            let ends = instruction.is_branch() || instruction.ends_flow() || instruction.info.name == "JSR";
+
+            if ends || leaders.contains(&next) || !instructions.contains_key(&next) {
+                let successors = successors(instruction, memory, tables).into_iter().filter(|address| instructions.contains_key(address)).collect();
+                blocks.insert(start, Block { start: start, end: next, successors: successors });
+                break;
+            }
+
+            instruction = &instructions[&next];
+        }
+    }
+
+    return blocks;
+}
+
+// Walks every routine, without stepping into the routines it calls.
+fn calls(instructions: &BTreeMap<u16, Instruction>, blocks: &BTreeMap<u16, Block>, routines: &BTreeSet<u16>) -> BTreeMap<u16, BTreeSet<u16>> {
+    let mut calls = BTreeMap::new();
+
+    for &routine in routines.iter().filter(|address| blocks.contains_key(address)) {
+        let mut callees = BTreeSet::new();
+        let mut visited = BTreeSet::new();
+        let mut pending = vec![routine];
+
+        while let Some(start) = pending.pop() {
+            if !visited.insert(start) {
+                continue;
+            }
+
+            let block = &blocks[&start];
+            let last = instructions.range(block.start..block.end).next_back().map(|(_, instruction)| instruction);
+
+            for &successor in block.successors.iter() {
+                match last {
+                    Some(instruction) if instruction.info.name == "JSR" && instruction.operand == successor => { callees.insert(successor); },
+                    _ => pending.push(successor)
+                }
+            }
+        }
+
+        calls.insert(routine, callees);
+    }
+
+    return calls;
+}
+
+impl Flow {
+    // Continuous runs of bytes of the same kind, operands are counted as code.
+    pub fn regions(&self) -> Vec<(u16, u16, ByteKind)> {
+        let mut regions: Vec<(u16, u16, ByteKind)> = Vec::new();
+
+        for (address, &kind) in self.kinds.iter().enumerate() {
+            let kind = if kind == ByteKind::Operand { ByteKind::Code } else { kind };
+
+            match regions.last_mut() {
+                Some(region) if region.2 == kind => region.1 = address as u16,
+                _ => regions.push((address as u16, address as u16, kind))
+            }
+        }
+
+        regions
+    }
+
+    pub fn to_text(&self, symbols: &Symbols) -> String {
+        let mut text = String::new();
+
+        for (start, end, kind) in self.regions() {
+            if kind != ByteKind::Unknown {
+                writeln!(text, "${:04x}-${:04x} {:?}", start, end, kind).unwrap();
+            }
+        }
+
+        for block in self.blocks.values() {
+            let successors: Vec<String> = block.successors.iter().map(|&address| symbols.describe(address)).collect();
+            writeln!(text, "block {}-${:04x} -> {}", symbols.describe(block.start), block.end.wrapping_sub(1), successors.join(", ")).unwrap();
+        }
+
+        for (routine, callees) in self.calls.iter() {
+            let callees: Vec<String> = callees.iter().map(|&address| symbols.describe(address)).collect();
+            writeln!(text, "routine {} calls {}", symbols.describe(*routine), callees.join(", ")).unwrap();
+        }
+
+        for address in self.invalid.iter() {
+            writeln!(text, "invalid opcode at {}", symbols.describe(*address)).unwrap();
+        }
+
+        for address in self.overlaps.iter() {
+            writeln!(text, "overlapping instruction at {}", symbols.describe(*address)).unwrap();
+        }
+
+        text
+    }
+
+    pub fn to_dot(&self, symbols: &Symbols) -> String {
+        let mut dot = String::from("digraph flow {\n    node [shape=box, fontname=monospace];\n");
+
+        for block in self.blocks.values() {
+            let shape = if self.calls.contains_key(&block.start) { ", style=bold" } else { "" };
+            writeln!(dot, "    \"${:04x}\" [label=\"{}-${:04x}\"{}];", block.start, symbols.describe(block.start), block.end.wrapping_sub(1), shape).unwrap();
+        }
+
+        for block in self.blocks.values() {
+            for successor in block.successors.iter() {
+                writeln!(dot, "    \"${:04x}\" -> \"${:04x}\";", block.start, successor).unwrap();
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn call_graph_dot(&self, symbols: &Symbols) -> String {
+        let mut dot = String::from("digraph calls {\n    node [shape=box, fontname=monospace];\n");
+
+        for (routine, callees) in self.calls.iter() {
+            writeln!(dot, "    \"${:04x}\" [label=\"{}\"];", routine, symbols.describe(*routine)).unwrap();
+            for callee in callees.iter() {
+                writeln!(dot, "    \"${:04x}\" -> \"${:04x}\";", routine, callee).unwrap();
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::analyse;
+    use super::ByteKind;
+    use super::JumpTable;
+    use super::Options;
+    use crate::symbols::Symbols;
+
+    // $0600: JSR $0610
+    // $0603: LDA $0620
+    // $0606: BEQ $0600
+    // $0608: JMP ($0622)
+    // $060B: .byte $02        - never reached
+    // $0610: INC $0621
+    // $0613: RTS
+    // $0620: .byte $00, $00   - data
+    // $0622: .word $0630      - jump table
+    // $0630: RTS
+    // $FFFA: .word $0630, $0600, $0630
+    fn memory() -> Vec<u8> {
+        let mut memory = vec![0; 65536];
+        memory[0x0600..0x060c].copy_from_slice(&[0x20, 0x10, 0x06, 0xad, 0x20, 0x06, 0xf0, 0xf8, 0x6c, 0x22, 0x06, 0x02]);
+        memory[0x0610..0x0614].copy_from_slice(&[0xee, 0x21, 0x06, 0x60]);
+        memory[0x0622..0x0624].copy_from_slice(&[0x30, 0x06]);
+        memory[0x0630] = 0x60;
+        memory[0xfffa..0x10000].copy_from_slice(&[0x30, 0x06, 0x00, 0x06, 0x30, 0x06]);
+
+        memory
+    }
+
+    fn options() -> Options {
+        Options {
+            entries: Vec::new(),
+            tables: vec![JumpTable { address: 0x0622, count: 1 }],
+            vectors: true
+        }
+    }
+
+    #[test]
+    fn test_classification() {
+        let flow = analyse(&memory(), &options());
+
+        assert_eq!(flow.kinds[0x0600], ByteKind::Code);
+        assert_eq!(flow.kinds[0x0601], ByteKind::Operand);
+        assert_eq!(flow.kinds[0x060b], ByteKind::Unknown);
+        assert_eq!(flow.kinds[0x0610], ByteKind::Code);
+        assert_eq!(flow.kinds[0x0620], ByteKind::Data);
+        assert_eq!(flow.kinds[0x0621], ByteKind::Data);
+        assert_eq!(flow.kinds[0x0622], ByteKind::Data);
+        assert_eq!(flow.kinds[0x0630], ByteKind::Code);
+    }
+
+    #[test]
+    fn test_blocks_and_calls() {
+        let flow = analyse(&memory(), &options());
+
+        let starts: Vec<u16> = flow.blocks.keys().cloned().collect();
+        assert_eq!(starts, vec![0x0600, 0x0603, 0x0608, 0x0610, 0x0630]);
+        assert_eq!(flow.blocks[&0x0603].successors, vec![0x0600, 0x0608]);
+        assert_eq!(flow.blocks[&0x0608].successors, vec![0x0630]);
+
+        // the table entry is a routine of its own, reached by a jump
+        assert_eq!(flow.calls[&0x0600].iter().cloned().collect::<Vec<u16>>(), vec![0x0610]);
+        assert!(flow.calls[&0x0610].is_empty());
+        assert!(flow.calls.contains_key(&0x0630));
+    }
+
+    #[test]
+    fn test_invalid_and_dot() {
+        let mut memory = memory();
+        memory[0x0608] = 0x02; // no jump table anymore
+
+        let flow = analyse(&memory, &Options { entries: vec![0x0600], tables: Vec::new(), vectors: false });
+
+        assert!(flow.invalid.contains(&0x0608));
+        assert_eq!(flow.kinds[0x0630], ByteKind::Unknown);
+        assert!(flow.to_dot(&Symbols::new()).contains("\"$0603\" -> \"$0600\";"));
+        assert!(flow.call_graph_dot(&Symbols::new()).contains("\"$0600\" -> \"$0610\";"));
+        assert!(flow.to_text(&Symbols::new()).contains("invalid opcode at $0608"));
+
+        let mut symbols = Symbols::new();
+        symbols.parse("main = $0600
+increment = $0610
+").unwrap();
+        assert!(flow.to_text(&symbols).contains("block main-$0602 -> increment, $0603\n"));
+        assert!(flow.to_text(&symbols).contains("routine main calls increment\n"));
+        assert!(flow.to_dot(&symbols).contains("\"$0600\" [label=\"main-$0602\""));
+    }
+}
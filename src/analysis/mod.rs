@@ -0,0 +1,138 @@
+// Static analysis of memory images - everything here works on the bytes only,
+// without executing them.
+
+pub mod export;
+pub mod flow;
+pub mod timing;
+
+use crate::cpu::AccessClass;
+use crate::cpu::Addressing;
+use crate::cpu::OpcodeInfo;
+use crate::cpu::OPCODE_INFO;
+use crate::symbols::Direction;
+use crate::symbols::Symbols;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Instruction {
+    pub address: u16,
+    pub opcode: u8,
+    pub info: OpcodeInfo,
+    pub operand: u16 // zero for implied and accumulator modes
+}
+
+impl Instruction {
+    // Address of the instruction which follows in memory.
+    pub fn next(&self) -> u16 {
+        self.address.wrapping_add(self.info.length as u16)
+    }
+
+    // Where a branch, JMP or JSR goes, when it is known without running the code.
+    pub fn target(&self) -> Option<u16> {
+        match self.info.mode {
+            Addressing::Relative => Some(self.next().wrapping_add(self.operand as u8 as i8 as u16)),
+            Addressing::Absolute if self.info.name == "JMP" || self.info.name == "JSR" => Some(self.operand),
+            _ => None
+        }
+    }
+
+    pub fn is_branch(&self) -> bool {
+        self.info.mode == Addressing::Relative
+    }
+
+    // The next instruction in memory is never executed after this one.
+    pub fn ends_flow(&self) -> bool {
+        matches!(self.info.name, "JMP" | "RTS" | "RTI" | "BRK")
+    }
+
+    // How the instruction accesses its operand address - the pointer of the indirect
+    // modes is always read.
+    pub fn direction(&self) -> Direction {
+        match (self.info.mode, self.info.class) {
+            (Addressing::IndirectX, _) | (Addressing::IndirectY, _) => Direction::Read,
+            (_, AccessClass::Read) => Direction::Read,
+            (_, AccessClass::Write) => Direction::Write,
+            _ => Direction::Both
+        }
+    }
+
+    // The instruction as it is listed, with names for the addresses, like `STA WSYNC`.
+    pub fn format(&self, symbols: &Symbols) -> String {
+        let name = |address: u16, digits: usize| match symbols.name_for(address, self.direction()) {
+            Some(name) => name.to_string(),
+            None => format!("${:0width$X}", address, width = digits)
+        };
+
+        let operand = match self.info.mode {
+            Addressing::Implied | Addressing::Accumulator => return self.info.name.to_string(),
+            Addressing::Immediate => format!("#${:02X}", self.operand),
+            Addressing::Relative => name(self.target().unwrap(), 4),
+            Addressing::ZeroPage => name(self.operand, 2),
+            Addressing::ZeroPageX => format!("{},X", name(self.operand, 2)),
+            Addressing::ZeroPageY => format!("{},Y", name(self.operand, 2)),
+            Addressing::Absolute => name(self.operand, 4),
+            Addressing::AbsoluteX => format!("{},X", name(self.operand, 4)),
+            Addressing::AbsoluteY => format!("{},Y", name(self.operand, 4)),
+            Addressing::Indirect => format!("({})", name(self.operand, 4)),
+            Addressing::IndirectX => format!("({},X)", name(self.operand, 2)),
+            Addressing::IndirectY => format!("({}),Y", name(self.operand, 2))
+        };
+
+        return format!("{} {}", self.info.name, operand);
+    }
+}
+
+// Decodes the instruction at the address, None for opcodes the CPU does not know.
+pub fn decode(memory: &[u8], address: u16) -> Option<Instruction> {
+    let opcode = memory[address as usize];
+    let info = OPCODE_INFO[opcode as usize]?;
+
+    let low = memory[address.wrapping_add(1) as usize] as u16;
+    let high = memory[address.wrapping_add(2) as usize] as u16;
+    let operand = match info.length {
+        1 => 0,
+        2 => low,
+        _ => (high << 8) | low
+    };
+
+    return Some(Instruction {
+        address: address,
+        opcode: opcode,
+        info: info,
+        operand: operand
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decode;
+    use crate::symbols::Symbols;
+
+    #[test]
+    fn test_decode() {
+        let mut memory = [0; 65536];
+        // $0600: LDA $1234,X
+        // $0603: BNE $0600
+        // $0605: .byte $02
+        memory[0x0600..0x0606].copy_from_slice(&[0xbd, 0x34, 0x12, 0xd0, 0xfb, 0x02]);
+
+        let lda = decode(&memory, 0x0600).unwrap();
+        assert_eq!(lda.info.name, "LDA");
+        assert_eq!(lda.operand, 0x1234);
+        assert_eq!(lda.next(), 0x0603);
+        assert_eq!(lda.target(), None);
+
+        let bne = decode(&memory, 0x0603).unwrap();
+        assert_eq!(bne.target(), Some(0x0600));
+        assert_eq!(bne.is_branch(), true);
+
+        assert_eq!(decode(&memory, 0x0605), None);
+
+        let symbols = Symbols::atari();
+        assert_eq!(lda.format(&symbols), "LDA $1234,X");
+        assert_eq!(bne.format(&symbols), "BNE $0600");
+        memory[0x0600..0x0606].copy_from_slice(&[0x8d, 0x0a, 0xd4, 0xb1, 0x58, 0x0a]); // STA $D40A, LDA ($58),Y, ASL A
+        assert_eq!(decode(&memory, 0x0600).unwrap().format(&symbols), "STA WSYNC");
+        assert_eq!(decode(&memory, 0x0603).unwrap().format(&symbols), "LDA (SAVMSC),Y");
+        assert_eq!(decode(&memory, 0x0605).unwrap().format(&symbols), "ASL");
+    }
+}
@@ -0,0 +1,423 @@
+// Static cycle counting for timing critical code (kernels, DLIs, VBIs).
+//
+// The code is walked from the start of the range, following branches and jumps
+// which stay inside of it. Every instruction gets its best and worst case, which
+// differ by the page crossing penalty when it cannot be ruled out - the index
+// registers are unknown, but an indexed base at the start of a page never crosses.
+// Branches are counted per edge: 2 cycles when not taken, 3 when taken and 4 when
+// the target is on another page.
+//
+// JSR is counted as its own 6 cycles, the called routine is not followed.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+
+use super::decode;
+use super::Instruction;
+use crate::cpu::Addressing;
+use crate::symbols::Symbols;
+
+const MAX_PATHS: usize = 1000;
+
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TimingError {
+    UnknownOpcode(u16),
+    Overlapping(u16) // a jump lands in the middle of another instruction
+}
+
+impl fmt::Display for TimingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimingError::UnknownOpcode(address) => write!(f, "unknown opcode at ${:04X}", address),
+            TimingError::Overlapping(address) => write!(f, "instruction at ${:04X} overlaps another one", address)
+        }
+    }
+}
+
+impl Error for TimingError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InstructionTiming {
+    pub address: u16,
+    pub name: &'static str,
+    pub best: usize,
+    pub worst: usize
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockTiming {
+    pub start: u16,
+    pub instructions: Vec<InstructionTiming>,
+    pub best: usize,
+    pub worst: usize,
+    pub successors: Vec<u16> // blocks inside of the range only
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathTiming {
+    pub blocks: Vec<u16>, // from the start of the range to the point where the code leaves it
+    pub best: usize,
+    pub worst: usize
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Timing {
+    pub blocks: Vec<BlockTiming>,
+    pub paths: Vec<PathTiming>,
+    pub loops: bool,    // some paths loop back - those are not listed, their time is unbounded
+    pub truncated: bool // there were more than MAX_PATHS paths
+}
+
+// Where the control goes after a block, with the cycles spent getting there.
+struct Edge {
+    target: Option<u16>, // None when the code leaves the range
+    cycles: usize
+}
+
+struct Block {
+    timing: BlockTiming,
+    body_best: usize, // without the closing branch, which is counted on the edges
+    body_worst: usize,
+    edges: Vec<Edge>
+}
+
+fn crosses_page(from: u16, to: u16) -> bool {
+    from & 0xff00 != to & 0xff00
+}
+
+fn instruction_timing(instruction: &Instruction) -> InstructionTiming {
+    let info = &instruction.info;
+    let best = info.cycles as usize;
+
+    let worst = if instruction.is_branch() {
+        let target = instruction.target().unwrap();
+        let page = if crosses_page(instruction.next(), target) { info.page_penalty } else { 0 };
+        best + (info.branch_penalty + page) as usize
+    } else {
+        let may_cross = match info.mode {
+            Addressing::AbsoluteX | Addressing::AbsoluteY => instruction.operand & 0xff != 0,
+            Addressing::IndirectY => true,
+            _ => false
+        };
+        best + if may_cross { info.page_penalty as usize } else { 0 }
+    };
+
+    return InstructionTiming {
+        address: instruction.address,
+        name: info.name,
+        best: best,
+        worst: worst
+    };
+}
+
+// Successors of an instruction inside of the walk, the flag tells if the flow leaves the range.
+fn successors(instruction: &Instruction, range: &RangeInclusive<u16>) -> (Vec<u16>, bool) {
+    let mut next = Vec::new();
+
+    if instruction.info.name != "JSR" {
+        if let Some(target) = instruction.target() {
+            next.push(target);
+        }
+    }
+
+    if !instruction.ends_flow() {
+        next.push(instruction.next());
+    }
+
+    let leaves = instruction.ends_flow() && instruction.target().is_none() || next.iter().any(|address| !range.contains(address));
+    next.retain(|address| range.contains(address));
+
+    return (next, leaves);
+}
+
+fn walk(memory: &[u8], range: &RangeInclusive<u16>) -> Result<BTreeMap<u16, Instruction>, TimingError> {
+    let mut instructions = BTreeMap::new();
+    let mut pending = vec![*range.start()];
+
+    while let Some(address) = pending.pop() {
+        if instructions.contains_key(&address) {
+            continue;
+        }
+
+        let instruction = decode(memory, address).ok_or(TimingError::UnknownOpcode(address))?;
+        pending.extend(successors(&instruction, range).0);
+        instructions.insert(address, instruction);
+    }
+
+    // instructions must not overlap
+    let mut end = 0u32;
+    for (&address, instruction) in instructions.iter() {
+        if (address as u32) < end {
+            return Err(TimingError::Overlapping(address));
+        }
+        end = address as u32 + instruction.info.length as u32;
+    }
+
+    return Ok(instructions);
+}
+
+fn blocks(instructions: &BTreeMap<u16, Instruction>, range: &RangeInclusive<u16>) -> BTreeMap<u16, Block> {
+    let mut leaders = BTreeSet::new();
+    leaders.insert(*range.start());
+
+    for instruction in instructions.values() {
+        if instruction.is_branch() || instruction.ends_flow() {
+            leaders.extend(successors(instruction, range).0);
+        }
+    }
+
+    let mut blocks = BTreeMap::new();
+
+    for &start in leaders.iter() {
+        let mut address = start;
+        let mut timing = BlockTiming { start: start, instructions: Vec::new(), best: 0, worst: 0, successors: Vec::new() };
+        let mut body_best = 0;
+        let mut body_worst = 0;
+        let mut edges = Vec::new();
+
+        loop {
+            let instruction = &instructions[&address];
+            let instruction_timing = instruction_timing(instruction);
+            let (next, leaves) = successors(instruction, range);
+
+            timing.best += instruction_timing.best;
+            timing.worst += instruction_timing.worst;
+
+            if instruction.is_branch() {
+                let target = instruction.target().unwrap();
+                let taken = instruction_timing.worst;
+                let not_taken = instruction_timing.best;
+
+                edges.push(Edge { target: if range.contains(&target) { Some(target) } else { None }, cycles: taken });
+                edges.push(Edge { target: if range.contains(&instruction.next()) { Some(instruction.next()) } else { None }, cycles: not_taken });
+            } else {
+                body_best += instruction_timing.best;
+                body_worst += instruction_timing.worst;
+            }
+            timing.instructions.push(instruction_timing);
+
+            if instruction.is_branch() {
+                break;
+            }
+
+            if leaves {
+                edges.push(Edge { target: None, cycles: 0 });
+                break;
+            }
+
+            address = next[0];
+            if leaders.contains(&address) {
+                edges.push(Edge { target: Some(address), cycles: 0 });
+                break;
+            }
+        }
+
+        timing.successors = edges.iter().filter_map(|edge| edge.target).collect();
+        blocks.insert(start, Block { timing: timing, body_best: body_best, body_worst: body_worst, edges: edges });
+    }
+
+    return blocks;
+}
+
+fn paths(blocks: &BTreeMap<u16, Block>, start: u16) -> (Vec<PathTiming>, bool, bool) {
+    let mut paths = Vec::new();
+    let mut loops = false;
+    let mut truncated = false;
+
+    // depth first, the stack holds the path so far with its best and worst time - the
+    // search stops at MAX_PATHS, the number of paths grows exponentially with branches
+    let mut stack = vec![(vec![start], 0usize, 0usize)];
+
+    'search: while let Some((path, best, worst)) = stack.pop() {
+        let block = &blocks[path.last().unwrap()];
+        let best = best + block.body_best;
+        let worst = worst + block.body_worst;
+
+        for edge in block.edges.iter() {
+            match edge.target {
+                None => {
+                    if paths.len() == MAX_PATHS {
+                        truncated = true;
+                        break 'search;
+                    }
+                    paths.push(PathTiming { blocks: path.clone(), best: best + edge.cycles, worst: worst + edge.cycles });
+                },
+                Some(target) if path.contains(&target) => loops = true,
+                Some(target) => {
+                    let mut next = path.clone();
+                    next.push(target);
+                    stack.push((next, best + edge.cycles, worst + edge.cycles));
+                }
+            }
+        }
+    }
+
+    paths.sort_by(|a, b| a.blocks.cmp(&b.blocks));
+
+    return (paths, loops, truncated);
+}
+
+// Counts cycles of the code starting at the beginning of the range.
+pub fn analyse(memory: &[u8], range: RangeInclusive<u16>) -> Result<Timing, TimingError> {
+    let instructions = walk(memory, &range)?;
+    let blocks = blocks(&instructions, &range);
+    let (paths, loops, truncated) = paths(&blocks, *range.start());
+
+    return Ok(Timing {
+        blocks: blocks.into_values().map(|block| block.timing).collect(),
+        paths: paths,
+        loops: loops,
+        truncated: truncated
+    });
+}
+
+fn cycles(best: usize, worst: usize) -> String {
+    if best == worst {
+        format!("{}", best)
+    } else {
+        format!("{}-{}", best, worst)
+    }
+}
+
+impl Timing {
+    pub fn to_text(&self, symbols: &Symbols) -> String {
+        let mut text = String::new();
+
+        for block in self.blocks.iter() {
+            writeln!(text, "block {}: {} cycles", symbols.describe(block.start), cycles(block.best, block.worst)).unwrap();
+
+            for instruction in block.instructions.iter() {
+                let name = match symbols.name(instruction.address) {
+                    Some(name) => format!(" {}:", name),
+                    None => String::new()
+                };
+                writeln!(text, "  ${:04x}{} {} {}", instruction.address, name, instruction.name, cycles(instruction.best, instruction.worst)).unwrap();
+            }
+        }
+
+        for path in self.paths.iter() {
+            let blocks: Vec<String> = path.blocks.iter().map(|&start| symbols.describe(start)).collect();
+            writeln!(text, "path {}: {} cycles", blocks.join(" -> "), cycles(path.best, path.worst)).unwrap();
+        }
+
+        if self.loops {
+            writeln!(text, "some paths loop, their time is unbounded").unwrap();
+        }
+
+        if self.truncated {
+            writeln!(text, "only the first {} paths are listed", MAX_PATHS).unwrap();
+        }
+
+        text
+    }
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_text(&Symbols::new()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::analyse;
+    use super::TimingError;
+    use crate::symbols::Symbols;
+
+    fn memory_with(address: usize, code: &[u8]) -> Vec<u8> {
+        let mut memory = vec![0; 65536];
+        memory[address..address + code.len()].copy_from_slice(code);
+
+        memory
+    }
+
+    #[test]
+    fn test_straight_code() {
+        // $0600: LDA $2000,X  - never crosses a page
+        // $0603: STA $D40A    - WSYNC
+        // $0606: LDA $2010,Y  - may cross
+        // $0609: RTS
+        let memory = memory_with(0x0600, &[0xbd, 0x00, 0x20, 0x8d, 0x0a, 0xd4, 0xb9, 0x10, 0x20, 0x60]);
+        let timing = analyse(&memory, 0x0600..=0x0609).unwrap();
+
+        assert_eq!(timing.blocks.len(), 1);
+        let worst: Vec<usize> = timing.blocks[0].instructions.iter().map(|instruction| instruction.worst).collect();
+        assert_eq!(worst, vec![4, 4, 5, 6]);
+        assert_eq!((timing.blocks[0].best, timing.blocks[0].worst), (18, 19));
+        assert_eq!(timing.paths.len(), 1);
+        assert_eq!((timing.paths[0].best, timing.paths[0].worst), (18, 19));
+        assert_eq!(timing.loops, false);
+    }
+
+    #[test]
+    fn test_branch_paths() {
+        // $06FA: LDA $80
+        // $06FC: BEQ $0702  - taken crosses a page
+        // $06FE: NOP
+        // $06FF: NOP
+        // $0700: NOP
+        // $0701: NOP
+        // $0702: RTS
+        let memory = memory_with(0x06fa, &[0xa5, 0x80, 0xf0, 0x04, 0xea, 0xea, 0xea, 0xea, 0x60]);
+        let timing = analyse(&memory, 0x06fa..=0x0702).unwrap();
+
+        let starts: Vec<u16> = timing.blocks.iter().map(|block| block.start).collect();
+        assert_eq!(starts, vec![0x06fa, 0x06fe, 0x0702]);
+        assert_eq!(timing.blocks[0].successors, vec![0x0702, 0x06fe]);
+
+        assert_eq!(timing.paths.len(), 2);
+        assert_eq!(timing.paths[0].blocks, vec![0x06fa, 0x06fe, 0x0702]);
+        assert_eq!((timing.paths[0].best, timing.paths[0].worst), (19, 19)); // 3 + 2 + 4 * 2 + 6
+        assert_eq!(timing.paths[1].blocks, vec![0x06fa, 0x0702]);
+        assert_eq!((timing.paths[1].best, timing.paths[1].worst), (13, 13)); // 3 + 4 + 6
+    }
+
+    #[test]
+    fn test_loop() {
+        // $0600: DEX
+        // $0601: BNE $0600
+        // $0603: RTS
+        let memory = memory_with(0x0600, &[0xca, 0xd0, 0xfd, 0x60]);
+        let timing = analyse(&memory, 0x0600..=0x0603).unwrap();
+
+        assert_eq!(timing.loops, true);
+        assert_eq!(timing.paths.len(), 1);
+        assert_eq!((timing.paths[0].best, timing.paths[0].worst), (10, 10));
+
+        let mut symbols = Symbols::new();
+        symbols.parse("delay = $0600\n").unwrap();
+        assert!(timing.to_text(&symbols).starts_with("block delay: 4-5 cycles\n  $0600 delay: DEX 2\n"));
+    }
+
+    #[test]
+    fn test_many_branches() {
+        // $0600: BEQ $0603
+        // $0602: NOP
+        // ... 40 times
+        // $0678: RTS
+        let mut code = [0xf0, 0x01, 0xea].repeat(40);
+        code.push(0x60);
+        let memory = memory_with(0x0600, &code);
+        let timing = analyse(&memory, 0x0600..=0x0678).unwrap();
+
+        assert_eq!(timing.paths.len(), 1000);
+        assert_eq!(timing.truncated, true);
+    }
+
+    #[test]
+    fn test_errors() {
+        let memory = memory_with(0x0600, &[0xea, 0x02]);
+        assert_eq!(analyse(&memory, 0x0600..=0x0601), Err(TimingError::UnknownOpcode(0x0601)));
+
+        // $0600: BNE $0603
+        // $0602: LDA #$EA  - the branch lands on the operand
+        // $0604: RTS
+        let memory = memory_with(0x0600, &[0xd0, 0x01, 0xa9, 0xea, 0x60]);
+        assert_eq!(analyse(&memory, 0x0600..=0x0604), Err(TimingError::Overlapping(0x0603)));
+    }
+}
@@ -0,0 +1,459 @@
+// Small two pass 6502 assembler, understanding the common subset of ca65 and MADS
+// syntax - enough to assemble exported sources and short test programs.
+//
+//   label:  lda #<table     ; ca65 labels end with a colon
+//   label   lda #>table     ; MADS labels start in the first column
+//           .org $0600      ; also `org` and `*=`
+//   name = $D40A
+//           .byte 1, $02, "text"
+//           dta $01, c'text'
+//           .word label, $1234
+//           lda a:$0012     ; `a:` forces absolute addressing of zero page addresses
+//           lda.w $0012     ; so does the MADS size suffix, `.w` or `.a`
+//
+// Expressions are numbers ($hex, %binary, decimal), symbols, `*` (current address),
+// `<` / `>` (low / high byte), joined with + and -. Operands which are known to fit in
+// a byte at the first pass use zero page addressing, the rest absolute.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::cpu::Addressing;
+use crate::cpu::OPCODE_INFO;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AssemblyError {
+    pub line: usize, // counted from 1
+    pub message: String
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AssemblyError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chunk {
+    pub address: u16,
+    pub bytes: Vec<u8>
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Assembly {
+    pub chunks: Vec<Chunk>,
+    pub symbols: HashMap<String, u16>
+}
+
+impl Assembly {
+    // Copies the assembled bytes into memory.
+    pub fn load(&self, memory: &mut [u8]) {
+        for chunk in self.chunks.iter() {
+            for (offset, &byte) in chunk.bytes.iter().enumerate() {
+                memory[(chunk.address as usize + offset) & 0xffff] = byte;
+            }
+        }
+    }
+}
+
+fn opcode(name: &str, mode: Addressing) -> Option<u8> {
+    (0..=255u8).find(|&opcode| match OPCODE_INFO[opcode as usize] {
+        Some(info) => info.name == name && info.mode == mode,
+        None => false
+    })
+}
+
+fn is_mnemonic(name: &str) -> bool {
+    OPCODE_INFO.iter().any(|info| info.map(|info| info.name == name).unwrap_or(false))
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '@'
+}
+
+// Removes the comment, leaving semicolons inside of strings alone.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' | '\'' if quote.is_none() => quote = Some(c),
+            _ if quote == Some(c) => quote = None,
+            ';' if quote.is_none() => return &line[..index],
+            _ => {}
+        }
+    }
+
+    line
+}
+
+// Splits on commas outside of strings and parentheses.
+fn split_list(text: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut quote = None;
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (index, c) in text.char_indices() {
+        match c {
+            '"' | '\'' if quote.is_none() => quote = Some(c),
+            _ if quote == Some(c) => quote = None,
+            '(' if quote.is_none() => depth += 1,
+            ')' if quote.is_none() => depth -= 1,
+            ',' if quote.is_none() && depth == 0 => {
+                items.push(text[start..index].trim());
+                start = index + 1;
+            },
+            _ => {}
+        }
+    }
+    items.push(text[start..].trim());
+
+    items
+}
+
+struct Assembler {
+    symbols: HashMap<String, u16>,
+    pc: u16,
+    final_pass: bool,
+    sizes: Vec<u8> // operand sizes chosen in the first pass, by line
+}
+
+impl Assembler {
+    // Evaluates an expression, None when it uses a symbol which is not defined (yet).
+    fn evaluate(&self, text: &str) -> Result<Option<u16>, String> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err("missing expression".to_string());
+        }
+
+        if let Some(rest) = text.strip_prefix('<') {
+            return Ok(self.evaluate(rest)?.map(|value| value & 0xff));
+        }
+
+        if let Some(rest) = text.strip_prefix('>') {
+            return Ok(self.evaluate(rest)?.map(|value| value >> 8));
+        }
+
+        // the last + or - which is not a sign or part of a term
+        let bytes = text.as_bytes();
+        for index in (1..bytes.len()).rev() {
+            let c = bytes[index] as char;
+            if (c == '+' || c == '-') && !matches!(bytes[index - 1] as char, '+' | '-' | '<' | '>') {
+                let left = self.evaluate(&text[..index])?;
+                let right = self.evaluate(&text[index + 1..])?;
+
+                return Ok(match (left, right) {
+                    (Some(left), Some(right)) if c == '+' => Some(left.wrapping_add(right)),
+                    (Some(left), Some(right)) => Some(left.wrapping_sub(right)),
+                    _ => None
+                });
+            }
+        }
+
+        if let Some(rest) = text.strip_prefix('-') {
+            return Ok(self.evaluate(rest)?.map(|value| 0u16.wrapping_sub(value)));
+        }
+
+        let number = if let Some(hex) = text.strip_prefix('$') {
+            u16::from_str_radix(hex, 16).ok()
+        } else if let Some(binary) = text.strip_prefix('%') {
+            u16::from_str_radix(binary, 2).ok()
+        } else if text.starts_with(|c: char| c.is_ascii_digit()) {
+            text.parse().ok()
+        } else if text.len() == 3 && text.starts_with('\'') && text.ends_with('\'') {
+            Some(text.as_bytes()[1] as u16)
+        } else if text == "*" {
+            Some(self.pc)
+        } else if text.chars().all(is_symbol_char) {
+            return match self.symbols.get(text) {
+                Some(&value) => Ok(Some(value)),
+                None if self.final_pass => Err(format!("unknown symbol {}", text)),
+                None => Ok(None)
+            };
+        } else {
+            return Err(format!("cannot parse {}", text));
+        };
+
+        return number.map(Some).ok_or_else(|| format!("bad number {}", text));
+    }
+
+    fn value(&self, text: &str) -> Result<u16, String> {
+        // unknown symbols are fine in the first pass, only the sizes matter there
+        Ok(self.evaluate(text)?.unwrap_or(0))
+    }
+
+    fn define(&mut self, name: &str, value: u16) -> Result<(), String> {
+        match self.symbols.insert(name.to_string(), value) {
+            Some(old) if old != value && !self.final_pass => Err(format!("{} defined twice", name)),
+            _ => Ok(())
+        }
+    }
+
+    fn data(&self, directive: &str, arguments: &str) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+
+        for item in split_list(arguments) {
+            if item.len() >= 2 && item.starts_with('"') && item.ends_with('"') {
+                bytes.extend_from_slice(&item.as_bytes()[1..item.len() - 1]);
+            } else if item.len() >= 3 && item.starts_with("c'") && item.ends_with('\'') {
+                bytes.extend_from_slice(&item.as_bytes()[2..item.len() - 1]); // MADS ATASCII string
+            } else if directive == ".word" {
+                bytes.extend_from_slice(&self.value(item)?.to_le_bytes());
+            } else {
+                bytes.push(self.value(item)? as u8);
+            }
+        }
+
+        Ok(bytes)
+    }
+
+    fn instruction(&mut self, index: usize, name: &str, operand: &str, forced_absolute: bool) -> Result<Vec<u8>, String> {
+        let operand = operand.trim();
+        let lower = operand.to_ascii_lowercase();
+
+        if operand.is_empty() || lower == "a" || lower == "@" {
+            let mode = if opcode(name, Addressing::Accumulator).is_some() { Addressing::Accumulator } else { Addressing::Implied };
+            return opcode(name, mode).map(|opcode| vec![opcode]).ok_or_else(|| format!("{} needs an operand", name));
+        }
+
+        if let Some(value) = operand.strip_prefix('#') {
+            let opcode = opcode(name, Addressing::Immediate).ok_or_else(|| format!("{} has no immediate mode", name))?;
+            return Ok(vec![opcode, self.value(value)? as u8]);
+        }
+
+        if opcode(name, Addressing::Relative).is_some() {
+            let target = self.value(operand)?;
+            let offset = target.wrapping_sub(self.pc.wrapping_add(2)) as i16;
+            if self.final_pass && !(-128..=127).contains(&offset) {
+                return Err(format!("branch to ${:04x} out of range", target));
+            }
+            return Ok(vec![opcode(name, Addressing::Relative).unwrap(), offset as u8]);
+        }
+
+        if lower.starts_with('(') {
+            let (mode, expression) = if let Some(inner) = lower.strip_suffix(",x)") {
+                (Addressing::IndirectX, &operand[1..inner.len()])
+            } else if let Some(inner) = lower.strip_suffix("),y") {
+                (Addressing::IndirectY, &operand[1..inner.len()])
+            } else if lower.ends_with(')') {
+                (Addressing::Indirect, &operand[1..operand.len() - 1])
+            } else {
+                return Err(format!("cannot parse operand {}", operand));
+            };
+
+            let opcode = opcode(name, mode).ok_or_else(|| format!("{} has no {:?} mode", name, mode))?;
+            let value = self.value(expression)?;
+            return Ok(if mode == Addressing::Indirect { vec![opcode, value as u8, (value >> 8) as u8] } else { vec![opcode, value as u8] });
+        }
+
+        let (expression, index_register) = if lower.ends_with(",x") || lower.ends_with(",y") {
+            (&operand[..operand.len() - 2], lower.chars().last())
+        } else {
+            (operand, None)
+        };
+        let (expression, forced_absolute) = match expression.strip_prefix("a:") {
+            Some(rest) => (rest, true),
+            None => (expression, forced_absolute)
+        };
+
+        let (zeropage, absolute) = match index_register {
+            Some('x') => (Addressing::ZeroPageX, Addressing::AbsoluteX),
+            Some(_) => (Addressing::ZeroPageY, Addressing::AbsoluteY),
+            None => (Addressing::ZeroPage, Addressing::Absolute)
+        };
+
+        if !self.final_pass {
+            let fits = match self.evaluate(expression)? {
+                Some(value) => value < 0x100,
+                None => false
+            };
+            let short = fits && !forced_absolute && opcode(name, zeropage).is_some();
+            self.sizes[index] = if short { 1 } else { 2 };
+        }
+
+        let value = self.value(expression)?;
+        if self.sizes[index] == 1 {
+            return Ok(vec![opcode(name, zeropage).unwrap(), value as u8]);
+        }
+
+        let opcode = opcode(name, absolute).ok_or_else(|| format!("{} has no {:?} mode", name, absolute))?;
+        return Ok(vec![opcode, value as u8, (value >> 8) as u8]);
+    }
+
+    // Assembles one line, returns the bytes it produced.
+    fn line(&mut self, index: usize, line: &str) -> Result<Vec<u8>, String> {
+        let line = strip_comment(line).trim_end();
+        if line.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut rest = line.trim_start();
+
+        // ca65 label with a colon, or MADS label in the first column
+        let word_end = rest.find(|c: char| !is_symbol_char(c)).unwrap_or(rest.len());
+        let word = &rest[..word_end];
+        if rest[word_end..].starts_with(':') && !word.is_empty() {
+            let pc = self.pc;
+            self.define(word, pc)?;
+            rest = rest[word_end + 1..].trim_start();
+        } else if !line.starts_with(char::is_whitespace) && !word.is_empty() && !word.starts_with('.') &&
+                  !is_mnemonic(&word.to_ascii_uppercase()) && word != "org" && word != "dta" && !rest[word_end..].trim_start().starts_with('=') {
+            let pc = self.pc;
+            self.define(word, pc)?;
+            rest = rest[word_end..].trim_start();
+        }
+
+        if rest.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        if let Some(value) = rest.strip_prefix("*=") {
+            self.pc = self.value(value)?;
+            return Ok(Vec::new());
+        }
+
+        let word_end = rest.find(|c: char| !is_symbol_char(c)).unwrap_or(rest.len());
+        let word = &rest[..word_end];
+        let arguments = rest[word_end..].trim();
+
+        if let Some(value) = arguments.strip_prefix('=') {
+            let value = self.value(value)?;
+            return self.define(word, value).map(|_| Vec::new());
+        }
+
+        // MADS forces absolute addressing with a size suffix on the mnemonic, `sta.w $0080`
+        let word = word.to_ascii_lowercase();
+        let (word, forced_absolute) = match word.strip_suffix(".w").or_else(|| word.strip_suffix(".a")) {
+            Some(name) if is_mnemonic(&name.to_ascii_uppercase()) => (name, true),
+            _ => (word.as_str(), false)
+        };
+
+        match word {
+            ".org" | "org" => {
+                self.pc = self.value(arguments)?;
+                Ok(Vec::new())
+            },
+            directive @ ".byte" | directive @ ".word" | directive @ "dta" => self.data(directive, arguments),
+            name if is_mnemonic(&name.to_ascii_uppercase()) => self.instruction(index, &name.to_ascii_uppercase(), arguments, forced_absolute),
+            _ => Err(format!("unknown instruction {}", word))
+        }
+    }
+
+    fn pass(&mut self, source: &str) -> Result<Vec<Chunk>, AssemblyError> {
+        let mut chunks: Vec<Chunk> = Vec::new();
+        self.pc = 0;
+
+        for (index, line) in source.lines().enumerate() {
+            let start = self.pc;
+            let bytes = self.line(index, line).map_err(|message| AssemblyError { line: index + 1, message: message })?;
+
+            if bytes.is_empty() {
+                continue;
+            }
+
+            match chunks.last_mut() {
+                Some(chunk) if chunk.address.wrapping_add(chunk.bytes.len() as u16) == start => chunk.bytes.extend_from_slice(&bytes),
+                _ => chunks.push(Chunk { address: start, bytes: bytes.clone() })
+            }
+            self.pc = start.wrapping_add(bytes.len() as u16);
+        }
+
+        Ok(chunks)
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Assembly, AssemblyError> {
+    let mut assembler = Assembler {
+        symbols: HashMap::new(),
+        pc: 0,
+        final_pass: false,
+        sizes: vec![0; source.lines().count()]
+    };
+
+    assembler.pass(source)?;
+    assembler.final_pass = true;
+    let chunks = assembler.pass(source)?;
+
+    return Ok(Assembly {
+        chunks: chunks,
+        symbols: assembler.symbols
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assemble;
+    use super::Chunk;
+
+    #[test]
+    fn test_assemble() {
+        let assembly = assemble("
+WSYNC = $D40A
+        .org $0600
+start:  ldx #0
+loop    lda text,x      ; MADS style label
+        sta a:$0080,x
+        sta WSYNC
+        inx
+        cpx #end-text
+        bne loop
+        jmp (vector)
+        asl
+vector: .word start
+text:   .byte \"Hi;\", $9b
+        dta c'A'
+end:
+").unwrap();
+
+        assert_eq!(assembly.symbols["loop"], 0x0602);
+        assert_eq!(assembly.chunks, vec![Chunk { address: 0x0600, bytes: vec![
+            0xa2, 0x00,
+            0xbd, 0x16, 0x06,
+            0x9d, 0x80, 0x00,
+            0x8d, 0x0a, 0xd4,
+            0xe8,
+            0xe0, 0x05,
+            0xd0, 0xf2,
+            0x6c, 0x14, 0x06,
+            0x0a,
+            0x00, 0x06,
+            b'H', b'i', b';', 0x9b, b'A'
+        ]}]);
+    }
+
+    #[test]
+    fn test_zeropage_and_forward_references() {
+        let assembly = assemble("
+        *= $2000
+        lda $12
+        lda later
+        lda ($12),y
+later:  rts
+").unwrap();
+
+        assert_eq!(assembly.chunks[0].bytes, vec![0xa5, 0x12, 0xad, 0x07, 0x20, 0xb1, 0x12, 0x60]);
+    }
+
+    #[test]
+    fn test_forced_absolute() {
+        let assembly = assemble("
+        *= $2000
+        lda a:$12
+        sta.w $12,x
+        INC.A $12
+").unwrap();
+
+        assert_eq!(assembly.chunks[0].bytes, vec![0xad, 0x12, 0x00, 0x9d, 0x12, 0x00, 0xee, 0x12, 0x00]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(assemble("  lda #1\n  foo $12").unwrap_err().line, 2);
+        assert_eq!(assemble("  .org $0600\n  bne $0700").unwrap_err().message, "branch to $0700 out of range");
+        assert_eq!(assemble("  jmp nowhere").unwrap_err().message, "unknown symbol nowhere");
+    }
+}
@@ -1,6 +1,8 @@
+use super::memory::Access;
+use super::memory::Memory;
 use super::register::Register;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Addressing {
     Implied,
     Accumulator,
@@ -22,85 +24,138 @@ pub struct MemoryCell {
     pub address: usize,
     pub value: u8,
     pub in_bounds: bool,
+    pub indexed: bool, // index may cross a page - reads pay for that only when it does, writes always
     pub cycles: u8,
     pub bytes: u8
 }
 
+impl MemoryCell {
+    // Indexed modes add the index to the low byte first, and read from there while the
+    // carry into the high byte is fixed up.
+    pub fn uncorrected_address(&self) -> usize {
+        if self.in_bounds {
+            return self.address;
+        }
+
+        return (self.address & 0xff) | (self.address.wrapping_sub(0x100) & 0xff00);
+    }
+}
+
 impl Addressing {
-    pub fn read(&self, memory: &[u8], register: &mut Register) -> MemoryCell {
+    // Instruction length in bytes, including the opcode.
+    pub const fn length(&self) -> u8 {
+        match *self {
+            Addressing::Implied | Addressing::Accumulator => 1,
+            Addressing::Absolute | Addressing::AbsoluteX | Addressing::AbsoluteY | Addressing::Indirect => 3,
+            _ => 2
+        }
+    }
+
+    // Resolves the operand and fetches the value it points to.
+    pub fn read<M: Memory + ?Sized>(&self, memory: &mut M, register: &mut Register) -> MemoryCell {
+        return self.fetch(memory, register, false);
+    }
+
+    // The same for read-modify-write instructions, which take the cycle at the uncorrected
+    // address also when the index does not cross a page.
+    pub fn modify<M: Memory + ?Sized>(&self, memory: &mut M, register: &mut Register) -> MemoryCell {
+        return self.fetch(memory, register, true);
+    }
+
+    fn fetch<M: Memory + ?Sized>(&self, memory: &mut M, register: &mut Register, always_fix_up: bool) -> MemoryCell {
+        let mut cell = self.locate(memory, register);
+
+        match self {
+            Addressing::ZeroPage | Addressing::ZeroPageX | Addressing::ZeroPageY |
+            Addressing::Absolute | Addressing::AbsoluteX | Addressing::AbsoluteY |
+            Addressing::IndirectX | Addressing::IndirectY => {
+                if cell.indexed && (always_fix_up || !cell.in_bounds) {
+                    memory.read(cell.uncorrected_address(), Access::Dummy);
+                }
+                cell.value = memory.read(cell.address, Access::Data);
+            },
+            _ => {}
+        }
+
+        cell
+    }
+
+    // Resolves the operand only, without touching the target - used by instructions
+    // which never read their effective address (stores and jumps).
+    pub fn locate<M: Memory + ?Sized>(&self, memory: &mut M, register: &mut Register) -> MemoryCell {
         match self {
             Addressing::Implied => { implied() }
             Addressing::Accumulator => { accumulator(register) },
             Addressing::Immediate => {
-                let value = memory[register.pc() as usize];
+                let value = memory.read(register.pc() as usize, Access::Operand);
                 register.increment_pc();
 
                 immediate(value as usize)
             },
             Addressing::Relative => {
-                let address = memory[register.pc() as usize];
+                let address = memory.read(register.pc() as usize, Access::Operand);
                 register.increment_pc();
 
                 relative(register, address as usize)
             }
             Addressing::ZeroPage => {
-                let address = memory[register.pc() as usize];
+                let address = memory.read(register.pc() as usize, Access::Operand);
                 register.increment_pc();
 
                 zeropage(memory, address as usize)
             },
             Addressing::ZeroPageX => {
-                let address = memory[register.pc() as usize];
+                let address = memory.read(register.pc() as usize, Access::Operand);
                 register.increment_pc();
 
                 zeropage_x(memory, register, address as usize)
             },
             Addressing::ZeroPageY => {
-                let address = memory[register.pc() as usize];
+                let address = memory.read(register.pc() as usize, Access::Operand);
                 register.increment_pc();
 
                 zeropage_y(memory, register, address as usize)
             },
             Addressing::Absolute => {
-                let address = memory[register.pc() as usize];
+                let address = memory.read(register.pc() as usize, Access::Operand);
                 register.increment_pc();
-                let address = ((memory[register.pc() as usize] as u16) << 8) + address as u16;
+                let address = ((memory.read(register.pc() as usize, Access::Operand) as u16) << 8) + address as u16;
                 register.increment_pc();
 
                 absolute(memory, address as usize)
             },
             Addressing::AbsoluteX => {
-                let address = memory[register.pc() as usize];
+                let address = memory.read(register.pc() as usize, Access::Operand);
                 register.increment_pc();
-                let address = ((memory[register.pc() as usize] as u16) << 8) + address as u16;
+                let address = ((memory.read(register.pc() as usize, Access::Operand) as u16) << 8) + address as u16;
                 register.increment_pc();
 
                 absolute_x(memory, register, address as usize)
             },
             Addressing::AbsoluteY => {
-                let address = memory[register.pc() as usize];
+                let address = memory.read(register.pc() as usize, Access::Operand);
                 register.increment_pc();
-                let address = ((memory[register.pc() as usize] as u16) << 8) + address as u16;
+                let address = ((memory.read(register.pc() as usize, Access::Operand) as u16) << 8) + address as u16;
                 register.increment_pc();
 
                 absolute_y(memory, register, address as usize)
             },
             Addressing::Indirect => {
-                let address = memory[register.pc() as usize];
+                let address = memory.read(register.pc() as usize, Access::Operand);
                 register.increment_pc();
-                let address = ((memory[register.pc() as usize] as u16) << 8) + address as u16;
+                let address = ((memory.read(register.pc() as usize, Access::Operand) as u16) << 8) + address as u16;
                 register.increment_pc();
 
                 indirect(memory, address as usize)
             },
             Addressing::IndirectX => {
-                let address = memory[register.pc() as usize];
+                let address = memory.read(register.pc() as usize, Access::Operand);
                 register.increment_pc();
 
                 indirect_x(memory, register, address as usize)
             },
             Addressing::IndirectY => {
-                let address = memory[register.pc() as usize];
+                let address = memory.read(register.pc() as usize, Access::Operand);
                 register.increment_pc();
 
                 indirect_y(memory, register, address as usize)
@@ -114,6 +169,7 @@ fn implied() -> MemoryCell {
         address: 0,
         value: 0,
         in_bounds: true,
+        indexed: false,
         cycles: 0,
         bytes: 0
     }
@@ -124,6 +180,7 @@ fn immediate(value: usize) -> MemoryCell {
         address: value,
         value: value as u8,
         in_bounds: true,
+        indexed: false,
         cycles: 0,
         bytes: 1
     }
@@ -134,6 +191,7 @@ fn accumulator(register: &Register) -> MemoryCell {
         address: 0,
         value: register.a,
         in_bounds: true,
+        indexed: false,
         cycles: 0,
         bytes: 0
     }
@@ -152,86 +210,93 @@ fn relative(register: &Register, address: usize) -> MemoryCell {
         address: address,
         value: 0,
         in_bounds: in_bounds,
+        indexed: false,
         cycles: 1,
         bytes: 1
     }
 }
 
-fn zeropage(memory: &[u8], address: usize) -> MemoryCell {
+fn zeropage<M: Memory + ?Sized>(memory: &mut M, address: usize) -> MemoryCell {
     let address = address & 0xff;
 
     MemoryCell {
         address: address,
-        value: memory[address],
+        value: memory.peek(address),
         in_bounds: true,
+        indexed: false,
         cycles: 1,
         bytes: 1
     }
 }
 
-fn zeropage_x(memory: &[u8], register: &Register, address: usize) -> MemoryCell {
+fn zeropage_x<M: Memory + ?Sized>(memory: &mut M, register: &Register, address: usize) -> MemoryCell {
     let address = (address + register.x as usize) & 0xff;
 
     MemoryCell {
         address: address,
-        value: memory[address],
+        value: memory.peek(address),
         in_bounds: true,
+        indexed: false,
         cycles: 2,
         bytes: 1
     }
 }
 
-fn zeropage_y(memory: &[u8], register: &Register, address: usize) -> MemoryCell {
+fn zeropage_y<M: Memory + ?Sized>(memory: &mut M, register: &Register, address: usize) -> MemoryCell {
     let address = (address + register.y as usize) & 0xff;
 
     MemoryCell {
         address: address,
-        value: memory[address],
+        value: memory.peek(address),
         in_bounds: true,
+        indexed: false,
         cycles: 2,
         bytes: 1
     }
 }
 
-fn absolute(memory: &[u8], address: usize) -> MemoryCell {
+fn absolute<M: Memory + ?Sized>(memory: &mut M, address: usize) -> MemoryCell {
     let address = address & 0xffff;
 
     MemoryCell {
         address: address,
-        value: memory[address],
+        value: memory.peek(address),
         in_bounds: true,
+        indexed: false,
         cycles: 2,
         bytes: 2
     }
 }
 
-fn absolute_x(memory: &[u8], register: &Register, address: usize) -> MemoryCell {
+fn absolute_x<M: Memory + ?Sized>(memory: &mut M, register: &Register, address: usize) -> MemoryCell {
     let new_address = (address + register.x as usize) & 0xffff;
     let in_bounds = new_address & 0xff00 == address & 0xff00;
 
     MemoryCell {
         address: new_address,
-        value: memory[new_address],
+        value: memory.peek(new_address),
         in_bounds: in_bounds,
-        cycles: 3,
+        indexed: true,
+        cycles: 2,
         bytes: 2
     }
 }
 
-fn absolute_y(memory: &[u8], register: &Register, address: usize) -> MemoryCell {
+fn absolute_y<M: Memory + ?Sized>(memory: &mut M, register: &Register, address: usize) -> MemoryCell {
     let new_address = (address + register.y as usize) & 0xffff;
     let in_bounds = new_address & 0xff00 == address & 0xff00;
 
     MemoryCell {
         address: new_address,
-        value: memory[new_address],
+        value: memory.peek(new_address),
         in_bounds: in_bounds,
-        cycles: 3,
+        indexed: true,
+        cycles: 2,
         bytes: 2
     }
 }
 
-fn indirect(memory: &[u8], address: usize) -> MemoryCell {
+fn indirect<M: Memory + ?Sized>(memory: &mut M, address: usize) -> MemoryCell {
     // 6502 has a well known bug in JMP (which is the only opcode using indirect addressing).
     // When fetching indirectly new PC address, only low byte of the address is increased (and overflowing)
     // without affecting the high byte. Thus:
@@ -240,56 +305,61 @@ fn indirect(memory: &[u8], address: usize) -> MemoryCell {
     // JMP $30FF - will fetch PCL from $30FF, but PCH will be fetched from $3000 not $3100
     // (only low byte overflows, without affecting the high one).
     let next_cell_address = ((address + 1) & 0xff) + (address & 0xff00);
-    let new_address = memory[address] as usize + ((memory[next_cell_address] as usize) << 8);
+    let new_address = memory.read(address, Access::Data) as usize + ((memory.read(next_cell_address, Access::Data) as usize) << 8);
 
     MemoryCell {
         address: new_address,
         value: 0,
         in_bounds: true,
+        indexed: false,
         cycles: 4,
         bytes: 2
     }
 }
 
-fn indirect_x(memory: &[u8], register: &Register, address: usize) -> MemoryCell {
+fn indirect_x<M: Memory + ?Sized>(memory: &mut M, register: &Register, address: usize) -> MemoryCell {
+    // Pointer never leaves the zero page - its high byte for $FF is fetched from $00.
     let address = (address + register.x as usize) & 0xff;
-    let new_address = memory[address] as usize + ((memory[address + 1] as usize) << 8);
+    let new_address = memory.read(address, Access::Data) as usize + ((memory.read((address + 1) & 0xff, Access::Data) as usize) << 8);
 
     MemoryCell {
         address: new_address,
-        value: memory[new_address],
+        value: memory.peek(new_address),
         in_bounds: true,
+        indexed: false,
         cycles: 4,
         bytes: 1
     }
 }
 
-fn indirect_y(memory: &[u8], register: &Register, address: usize) -> MemoryCell {
-    let address = memory[address & 0xff] as usize + ((memory[(address & 0xff) + 1] as usize) << 8);
+fn indirect_y<M: Memory + ?Sized>(memory: &mut M, register: &Register, address: usize) -> MemoryCell {
+    // Pointer never leaves the zero page - its high byte for $FF is fetched from $00.
+    let address = memory.read(address & 0xff, Access::Data) as usize + ((memory.read((address + 1) & 0xff, Access::Data) as usize) << 8);
     let new_address = (address + register.y as usize) & 0xffff;
     let in_bounds = new_address & 0xff00 == address & 0xff00;
 
     MemoryCell {
         address: new_address,
-        value: memory[new_address],
+        value: memory.peek(new_address),
         in_bounds: in_bounds,
-        cycles: 4,
+        indexed: true,
+        cycles: 3,
         bytes: 1
     }
 }
 
-pub fn stack_push(memory: &mut [u8], register: &mut Register, value: u8) {
+pub fn stack_push<M: Memory + ?Sized>(memory: &mut M, register: &mut Register, value: u8) {
     let stack_address:usize = register.s() as usize + 0x100;
 
-    memory[stack_address] = value;
+    memory.write(stack_address, value, Access::Stack);
     register.push_s();
 }
 
-pub fn stack_pull(memory: &[u8], register: &mut Register) -> u8 {
+pub fn stack_pull<M: Memory + ?Sized>(memory: &mut M, register: &mut Register) -> u8 {
     register.pull_s();
     let stack_address:usize = register.s() as usize + 0x100;
 
-    memory[stack_address]
+    memory.read(stack_address, Access::Stack)
 }
 
 #[cfg(test)]
@@ -310,7 +380,7 @@ mod tests {
         let mut memory = [0; 65536];
         memory[0x30] = 0x42;
 
-        let result = zeropage(&memory, 0x30);
+        let result = zeropage(&mut memory, 0x30);
 
         assert_eq!(result.address, 0x30);
         assert_eq!(result.value, 0x42);
@@ -325,7 +395,7 @@ mod tests {
         memory[0x35] = 0x42;
         register.x = 0x05;
 
-        let result = zeropage_x(&memory, &register, 0x30);
+        let result = zeropage_x(&mut memory, &register, 0x30);
 
         assert_eq!(result.address, 0x35);
         assert_eq!(result.value, 0x42);
@@ -341,7 +411,7 @@ mod tests {
         memory[0x135] = 0x27;
         register.x = 0x36;
 
-        let result = zeropage_x(&memory, &register, 0xff);
+        let result = zeropage_x(&mut memory, &register, 0xff);
 
         assert_eq!(result.address, 0x35);
         assert_eq!(result.value, 0x42);
@@ -357,7 +427,7 @@ mod tests {
         memory[0x35] = 0x42;
         register.y = 0x05;
 
-        let result = zeropage_y(&memory, &register, 0x30);
+        let result = zeropage_y(&mut memory, &register, 0x30);
 
         assert_eq!(result.address, 0x35);
         assert_eq!(result.value, 0x42);
@@ -373,7 +443,7 @@ mod tests {
         memory[0x135] = 0x27;
         register.y = 0x36;
 
-        let result = zeropage_y(&memory, &register, 0xff);
+        let result = zeropage_y(&mut memory, &register, 0xff);
 
         assert_eq!(result.address, 0x35);
         assert_eq!(result.value, 0x42);
@@ -386,7 +456,7 @@ mod tests {
 
         memory[0x5a3c] = 0x42;
 
-        let result = absolute(&memory, 0x5a3c);
+        let result = absolute(&mut memory, 0x5a3c);
 
         assert_eq!(result.address, 0x5a3c);
         assert_eq!(result.value, 0x42);
@@ -401,7 +471,7 @@ mod tests {
         memory[0x5a4c] = 0x42;
         register.x = 0x10;
 
-        let result = absolute_x(&memory, &register, 0x5a3c);
+        let result = absolute_x(&mut memory, &register, 0x5a3c);
 
         assert_eq!(result.address, 0x5a4c);
         assert_eq!(result.value, 0x42);
@@ -416,7 +486,7 @@ mod tests {
         memory[0x5b0c] = 0x42;
         register.x = 0x10;
 
-        let result = absolute_x(&memory, &register, 0x5afc);
+        let result = absolute_x(&mut memory, &register, 0x5afc);
 
         assert_eq!(result.address, 0x5b0c);
         assert_eq!(result.value, 0x42);
@@ -431,7 +501,7 @@ mod tests {
         memory[0x5a] = 0x42;
         register.x = 0x5b;
 
-        let result = absolute_x(&memory, &register, 0xffff);
+        let result = absolute_x(&mut memory, &register, 0xffff);
 
         assert_eq!(result.address, 0x5a);
         assert_eq!(result.value, 0x42);
@@ -446,7 +516,7 @@ mod tests {
         memory[0x5a4c] = 0x42;
         register.y = 0x10;
 
-        let result = absolute_y(&memory, &register, 0x5a3c);
+        let result = absolute_y(&mut memory, &register, 0x5a3c);
 
         assert_eq!(result.address, 0x5a4c);
         assert_eq!(result.value, 0x42);
@@ -461,7 +531,7 @@ mod tests {
         memory[0x5b0c] = 0x42;
         register.y = 0x10;
 
-        let result = absolute_y(&memory, &register, 0x5afc);
+        let result = absolute_y(&mut memory, &register, 0x5afc);
 
         assert_eq!(result.address, 0x5b0c);
         assert_eq!(result.value, 0x42);
@@ -476,7 +546,7 @@ mod tests {
         memory[0x5a] = 0x42;
         register.y = 0x5b;
 
-        let result = absolute_y(&memory, &register, 0xffff);
+        let result = absolute_y(&mut memory, &register, 0xffff);
 
         assert_eq!(result.address, 0x5a);
         assert_eq!(result.value, 0x42);
@@ -493,7 +563,7 @@ mod tests {
         memory[0x105] = 0x42;
         register.x = 0x33;
 
-        let result = indirect_x(&memory, &register, 0x44);
+        let result = indirect_x(&mut memory, &register, 0x44);
 
         assert_eq!(result.address, 0x105);
         assert_eq!(result.value, 0x42);
@@ -506,11 +576,12 @@ mod tests {
         let mut register = Register::new();
 
         memory[0xff] = 0x05;
-        memory[0x100] = 0x01;
+        memory[0x00] = 0x01;
+        memory[0x100] = 0x02;
         memory[0x105] = 0x42;
         register.x = 0x33;
 
-        let result = indirect_x(&memory, &register, 0xcc);
+        let result = indirect_x(&mut memory, &register, 0xcc);
 
         assert_eq!(result.address, 0x105);
         assert_eq!(result.value, 0x42);
@@ -527,7 +598,7 @@ mod tests {
         memory[0x105] = 0x42;
         register.x = 0x36;
 
-        let result = indirect_x(&memory, &register, 0xcc);
+        let result = indirect_x(&mut memory, &register, 0xcc);
 
         assert_eq!(result.address, 0x105);
         assert_eq!(result.value, 0x42);
@@ -544,7 +615,7 @@ mod tests {
         memory[0x109] = 0x42;
         register.y = 0x04;
 
-        let result = indirect_y(&memory, &register, 0x77);
+        let result = indirect_y(&mut memory, &register, 0x77);
 
         assert_eq!(result.address, 0x109);
         assert_eq!(result.value, 0x42);
@@ -561,7 +632,7 @@ mod tests {
         memory[0x205] = 0x42;
         register.y = 0x06;
 
-        let result = indirect_y(&memory, &register, 0x77);
+        let result = indirect_y(&mut memory, &register, 0x77);
 
         assert_eq!(result.address, 0x205);
         assert_eq!(result.value, 0x42);
@@ -574,11 +645,12 @@ mod tests {
         let mut register = Register::new();
 
         memory[0xff] = 0x05;
-        memory[0x100] = 0x01;
+        memory[0x00] = 0x01;
+        memory[0x100] = 0x02;
         memory[0x109] = 0x42;
         register.y = 0x04;
 
-        let result = indirect_y(&memory, &register, 0xff);
+        let result = indirect_y(&mut memory, &register, 0xff);
 
         assert_eq!(result.address, 0x109);
         assert_eq!(result.value, 0x42);
@@ -1,6 +1,8 @@
-// Regarding BCD math - this ALU emulates behavior of 65C02 and 65816 CPU,
-// which corrected N and Z flags for BCD. 6502 calculates them for binary
-// math despite the fact, that the D flag is set.
+// Regarding BCD math - this ALU emulates the NMOS 6502, which (unlike 65C02 and
+// 65816) does not correct the flags in decimal mode. Z comes from the binary sum,
+// N and V from the sum after adjusting only the low digit, and subtraction sets
+// all the flags like in binary mode. Non-BCD digits give the same garbage as the
+// real chip, some software (like score routines) depends on it.
 // More info: http://www.6502.org/tutorials/decimal_mode.html#A
 
 #[derive(Debug)]
@@ -13,8 +15,12 @@ pub struct AluResult {
 }
 
 pub fn add(base: u8, operand: u8, carry_in: bool, decimal: bool) -> AluResult {
-    let (result, carry) = calculate_addition_result_in_proper_math_mode(base, operand, carry_in, decimal);
-    let overflow = calculate_overflow_bit('+', base, operand, carry_in, decimal);
+    if decimal {
+        return bcd_add(base, operand, carry_in);
+    }
+
+    let (result, carry) = bin_add(base, operand, carry_in);
+    let overflow = bin_overflow('+', base, operand, carry_in);
     let (negative, zero) = calculate_nz_bits(result);
 
     AluResult {
@@ -107,12 +113,12 @@ pub fn shift_right(operand: u8) -> AluResult {
 }
 
 pub fn subtract(base: u8, operand: u8, carry_in: bool, decimal: bool) -> AluResult {
-    let (result, carry) = calculate_subtraction_result_in_proper_math_mode(base, operand, carry_in, decimal);
-    let overflow = calculate_overflow_bit('-', base, operand, carry_in, decimal);
+    let (result, carry) = bin_subtract(base, operand, carry_in);
+    let overflow = bin_overflow('-', base, operand, carry_in);
     let (negative, zero) = calculate_nz_bits(result);
 
     AluResult {
-        value: result,
+        value: if decimal { bcd_subtract(base, operand, carry_in) } else { result },
         negative: negative,
         overflow: overflow,
         zero: zero,
@@ -138,31 +144,6 @@ fn calculate_nz_bits(operand: u8) -> (bool, bool) {
     (operand > 127, operand == 0)
 }
 
-// Based on <http://www.6502.org/tutorials/vflag.html>
-fn calculate_overflow_bit(operation: char, base: u8, operand: u8, carry: bool, decimal: bool) -> bool {
-    if !decimal || operation == '-' {
-        return bin_overflow(operation, base, operand, carry);
-    }
-
-    return bcd_overflow(base, operand, carry);
-}
-
-fn calculate_addition_result_in_proper_math_mode(base: u8, operand: u8, carry: bool, decimal: bool) -> (u8, bool) {
-    if !decimal {
-        return bin_add(base, operand, carry);
-    }
-
-    return bcd_add(base, operand, carry);
-}
-
-fn calculate_subtraction_result_in_proper_math_mode(base: u8, operand: u8, carry: bool, decimal: bool) -> (u8, bool) {
-    if !decimal {
-        return bin_subtract(base, operand, carry);
-    }
-
-    return bcd_subtract(base, operand, carry);
-}
-
 // BIN Math
 fn bin_add(a: u8, b: u8, initial_carry: bool) -> (u8, bool) {
     let (result, computed_carry) = a.overflowing_add(b);
@@ -187,6 +168,7 @@ fn bin_subtract(a: u8, b: u8, initial_carry: bool) -> (u8, bool) {
     return (result, computed_carry);
 }
 
+// Based on <http://www.6502.org/tutorials/vflag.html>
 fn bin_overflow(operation: char, a: u8, b: u8, initial_carry: bool) -> bool {
     let carry_value = if initial_carry { 1u8 } else { 0u8 };
     let left_operand: i16 = (a as i8) as i16;
@@ -198,72 +180,58 @@ fn bin_overflow(operation: char, a: u8, b: u8, initial_carry: bool) -> bool {
         left_operand + right_operand + carry_value as i16
     };
 
-    return v_sum < -128 || v_sum > 127;
+    return !(-128..=127).contains(&v_sum);
 }
 
 
 // BCD Math
-// https://homepage.cs.uiowa.edu/~jones/bcd/bcd.html
-fn bcd_valid(a: u8) -> bool {
-    let t1: u8 = a + 0x06;
-    let t2: u8 = t1 ^ a;
-    let t3: u8 = t2 & 0x10;
-    return t3 == 0;
-}
-
-fn bcd_tencomp(a: u8) -> u8 {
-    return bcd_add(0x99 - a, 0x01, false).0;
-}
-
-fn bcd_add(a: u8, b: u8, initial_carry: bool) -> (u8, bool) {
-    let t1: u16 = a as u16 + 0x0666;
-    let t2: u16 = t1 + b as u16;
-    let t3: u16 = t1 ^ b as u16;
-    let t4: u16 = t2 ^ t3;
-    let carry_correction = if !bcd_valid(a) && !bcd_valid(b) && t4 != 0 { 0x10 } else { 0 };
-    let t5: u16 = !t4 & 0x1110;
-    let t6: u16 = (t5 >> 2) | (t5 >> 3);
-    let t7: u16 = t2 - t6 - carry_correction;
-
-    let result = (t7 & 0xff) as u8;
-    let computed_carry = t7 & 0xff00 > 0;
-
-    if initial_carry {
-        let out = bcd_add(result, 0x01, false);
-        return (out.0, computed_carry | out.1);
+// Digit by digit: a digit above 9 gets 6 added and carries into the next one, a digit
+// below 0 gets 6 subtracted and borrows from the next one.
+fn bcd_add(a: u8, b: u8, initial_carry: bool) -> AluResult {
+    let mut low = (a & 0x0f) + (b & 0x0f) + initial_carry as u8;
+    if low > 0x09 {
+        low += 0x06;
     }
 
-    return (result, computed_carry);
-}
+    let mut high = (a >> 4) + (b >> 4) + (low > 0x0f) as u8;
 
-fn bcd_subtract(a: u8, b: u8, initial_carry: bool) -> (u8, bool) {
-    let t1 = bcd_tencomp(b);
-    let result = bcd_add(a, t1, false).0;
-    let computed_carry = a >= b;
+    // N and V see the high digit before its adjustment
+    let unadjusted = high << 4 | (low & 0x0f);
+    let negative = unadjusted & 0x80 != 0;
+    let overflow = (a ^ unadjusted) & (b ^ unadjusted) & 0x80 != 0;
 
-    if !initial_carry {
-        let out = bcd_subtract(result, 0x01, true);
-        return (out.0, computed_carry & out.1);
+    if high > 0x09 {
+        high += 0x06;
     }
 
-    return (result, computed_carry);
+    AluResult {
+        value: high << 4 | (low & 0x0f),
+        negative: negative,
+        overflow: overflow,
+        zero: bin_add(a, b, initial_carry).0 == 0,
+        carry: high > 0x0f
+    }
 }
 
-fn bcd_overflow(a: u8, b: u8, initial_carry: bool) -> bool {
-    let carry_value = if initial_carry { 1u8 } else { 0u8 };
-    let left_operand: i8 = ((a & 0b1111_0000) >> 4) as i8;
-    let right_operand: i8 = ((b & 0b1111_0000) >> 4) as i8;
-    let left_operand = if left_operand > 7 { left_operand - 16 } else { left_operand };
-    let right_operand = if right_operand > 7 { right_operand - 16 } else { right_operand };
-    let carry = if (a & 0b1111) + (b & 0b1111) + carry_value > 9 { 1i8 } else { 0i8 };
+// Only the value - subtraction in decimal mode sets the flags as in binary mode.
+fn bcd_subtract(a: u8, b: u8, initial_carry: bool) -> u8 {
+    let mut low = (a & 0x0f).wrapping_sub(b & 0x0f).wrapping_sub(!initial_carry as u8);
+    let low_borrow = low & 0x80 != 0;
+    if low_borrow {
+        low = low.wrapping_sub(0x06);
+    }
 
-    let v_sum = left_operand + right_operand + carry;
+    let mut high = (a >> 4).wrapping_sub(b >> 4).wrapping_sub(low_borrow as u8);
+    if high & 0x80 != 0 {
+        high = high.wrapping_sub(0x06);
+    }
 
-    return v_sum < -8 || v_sum > 7;
+    return high << 4 | (low & 0x0f);
 }
 
 #[cfg(test)]
 mod tests {
+    use super::AluResult;
     use super::add;
     use super::and;
     use super::decrement;
@@ -273,6 +241,9 @@ mod tests {
     use super::shift_right;
     use super::subtract;
     use super::xor;
+    use std::format;
+    use std::string::String;
+    use std::vec::Vec;
 
     #[test]
     fn test_binary_sum() {
@@ -389,7 +360,7 @@ mod tests {
         let result = add(0b0001_0101, 0b1000_0111, false, true); // 15 and 87 in BCD
 
         assert_eq!(result.value, 0b0000_0010); // 2 in BCD
-        assert_eq!(result.negative, false);
+        assert_eq!(result.negative, true); // from the sum before adjusting the high digit ($A2)
         assert_eq!(result.overflow, false);
         assert_eq!(result.zero, false);
         assert_eq!(result.carry, true);
@@ -400,9 +371,9 @@ mod tests {
         let result = add(0b0001_0101, 0b1000_0101, false, true); // 15 and 85 in BCD
 
         assert_eq!(result.value, 0b0000_0000); // 2 in BCD
-        assert_eq!(result.negative, false);
+        assert_eq!(result.negative, true); // from the sum before adjusting the high digit ($A0)
         assert_eq!(result.overflow, false);
-        assert_eq!(result.zero, true);
+        assert_eq!(result.zero, false); // as in binary mode ($9A)
         assert_eq!(result.carry, true);
     }
 
@@ -446,7 +417,7 @@ mod tests {
         let result = add(0b1000_0000, 0b1111_0000, false, true); // 80 and invalid number in BCD
 
         assert_eq!(result.value, 0b1101_0000); // invalid result in BCD
-        assert_eq!(result.negative, true);
+        assert_eq!(result.negative, false); // from the sum before adjusting the high digit ($170)
         assert_eq!(result.overflow, true);
         assert_eq!(result.zero, false);
         assert_eq!(result.carry, true);
@@ -831,7 +802,7 @@ mod tests {
         let result = subtract(0b0001_0101, 0b0101_0000, true, true); // 15 and 50 in BCD
 
         assert_eq!(result.value, 0b0110_0101); // 65 in BCD (wraparound)
-        assert_eq!(result.negative, false);
+        assert_eq!(result.negative, true); // as in binary mode ($C5)
         assert_eq!(result.overflow, false);
         assert_eq!(result.zero, false);
         assert_eq!(result.carry, false);
@@ -886,7 +857,7 @@ mod tests {
         let result = subtract(0b0001_0101, 0b0101_0000, false, true); // 15 and 50 in BCD
 
         assert_eq!(result.value, 0b0110_0100); // 64 in BCD (wraparound)
-        assert_eq!(result.negative, false);
+        assert_eq!(result.negative, true); // as in binary mode ($C4)
         assert_eq!(result.overflow, false);
         assert_eq!(result.zero, false);
         assert_eq!(result.carry, false);
@@ -899,10 +870,39 @@ mod tests {
         assert_eq!(result.value, 0b0000_0000); // 0 in BCD
         assert_eq!(result.negative, false);
         assert_eq!(result.overflow, false);
-        assert_eq!(result.zero, true);
+        assert_eq!(result.zero, false); // as in binary mode ($06)
         assert_eq!(result.carry, true);
     }
 
+    // NMOS results from the published tables (6502.org decimal mode tutorial, appendix A),
+    // independent of the reference model the exhaustive tests below compare with.
+    #[test]
+    fn test_bcd_published_results() {
+        // (base, operand, carry in) => (value, negative, overflow, zero, carry)
+        let sums = [
+            ((0x99, 0x01, false), (0x00, true, false, false, true)), // Z and N from before the adjustment
+            ((0x99, 0x01, true), (0x01, true, false, false, true)),
+            ((0x0f, 0x0f, false), (0x14, false, false, false, false)), // invalid digits
+            ((0x50, 0x50, false), (0x00, true, true, false, true))
+        ];
+        let differences = [
+            ((0x00, 0x01, true), (0x99, true, false, false, false)),
+            ((0x00, 0x01, false), (0x98, true, false, false, false)),
+            ((0x00, 0x0f, true), (0x9b, true, false, false, false)), // invalid digits
+            ((0x0a, 0x00, true), (0x0a, false, false, false, true))
+        ];
+
+        for &((base, operand, carry), expected) in sums.iter() {
+            let result = add(base, operand, carry, true);
+            assert_eq!((result.value, result.negative, result.overflow, result.zero, result.carry), expected, "${:02x} + ${:02x} + {}", base, operand, carry as u8);
+        }
+
+        for &((base, operand, carry), expected) in differences.iter() {
+            let result = subtract(base, operand, carry, true);
+            assert_eq!((result.value, result.negative, result.overflow, result.zero, result.carry), expected, "${:02x} - ${:02x} - {}", base, operand, !carry as u8);
+        }
+    }
+
     #[test]
     fn test_binary_xor() {
         let result = xor(0b0110_0111, 0b0010_1010);
@@ -935,4 +935,116 @@ mod tests {
         assert_eq!(result.zero, false);
         assert_eq!(result.carry, false);
     }
+
+    type Flags = (u8, bool, bool, bool, bool); // value, N, V, Z, C
+
+    // ADC and SBC of the 6502 in binary mode.
+    fn binary_add(a: u8, b: u8, carry: bool) -> Flags {
+        let binary = a as u16 + b as u16 + carry as u16;
+        let value = binary as u8;
+        let overflow = (a ^ value) & (b ^ value) & 0x80 != 0;
+
+        return (value, value & 0x80 != 0, overflow, value == 0, binary > 0xff);
+    }
+
+    fn binary_subtract(a: u8, b: u8, carry: bool) -> Flags {
+        return binary_add(a, !b, carry);
+    }
+
+    // Decimal mode predicted with binary arithmetic, the way Bruce Clark's 6502_decimal_test
+    // does it (see examples/6502_decimal_test.s, ADD and A6502) - the low digits are added
+    // and adjusted first, a digit carry adds $0F plus the carry to the high digit of the
+    // operand. N and V come from adding the high digits, Z from the binary sum.
+    fn reference_add(a: u8, b: u8, carry: bool, decimal: bool) -> Flags {
+        let binary = binary_add(a, b, carry);
+
+        if !decimal {
+            return binary;
+        }
+
+        let low = binary_add(a & 0x0f, b & 0x0f, carry).0;
+        let (low, high_operand, high_carry) = if low >= 0x0a {
+            (binary_add(low, 0x05, true).0 & 0x0f, (b & 0xf0) + 0x0f, true)
+        } else {
+            (low, b & 0xf0, false)
+        };
+
+        let (high, negative, overflow, _, high_carry) = binary_add(low | (a & 0xf0), high_operand, high_carry);
+        let (value, carry_out) = if high_carry || high >= 0xa0 {
+            (binary_add(high, 0x5f, true).0, true)
+        } else {
+            (high, false)
+        };
+
+        return (value, negative, overflow, binary.3, carry_out);
+    }
+
+    // The same for SBC (SUB1 and S6502 there), all the flags are the binary ones.
+    fn reference_subtract(a: u8, b: u8, carry: bool, decimal: bool) -> Flags {
+        let binary = binary_subtract(a, b, carry);
+
+        if !decimal {
+            return binary;
+        }
+
+        let (low, _, _, _, no_borrow) = binary_subtract(a & 0x0f, b & 0x0f, carry);
+        let (low, high_operand, high_carry) = if no_borrow {
+            (low, b & 0xf0, true)
+        } else {
+            (binary_subtract(low, 0x05, false).0 & 0x0f, (b & 0xf0) + 0x0f, false)
+        };
+
+        let (high, _, _, _, no_borrow) = binary_subtract(low | (a & 0xf0), high_operand, high_carry);
+        let value = if no_borrow { high } else { binary_subtract(high, 0x5f, false).0 };
+
+        return (value, binary.1, binary.2, binary.3, binary.4);
+    }
+
+    // Runs every base, operand and carry combination, reports the differences.
+    fn mismatches(name: &str, operation: fn(u8, u8, bool, bool) -> AluResult, reference: fn(u8, u8, bool, bool) -> Flags, decimal: bool) -> Vec<String> {
+        let mut mismatches = Vec::new();
+
+        for base in 0..=255u8 {
+            for operand in 0..=255u8 {
+                for &carry in [false, true].iter() {
+                    let result = operation(base, operand, carry, decimal);
+                    let actual = (result.value, result.negative, result.overflow, result.zero, result.carry);
+                    let expected = reference(base, operand, carry, decimal);
+
+                    if actual != expected {
+                        mismatches.push(format!(
+                            "{}(${:02x}, ${:02x}, carry: {}, decimal: {}) - (value, N, V, Z, C): {:02x?}, expected {:02x?}",
+                            name, base, operand, carry, decimal, actual, expected
+                        ));
+                    }
+                }
+            }
+        }
+
+        mismatches
+    }
+
+    fn assert_no_mismatches(mismatches: Vec<String>) {
+        assert!(mismatches.is_empty(), "{} mismatches, first ones:\n{}", mismatches.len(), mismatches[..mismatches.len().min(10)].join("\n"));
+    }
+
+    #[test]
+    fn test_add_against_reference_in_binary_mode() {
+        assert_no_mismatches(mismatches("add", add, reference_add, false));
+    }
+
+    #[test]
+    fn test_add_against_reference_in_decimal_mode() {
+        assert_no_mismatches(mismatches("add", add, reference_add, true));
+    }
+
+    #[test]
+    fn test_subtract_against_reference_in_binary_mode() {
+        assert_no_mismatches(mismatches("subtract", subtract, reference_subtract, false));
+    }
+
+    #[test]
+    fn test_subtract_against_reference_in_decimal_mode() {
+        assert_no_mismatches(mismatches("subtract", subtract, reference_subtract, true));
+    }
 }
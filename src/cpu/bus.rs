@@ -0,0 +1,124 @@
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
+
+#[cfg(feature = "std")]
+use super::diagnostics;
+#[cfg(feature = "std")]
+use super::hooks::Hooks;
+use super::memory::Access;
+use super::memory::Memory;
+#[cfg(feature = "std")]
+use super::smc;
+
+// CPU side view of the memory. Forwards every access to the underlying array,
+// and lets the enabled inspectors observe it on the way. Frozen bytes keep their
+// value, writes to them are observed but do not change the memory. Without the `std`
+// feature there is nothing to forward to, and the bus is just the memory.
+pub struct Bus<'a> {
+    memory: &'a mut [u8],
+    pc: u16, // address of the last fetched opcode
+    #[cfg(feature = "std")]
+    pub frozen: BTreeMap<u16, u8>,
+    #[cfg(feature = "std")]
+    smc: Option<smc::Tracker<'a>>,
+    #[cfg(feature = "std")]
+    checker: Option<diagnostics::Checker<'a>>,
+    #[cfg(feature = "std")]
+    pub hooks: Hooks<'a>
+}
+
+impl<'a> Bus<'a> {
+    pub fn new(memory: &'a mut [u8]) -> Bus<'a> {
+        Bus {
+            memory: memory,
+            pc: 0,
+            #[cfg(feature = "std")]
+            frozen: BTreeMap::new(),
+            #[cfg(feature = "std")]
+            smc: None,
+            #[cfg(feature = "std")]
+            checker: None,
+            #[cfg(feature = "std")]
+            hooks: Hooks::default()
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn len(&self) -> usize {
+        self.memory.len()
+    }
+
+    // Direct access to the memory, bypassing all the inspectors.
+    pub fn memory(&self) -> &[u8] {
+        self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        self.memory
+    }
+
+    #[cfg(feature = "std")]
+    pub fn set_smc_tracker(&mut self, tracker: smc::Tracker<'a>) {
+        self.smc = Some(tracker);
+    }
+
+    #[cfg(feature = "std")]
+    pub fn set_checker(&mut self, checker: diagnostics::Checker<'a>) {
+        self.checker = Some(checker);
+    }
+}
+
+impl<'a> Memory for Bus<'a> {
+    fn read(&mut self, address: usize, access: Access) -> u8 {
+        let value = self.memory[address];
+
+        if access == Access::Opcode {
+            self.pc = address as u16;
+        }
+
+        #[cfg(feature = "std")]
+        if let Some(smc) = &mut self.smc {
+            if access == Access::Opcode || access == Access::Operand {
+                smc.fetch(self.pc, address, value);
+            }
+        }
+
+        #[cfg(feature = "std")]
+        if let Some(checker) = &mut self.checker {
+            checker.read(self.pc, address, access);
+        }
+
+        #[cfg(feature = "std")]
+        self.hooks.read(address, value, access);
+
+        value
+    }
+
+    #[cfg_attr(not(feature = "std"), allow(unused_variables))]
+    fn write(&mut self, address: usize, value: u8, access: Access) {
+        #[cfg(feature = "std")]
+        if let Some(smc) = &mut self.smc {
+            smc.write(self.pc, address, self.memory[address], value);
+        }
+
+        #[cfg(feature = "std")]
+        if let Some(checker) = &mut self.checker {
+            checker.write(self.pc, address, access);
+        }
+
+        #[cfg(feature = "std")]
+        self.hooks.write(address, value, access);
+
+        #[cfg(feature = "std")]
+        let value = match self.frozen.get(&(address as u16)) {
+            Some(&frozen) => frozen,
+            None => value
+        };
+
+        self.memory[address] = value;
+    }
+
+    fn peek(&self, address: usize) -> u8 {
+        self.memory[address]
+    }
+}
@@ -0,0 +1,218 @@
+// Calling 6502 subroutines from Rust, mostly for unit testing assembly routines.
+//
+// The call pushes a sentinel return address, as JSR would, and runs the routine
+// until its RTS returns to the sentinel. Pulling more from the stack than the
+// routine pushed, or returning with a different stack pointer, means the routine
+// corrupted the stack. The stack pointer may wrap around page $01 while doing so.
+//
+// After an error the registers are restored to what they were before the call, the
+// memory keeps whatever the routine wrote.
+
+use std::error::Error;
+use std::fmt;
+
+use super::run::Condition;
+use super::run::StopReason;
+use super::addressing::stack_push;
+use super::Cpu;
+
+const SENTINEL: u16 = 0x0000; // PC after RTS to the pushed $FFFF
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Regs {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MemoryChange {
+    pub address: u16,
+    pub old: u8,
+    pub new: u8
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallResult {
+    pub registers: Regs,
+    pub cycles: usize,
+    pub changes: Vec<MemoryChange>
+}
+
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CallError {
+    CycleLimit { pc: u16 },           // routine did not return within the limit
+    StackCorrupted { pc: u16, s: u8 } // routine pulled its own return address, or returned with unbalanced stack
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CallError::CycleLimit { pc } => write!(f, "routine did not return within the cycle limit, PC ${:04X}", pc),
+            CallError::StackCorrupted { pc, s } => write!(f, "routine corrupted the stack, PC ${:04X}, S ${:02X}", pc, s)
+        }
+    }
+}
+
+impl Error for CallError {}
+
+impl<'a> Cpu<'a> {
+    pub fn call(&mut self, address: u16, registers: Regs, cycle_limit: usize) -> Result<CallResult, CallError> {
+        let memory_before = self.bus.memory().to_vec();
+        let cycles_before = self.cycles;
+        let register_before = self.register;
+        let s_before = self.register.s();
+        let frame = [0x100 + s_before as u16, 0x100 + s_before.wrapping_sub(1) as u16];
+
+        stack_push(&mut self.bus, &mut self.register, (SENTINEL.wrapping_sub(1) >> 8) as u8);
+        stack_push(&mut self.bus, &mut self.register, SENTINEL.wrapping_sub(1) as u8);
+
+        self.register.a = registers.a;
+        self.register.x = registers.x;
+        self.register.y = registers.y;
+        self.register.set_p(registers.p);
+        self.register.set_pc(address);
+
+        // returned (or pulled) once fewer than the two sentinel bytes are on the stack - the
+        // depth is unsigned, a routine may use all of the stack below them
+        let reason = self.run_until(Condition::Check(Box::new(|register| s_before.wrapping_sub(register.s()) < 2)), cycle_limit);
+
+        let pc = self.register.pc();
+        let s = self.register.s();
+
+        if reason == StopReason::CycleBudget {
+            self.register = register_before;
+            return Err(CallError::CycleLimit { pc: pc });
+        }
+
+        if pc != SENTINEL || s != s_before {
+            self.register = register_before;
+            return Err(CallError::StackCorrupted { pc: pc, s: s });
+        }
+
+        self.register.set_pc(register_before.pc());
+
+        let changes = memory_before.iter().zip(self.bus.memory().iter()).enumerate()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(address, (&old, &new))| MemoryChange { address: address as u16, old: old, new: new })
+            .filter(|change| !frame.contains(&change.address))
+            .collect();
+
+        return Ok(CallResult {
+            registers: Regs { a: self.register.a, x: self.register.x, y: self.register.y, p: self.register.p() },
+            cycles: self.cycles - cycles_before,
+            changes: changes
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CallError;
+    use super::MemoryChange;
+    use super::Regs;
+    use crate::cpu::Cpu;
+
+    fn memory_with(program: &[u8]) -> [u8; 65536] {
+        let mut memory = [0; 65536];
+        memory[0x0600..0x0600 + program.len()].copy_from_slice(program);
+
+        memory
+    }
+
+    #[test]
+    fn test_call() {
+        // $0600: ASL A
+        // $0601: STA $80
+        // $0603: INX
+        // $0604: RTS
+        let mut memory = memory_with(&[0x0a, 0x85, 0x80, 0xe8, 0x60]);
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.cold_reset();
+
+        let result = cpu.call(0x0600, Regs { a: 0x21, x: 0x01, y: 0x00, p: 0x00 }, 1000).unwrap();
+
+        assert_eq!(result.registers, Regs { a: 0x42, x: 0x02, y: 0x00, p: 0x20 });
+        assert_eq!(result.cycles, 13);
+        assert_eq!(result.changes, vec![MemoryChange { address: 0x80, old: 0x00, new: 0x42 }]);
+        assert_eq!(cpu.register().pc(), 0x0400);
+        assert_eq!(cpu.register().s(), 0xff);
+    }
+
+    #[test]
+    fn test_call_with_wrapping_stack() {
+        // $0600: PHA
+        // $0601: STA $80
+        // $0603: PLA
+        // $0604: RTS
+        let mut memory = memory_with(&[0x48, 0x85, 0x80, 0x68, 0x60]);
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.cold_reset();
+        cpu.register_mut().set_s(0x00);
+
+        let result = cpu.call(0x0600, Regs { a: 0x42, x: 0x00, y: 0x00, p: 0x00 }, 1000).unwrap();
+
+        assert_eq!(result.changes, vec![
+            MemoryChange { address: 0x80, old: 0x00, new: 0x42 },
+            MemoryChange { address: 0x01fe, old: 0x00, new: 0x42 }
+        ]);
+        assert_eq!(cpu.register().s(), 0x00);
+        assert_eq!(cpu.register().pc(), 0x0400);
+    }
+
+    #[test]
+    fn test_call_with_deep_stack() {
+        // $0600: LDX #$C8
+        // $0602: PHA
+        // $0603: DEX
+        // $0604: BNE $0602
+        // $0606: LDX #$C8
+        // $0608: PLA
+        // $0609: DEX
+        // $060A: BNE $0608
+        // $060C: RTS
+        let mut memory = memory_with(&[0xa2, 0xc8, 0x48, 0xca, 0xd0, 0xfc, 0xa2, 0xc8, 0x68, 0xca, 0xd0, 0xfc, 0x60]);
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.cold_reset();
+
+        let result = cpu.call(0x0600, Regs::default(), 10000).unwrap();
+
+        assert_eq!(result.registers.x, 0x00);
+        assert_eq!(cpu.register().s(), 0xff);
+    }
+
+    #[test]
+    fn test_call_never_returning() {
+        // $0600: JMP $0600
+        let mut memory = memory_with(&[0x4c, 0x00, 0x06]);
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.cold_reset();
+
+        assert_eq!(cpu.call(0x0600, Regs { a: 0x42, x: 0x00, y: 0x00, p: 0x00 }, 100), Err(CallError::CycleLimit { pc: 0x0600 }));
+        assert_eq!((cpu.register().pc(), cpu.register().s(), cpu.register().a), (0x0400, 0xff, 0x00));
+    }
+
+    #[test]
+    fn test_call_pulling_return_address() {
+        // $0600: PLA
+        // $0601: RTS
+        let mut memory = memory_with(&[0x68, 0x60]);
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.cold_reset();
+
+        assert_eq!(cpu.call(0x0600, Regs::default(), 100), Err(CallError::StackCorrupted { pc: 0x0601, s: 0xfe }));
+    }
+
+    #[test]
+    fn test_call_with_unbalanced_stack() {
+        // $0600: PHA
+        // $0601: RTS
+        let mut memory = memory_with(&[0x48, 0x60]);
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.cold_reset();
+
+        assert_eq!(cpu.call(0x0600, Regs::default(), 100), Err(CallError::StackCorrupted { pc: 0xff01, s: 0xfe }));
+    }
+}
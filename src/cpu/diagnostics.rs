@@ -0,0 +1,194 @@
+// Checked mode - reports suspicious memory accesses which are otherwise silent,
+// because any zero-filled array is a valid memory for the CPU.
+//
+// Memory starts as never-written, except the ranges marked as initialized
+// (like loaded ROM images). Stack wraps are detected from the stack accesses:
+// a push writing to $0100 makes S wrap to $FF, and a pull reading from $0100
+// happens only when S wrapped from $FF to $00.
+
+use std::ops::RangeInclusive;
+
+use super::memory::Access;
+
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Diagnostic {
+    UninitializedRead { pc: u16, address: u16 },
+    UninitializedExecution { pc: u16, address: u16 },
+    StackOverflow { pc: u16 },  // push with S = $00
+    StackUnderflow { pc: u16 }, // pull with S = $FF
+    OutOfRegion { pc: u16, address: u16, access: Access }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Checks {
+    regions: Vec<RangeInclusive<u16>>,
+    initialized: Vec<RangeInclusive<u16>>
+}
+
+impl Checks {
+    pub fn new() -> Checks {
+        Checks { regions: Vec::new(), initialized: Vec::new() }
+    }
+
+    // Allows accesses to given range. When no region is configured, the whole memory is allowed.
+    pub fn region(mut self, range: RangeInclusive<u16>) -> Checks {
+        self.regions.push(range);
+        self
+    }
+
+    // Treats given range as written, so reads from it are not reported.
+    pub fn initialized(mut self, range: RangeInclusive<u16>) -> Checks {
+        self.initialized.push(range);
+        self
+    }
+}
+
+pub struct Checker<'a> {
+    written: Vec<bool>,
+    regions: Vec<RangeInclusive<u16>>,
+    callback: Box<dyn FnMut(Diagnostic) + 'a>
+}
+
+impl<'a> Checker<'a> {
+    pub fn new<F: FnMut(Diagnostic) + 'a>(size: usize, checks: Checks, callback: F) -> Checker<'a> {
+        let mut written = vec![false; size];
+
+        for range in checks.initialized {
+            for address in range {
+                if (address as usize) < size {
+                    written[address as usize] = true;
+                }
+            }
+        }
+
+        Checker {
+            written: written,
+            regions: checks.regions,
+            callback: Box::new(callback)
+        }
+    }
+
+    pub fn read(&mut self, pc: u16, address: usize, access: Access) {
+        self.check_region(pc, address, access);
+
+        if access == Access::Stack && address == 0x100 {
+            (self.callback)(Diagnostic::StackUnderflow { pc: pc });
+        }
+
+        if !self.written[address] && access != Access::Dummy {
+            let diagnostic = match access {
+                Access::Opcode | Access::Operand => Diagnostic::UninitializedExecution { pc: pc, address: address as u16 },
+                _ => Diagnostic::UninitializedRead { pc: pc, address: address as u16 }
+            };
+
+            (self.callback)(diagnostic);
+        }
+    }
+
+    pub fn write(&mut self, pc: u16, address: usize, access: Access) {
+        self.check_region(pc, address, access);
+
+        if access == Access::Stack && address == 0x100 {
+            (self.callback)(Diagnostic::StackOverflow { pc: pc });
+        }
+
+        self.written[address] = true;
+    }
+
+    fn check_region(&mut self, pc: u16, address: usize, access: Access) {
+        if self.regions.is_empty() {
+            return;
+        }
+
+        if !self.regions.iter().any(|range| range.contains(&(address as u16))) {
+            (self.callback)(Diagnostic::OutOfRegion { pc: pc, address: address as u16, access: access });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Checks;
+    use super::Diagnostic;
+    use crate::cpu::Access;
+    use crate::cpu::Cpu;
+
+    fn run(program: &[u8], checks: Checks, steps: usize) -> Vec<Diagnostic> {
+        let mut memory = [0; 65536];
+        let mut diagnostics = Vec::new();
+        memory[0x0400..0x0400 + program.len()].copy_from_slice(program);
+
+        {
+            let mut cpu = Cpu::new(&mut memory);
+            cpu.check(checks, |diagnostic| diagnostics.push(diagnostic));
+            cpu.cold_reset();
+
+            for _ in 0..steps {
+                cpu.step();
+            }
+        }
+
+        diagnostics
+    }
+
+    #[test]
+    fn test_uninitialized_read() {
+        // $0400: LDA $80
+        // $0402: STA $81
+        // $0404: LDA $81
+        let checks = Checks::new().initialized(0x0400..=0x04ff);
+        let diagnostics = run(&[0xa5, 0x80, 0x85, 0x81, 0xa5, 0x81], checks, 3);
+
+        assert_eq!(diagnostics, vec![
+            Diagnostic::UninitializedRead { pc: 0x0400, address: 0x80 }
+        ]);
+    }
+
+    #[test]
+    fn test_uninitialized_execution() {
+        // $0400: JMP $0500
+        // $0500: BRK
+        let checks = Checks::new().initialized(0x0400..=0x0402).initialized(0xfffe..=0xffff);
+        let diagnostics = run(&[0x4c, 0x00, 0x05], checks, 2);
+
+        assert_eq!(diagnostics, vec![
+            Diagnostic::UninitializedExecution { pc: 0x0500, address: 0x0500 }
+        ]);
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        // $0400: LDX #$00
+        // $0402: TXS
+        // $0403: PHA
+        let checks = Checks::new().initialized(0x0000..=0xffff);
+        let diagnostics = run(&[0xa2, 0x00, 0x9a, 0x48], checks, 3);
+
+        assert_eq!(diagnostics, vec![
+            Diagnostic::StackOverflow { pc: 0x0403 }
+        ]);
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        // $0400: PLA
+        let checks = Checks::new().initialized(0x0000..=0xffff);
+        let diagnostics = run(&[0x68], checks, 1);
+
+        assert_eq!(diagnostics, vec![
+            Diagnostic::StackUnderflow { pc: 0x0400 }
+        ]);
+    }
+
+    #[test]
+    fn test_out_of_region() {
+        // $0400: STA $C000
+        let checks = Checks::new().initialized(0x0000..=0xffff).region(0x0000..=0xbfff).region(0xfffa..=0xffff);
+        let diagnostics = run(&[0x8d, 0x00, 0xc0], checks, 1);
+
+        assert_eq!(diagnostics, vec![
+            Diagnostic::OutOfRegion { pc: 0x0400, address: 0xc000, access: Access::Data }
+        ]);
+    }
+}
@@ -0,0 +1,211 @@
+// Extension points for tools (tracers, profilers, cheat finders, HLE traps).
+// Hooks are kept in plain vectors, so when none are registered the only cost
+// is an empty loop. They need the `std` feature, the interrupt kinds do not.
+
+#[cfg(feature = "std")]
+use super::memory::Access;
+#[cfg(feature = "std")]
+use super::register::Register;
+
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Interrupt {
+    Reset,
+    Brk,
+    Irq,
+    Nmi
+}
+
+#[cfg(feature = "std")]
+type InstructionHook<'a> = Box<dyn FnMut(&Register) + 'a>;
+#[cfg(feature = "std")]
+type CompletionHook<'a> = Box<dyn FnMut(&Register, usize) + 'a>;
+#[cfg(feature = "std")]
+type MemoryHook<'a> = Box<dyn FnMut(u16, u8, Access) + 'a>;
+#[cfg(feature = "std")]
+type InterruptHook<'a> = Box<dyn FnMut(Interrupt, &Register) + 'a>;
+
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub struct Hooks<'a> {
+    pub before_instruction: Vec<InstructionHook<'a>>,
+    pub after_instruction: Vec<CompletionHook<'a>>,
+    pub read: Vec<MemoryHook<'a>>,
+    pub write: Vec<MemoryHook<'a>>,
+    pub interrupt: Vec<InterruptHook<'a>>
+}
+
+#[cfg(feature = "std")]
+impl<'a> Hooks<'a> {
+    pub fn before_instruction(&mut self, register: &Register) {
+        for hook in self.before_instruction.iter_mut() {
+            hook(register);
+        }
+    }
+
+    pub fn after_instruction(&mut self, register: &Register, cycles: usize) {
+        for hook in self.after_instruction.iter_mut() {
+            hook(register, cycles);
+        }
+    }
+
+    pub fn read(&mut self, address: usize, value: u8, access: Access) {
+        for hook in self.read.iter_mut() {
+            hook(address as u16, value, access);
+        }
+    }
+
+    pub fn write(&mut self, address: usize, value: u8, access: Access) {
+        for hook in self.write.iter_mut() {
+            hook(address as u16, value, access);
+        }
+    }
+
+    pub fn interrupt(&mut self, interrupt: Interrupt, register: &Register) {
+        for hook in self.interrupt.iter_mut() {
+            hook(interrupt, register);
+        }
+    }
+}
+
+// The hooks are installed through the std-only Cpu methods.
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::Interrupt;
+    use crate::cpu::Access;
+    use crate::cpu::Cpu;
+
+    fn memory_with(program: &[u8]) -> [u8; 65536] {
+        let mut memory = [0; 65536];
+        memory[0x0400..0x0400 + program.len()].copy_from_slice(program);
+
+        memory
+    }
+
+    #[test]
+    fn test_instruction_hooks() {
+        // $0400: LDA #$42
+        // $0402: TAX
+        let mut memory = memory_with(&[0xa9, 0x42, 0xaa]);
+        let mut before = Vec::new();
+        let mut after = Vec::new();
+
+        {
+            let mut cpu = Cpu::new(&mut memory);
+            cpu.cold_reset();
+            cpu.on_before_instruction(|register| before.push((register.pc(), register.a, register.x)));
+            cpu.on_after_instruction(|register, cycles| after.push((register.pc(), register.a, register.x, cycles)));
+            cpu.step();
+            cpu.step();
+        }
+
+        assert_eq!(before, vec![(0x0400, 0x00, 0x00), (0x0402, 0x42, 0x00)]);
+        assert_eq!(after, vec![(0x0402, 0x42, 0x00, 2), (0x0403, 0x42, 0x42, 2)]);
+    }
+
+    #[test]
+    fn test_memory_hooks() {
+        // $0400: INC $80
+        // $0402: PHA
+        let mut memory = memory_with(&[0xe6, 0x80, 0x48]);
+        memory[0x80] = 0x41;
+        let mut reads = Vec::new();
+        let mut writes = Vec::new();
+
+        {
+            let mut cpu = Cpu::new(&mut memory);
+            cpu.cold_reset();
+            cpu.on_read(|address, value, access| reads.push((address, value, access)));
+            cpu.on_write(|address, value, access| writes.push((address, value, access)));
+            cpu.step();
+            cpu.step();
+        }
+
+        assert_eq!(reads, vec![
+            (0x0400, 0xe6, Access::Opcode),
+            (0x0401, 0x80, Access::Operand),
+            (0x0080, 0x41, Access::Data),
+            (0x0402, 0x48, Access::Opcode)
+        ]);
+        assert_eq!(writes, vec![
+            (0x0080, 0x41, Access::Dummy),
+            (0x0080, 0x42, Access::Data),
+            (0x01ff, 0x00, Access::Stack)
+        ]);
+    }
+
+    #[test]
+    fn test_dummy_reads() {
+        // $0400: LDX #$20
+        // $0402: LDA $04F0,X
+        // $0405: STA $0480,X
+        let mut memory = memory_with(&[0xa2, 0x20, 0xbd, 0xf0, 0x04, 0x9d, 0x80, 0x04]);
+        let mut reads = Vec::new();
+
+        {
+            let mut cpu = Cpu::new(&mut memory);
+            cpu.cold_reset();
+            cpu.step();
+            cpu.on_read(|address, _value, access| reads.push((address, access)));
+            cpu.step();
+            cpu.step();
+        }
+
+        // the page crossing read first goes to $0410, the store always reads before writing
+        assert_eq!(reads, vec![
+            (0x0402, Access::Opcode),
+            (0x0403, Access::Operand),
+            (0x0404, Access::Operand),
+            (0x0410, Access::Dummy),
+            (0x0510, Access::Data),
+            (0x0405, Access::Opcode),
+            (0x0406, Access::Operand),
+            (0x0407, Access::Operand),
+            (0x04a0, Access::Dummy)
+        ]);
+    }
+
+    #[test]
+    fn test_interrupt_hooks() {
+        // $0400: BRK
+        let mut memory = memory_with(&[0x00]);
+        memory[0xfffe] = 0x00;
+        memory[0xffff] = 0x06;
+        let mut interrupts = Vec::new();
+
+        {
+            let mut cpu = Cpu::new(&mut memory);
+            cpu.on_interrupt(|interrupt, register| interrupts.push((interrupt, register.pc())));
+            cpu.cold_reset();
+            cpu.step();
+        }
+
+        assert_eq!(interrupts, vec![(Interrupt::Reset, 0x0400), (Interrupt::Brk, 0x0600)]);
+    }
+
+    #[test]
+    fn test_irq_and_nmi_hooks() {
+        let mut memory = memory_with(&[0xea]);
+        memory[0xfffa] = 0x00;
+        memory[0xfffb] = 0x07;
+        memory[0xfffe] = 0x00;
+        memory[0xffff] = 0x06;
+        let mut interrupts = Vec::new();
+
+        {
+            let mut cpu = Cpu::new(&mut memory);
+            cpu.cold_reset();
+            cpu.on_interrupt(|interrupt, register| interrupts.push((interrupt, register.pc())));
+
+            assert_eq!(cpu.irq(), false);
+            cpu.register_mut().set_interrupt_bit(false);
+            assert_eq!(cpu.irq(), true);
+            cpu.nmi();
+            assert_eq!(cpu.register().s(), 0xf9);
+            assert_eq!(cpu.cycles, 14);
+        }
+
+        assert_eq!(interrupts, vec![(Interrupt::Irq, 0x0600), (Interrupt::Nmi, 0x0700)]);
+        assert_eq!(&memory[0x01fa..0x0200], &[0x24, 0x00, 0x06, 0x20, 0x00, 0x04]);
+    }
+}
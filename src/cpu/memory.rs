@@ -0,0 +1,50 @@
+// Every access the CPU makes goes through the Memory trait, tagged with the
+// reason for the access. Plain byte arrays implement it directly, so the
+// instruction handlers can still be exercised against a bare array, while
+// the CPU itself wraps its memory in a Bus, which can observe the traffic.
+
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Access {
+    Opcode,  // first byte of an instruction
+    Operand, // remaining bytes of an instruction, fetched from PC
+    Data,    // effective address reads/writes, pointers and vectors
+    Stack,   // pushes and pulls in page $01
+    Dummy    // accesses with discarded result, like the first write of read-modify-write instructions
+}
+
+pub trait Memory {
+    fn read(&mut self, address: usize, access: Access) -> u8;
+    fn write(&mut self, address: usize, value: u8, access: Access);
+
+    // Side effect free read - it is not reported to any observer.
+    fn peek(&self, address: usize) -> u8;
+}
+
+impl Memory for [u8] {
+    fn read(&mut self, address: usize, _access: Access) -> u8 {
+        self[address]
+    }
+
+    fn write(&mut self, address: usize, value: u8, _access: Access) {
+        self[address] = value;
+    }
+
+    fn peek(&self, address: usize) -> u8 {
+        self[address]
+    }
+}
+
+impl<const N: usize> Memory for [u8; N] {
+    fn read(&mut self, address: usize, _access: Access) -> u8 {
+        self[address]
+    }
+
+    fn write(&mut self, address: usize, value: u8, _access: Access) {
+        self[address] = value;
+    }
+
+    fn peek(&self, address: usize) -> u8 {
+        self[address]
+    }
+}
@@ -3,6 +3,8 @@ use super::addressing::Addressing;
 use super::addressing::MemoryCell;
 use super::addressing::stack_push;
 use super::addressing::stack_pull;
+use super::memory::Access;
+use super::memory::Memory;
 use super::register::Register;
 
 #[derive(Copy, Clone, Debug)]
@@ -21,7 +23,7 @@ pub enum Mnemonics {
 }
 
 impl Mnemonics {
-    pub fn handle(&self, register: &mut Register, memory: &mut [u8]) -> u8 {
+    pub fn handle<M: Memory + ?Sized>(&self, register: &mut Register, memory: &mut M) -> u8 {
         match self {
             Mnemonics::ADC(addressing) => {
                 let cell = addressing.read(memory, register);
@@ -32,7 +34,7 @@ impl Mnemonics {
                 and(cell, register)
             },
             Mnemonics::ASL(addressing) => {
-                let cell = addressing.read(memory, register);
+                let cell = addressing.modify(memory, register);
                 asl(memory, cell, register)
             },
             Mnemonics::BCC(addressing) => {
@@ -89,7 +91,7 @@ impl Mnemonics {
                 cpy(cell, register)
             },
             Mnemonics::DEC(addressing) => {
-                let cell = addressing.read(memory, register);
+                let cell = addressing.modify(memory, register);
                 dec(memory, cell, register)
             },
             Mnemonics::DEX(_addressing) => { dex(register) },
@@ -99,17 +101,17 @@ impl Mnemonics {
                 eor(cell, register)
             },
             Mnemonics::INC(addressing) => {
-                let cell = addressing.read(memory, register);
+                let cell = addressing.modify(memory, register);
                 inc(memory, cell, register)
             },
             Mnemonics::INX(_addressing) => { inx(register) },
             Mnemonics::INY(_addressing) => { iny(register) },
             Mnemonics::JMP(addressing) => {
-                let cell = addressing.read(memory, register);
+                let cell = addressing.locate(memory, register);
                 jmp(cell, register)
             },
             Mnemonics::JSR(addressing) => {
-                let cell = addressing.read(memory, register);
+                let cell = addressing.locate(memory, register);
                 jsr(memory, cell, register)
             },
             Mnemonics::LDA(addressing) => {
@@ -125,7 +127,7 @@ impl Mnemonics {
                 ldy(cell, register)
             },
             Mnemonics::LSR(addressing) => {
-                let cell = addressing.read(memory, register);
+                let cell = addressing.modify(memory, register);
                 lsr(memory, cell, register)
             },
             Mnemonics::NOP(_addressing) => { nop() },
@@ -138,11 +140,11 @@ impl Mnemonics {
             Mnemonics::PLA(_addressing) => { pla(memory, register) },
             Mnemonics::PLP(_addressing) => { plp(memory, register) },
             Mnemonics::ROL(addressing) => {
-                let cell = addressing.read(memory, register);
+                let cell = addressing.modify(memory, register);
                 rol(memory, cell, register)
             },
             Mnemonics::ROR(addressing) => {
-                let cell = addressing.read(memory, register);
+                let cell = addressing.modify(memory, register);
                 ror(memory, cell, register)
             },
             Mnemonics::RTI(_addressing) => { rti(memory, register) },
@@ -155,15 +157,15 @@ impl Mnemonics {
             Mnemonics::SED(_addressing) => { sed(register) },
             Mnemonics::SEI(_addressing) => { sei(register) },
             Mnemonics::STA(addressing) => {
-                let cell = addressing.read(memory, register);
+                let cell = addressing.locate(memory, register);
                 sta(memory, cell, register)
             },
             Mnemonics::STX(addressing) => {
-                let cell = addressing.read(memory, register);
+                let cell = addressing.locate(memory, register);
                 stx(memory, cell, register)
             },
             Mnemonics::STY(addressing) => {
-                let cell = addressing.read(memory, register);
+                let cell = addressing.locate(memory, register);
                 sty(memory, cell, register)
             },
             Mnemonics::TAX(_addressing) => { tax(register) },
@@ -195,7 +197,7 @@ fn and(cell: MemoryCell, register: &mut Register) -> u8 {
     return 2 + cell.cycles + if cell.in_bounds { 0 } else { 1 };
 }
 
-fn asl(memory: &mut [u8], cell: MemoryCell, register: &mut Register) -> u8 {
+fn asl<M: Memory + ?Sized>(memory: &mut M, cell: MemoryCell, register: &mut Register) -> u8 {
     let result = alu::shift_left(cell.value);
     let result_value = result.value;
     set_nzc_from_alu_result_bits(register, result);
@@ -205,8 +207,9 @@ fn asl(memory: &mut [u8], cell: MemoryCell, register: &mut Register) -> u8 {
         return 2;
     }
 
-    memory[cell.address] = result_value;
-    return 4 + cell.cycles
+    memory.write(cell.address, cell.value, Access::Dummy);
+    memory.write(cell.address, result_value, Access::Data);
+    return 4 + cell.cycles + if cell.indexed { 1 } else { 0 };
 }
 
 fn bcc(cell: MemoryCell, register: &mut Register) -> u8 {
@@ -259,7 +262,7 @@ fn bpl(cell: MemoryCell, register: &mut Register) -> u8 {
     return 2 + cell.cycles + if cell.in_bounds { 0 } else { 1 };
 }
 
-fn brk(memory: &mut [u8], register: &mut Register) -> u8 {
+fn brk<M: Memory + ?Sized>(memory: &mut M, register: &mut Register) -> u8 {
     register.increment_pc();
     stack_push(memory, register, (register.pc() >> 8) as u8);
     stack_push(memory, register, register.pc() as u8);
@@ -267,8 +270,8 @@ fn brk(memory: &mut [u8], register: &mut Register) -> u8 {
     register.set_break_bit(true);
     stack_push(memory, register, register.p());
 
-    let pc_low = memory[0xfffe];
-    let pc_high = memory[0xffff];
+    let pc_low = memory.read(0xfffe, Access::Data);
+    let pc_high = memory.read(0xffff, Access::Data);
 
     register.set_pc(((pc_high as u16) << 8) + pc_low as u16);
     register.set_interrupt_bit(true);
@@ -335,12 +338,13 @@ fn cpy(cell: MemoryCell, register: &mut Register) -> u8 {
     return 2 + cell.cycles + if cell.in_bounds { 0 } else { 1 };
 }
 
-fn dec(memory: &mut [u8], cell: MemoryCell, register: &mut Register) -> u8 {
+fn dec<M: Memory + ?Sized>(memory: &mut M, cell: MemoryCell, register: &mut Register) -> u8 {
     let result = alu::decrement(cell.value);
-    memory[cell.address] = result.value;
+    memory.write(cell.address, cell.value, Access::Dummy);
+    memory.write(cell.address, result.value, Access::Data);
     set_nz_from_alu_result_bits(register, result);
 
-    return 4 + cell.cycles;
+    return 4 + cell.cycles + if cell.indexed { 1 } else { 0 };
 }
 
 fn dex(register: &mut Register) -> u8 {
@@ -368,12 +372,13 @@ fn eor(cell: MemoryCell, register: &mut Register) -> u8 {
     return 2 + cell.cycles + if cell.in_bounds { 0 } else { 1 };
 }
 
-fn inc(memory: &mut [u8], cell: MemoryCell, register: &mut Register) -> u8 {
+fn inc<M: Memory + ?Sized>(memory: &mut M, cell: MemoryCell, register: &mut Register) -> u8 {
     let result = alu::increment(cell.value);
-    memory[cell.address] = result.value;
+    memory.write(cell.address, cell.value, Access::Dummy);
+    memory.write(cell.address, result.value, Access::Data);
     set_nz_from_alu_result_bits(register, result);
 
-    return 4 + cell.cycles;
+    return 4 + cell.cycles + if cell.indexed { 1 } else { 0 };
 }
 
 fn inx(register: &mut Register) -> u8 {
@@ -397,7 +402,7 @@ fn jmp(cell: MemoryCell, register: &mut Register) -> u8 {
     return 1 + cell.cycles;
 }
 
-fn jsr(memory: &mut [u8], cell: MemoryCell, register: &mut Register) -> u8 {
+fn jsr<M: Memory + ?Sized>(memory: &mut M, cell: MemoryCell, register: &mut Register) -> u8 {
     register.set_pc(register.pc() - 1);
     stack_push(memory, register, ((register.pc() & 0xff00) >> 8) as u8);
     stack_push(memory, register, register.pc() as u8);
@@ -427,7 +432,7 @@ fn ldy(cell: MemoryCell, register: &mut Register) -> u8 {
     return 2 + cell.cycles + if cell.in_bounds { 0 } else { 1 };
 }
 
-fn lsr(memory: &mut [u8], cell: MemoryCell, register: &mut Register) -> u8 {
+fn lsr<M: Memory + ?Sized>(memory: &mut M, cell: MemoryCell, register: &mut Register) -> u8 {
     let result = alu::shift_right(cell.value);
     let result_value = result.value;
     set_nzc_from_alu_result_bits(register, result);
@@ -437,8 +442,9 @@ fn lsr(memory: &mut [u8], cell: MemoryCell, register: &mut Register) -> u8 {
         return 2;
     }
 
-    memory[cell.address] = result_value;
-    return 4 + cell.cycles
+    memory.write(cell.address, cell.value, Access::Dummy);
+    memory.write(cell.address, result_value, Access::Data);
+    return 4 + cell.cycles + if cell.indexed { 1 } else { 0 };
 }
 
 fn nop() -> u8 {
@@ -454,33 +460,33 @@ fn ora(cell: MemoryCell, register: &mut Register) -> u8 {
     return 2 + cell.cycles + if cell.in_bounds { 0 } else { 1 };
 }
 
-fn pha(memory: &mut [u8], register: &mut Register) -> u8 {
+fn pha<M: Memory + ?Sized>(memory: &mut M, register: &mut Register) -> u8 {
     stack_push(memory, register, register.a);
 
     return 3;
 }
 
-fn php(memory: &mut [u8], register: &mut Register) -> u8 {
+fn php<M: Memory + ?Sized>(memory: &mut M, register: &mut Register) -> u8 {
     stack_push(memory, register, register.p() | 0x30);
 
     return 3;
 }
 
-fn pla(memory: &[u8], register: &mut Register) -> u8 {
+fn pla<M: Memory + ?Sized>(memory: &mut M, register: &mut Register) -> u8 {
     register.a = stack_pull(memory, register);
     set_nz_from_raw_result_bits(register, register.a);
 
     return 4;
 }
 
-fn plp(memory: &[u8], register: &mut Register) -> u8 {
+fn plp<M: Memory + ?Sized>(memory: &mut M, register: &mut Register) -> u8 {
     let status_register = stack_pull(memory, register);
     register.set_p(status_register);
 
     return 4;
 }
 
-fn rol(memory: &mut [u8], cell: MemoryCell, register: &mut Register) -> u8 {
+fn rol<M: Memory + ?Sized>(memory: &mut M, cell: MemoryCell, register: &mut Register) -> u8 {
     let previous_carry_bit = register.carry_bit();
     let result = alu::shift_left(cell.value);
     let result_value = if previous_carry_bit { result.value | 0x01 } else { result.value & 0xFE };
@@ -492,11 +498,12 @@ fn rol(memory: &mut [u8], cell: MemoryCell, register: &mut Register) -> u8 {
         return 2;
     }
 
-    memory[cell.address] = result_value;
-    return 4 + cell.cycles
+    memory.write(cell.address, cell.value, Access::Dummy);
+    memory.write(cell.address, result_value, Access::Data);
+    return 4 + cell.cycles + if cell.indexed { 1 } else { 0 };
 }
 
-fn ror(memory: &mut [u8], cell: MemoryCell, register: &mut Register) -> u8 {
+fn ror<M: Memory + ?Sized>(memory: &mut M, cell: MemoryCell, register: &mut Register) -> u8 {
     let previous_carry_bit = register.carry_bit();
     let result = alu::shift_right(cell.value);
     let result_value = if previous_carry_bit { result.value | 0x80 } else { result.value & 0x7f };
@@ -508,11 +515,12 @@ fn ror(memory: &mut [u8], cell: MemoryCell, register: &mut Register) -> u8 {
         return 2;
     }
 
-    memory[cell.address] = result_value;
-    return 4 + cell.cycles
+    memory.write(cell.address, cell.value, Access::Dummy);
+    memory.write(cell.address, result_value, Access::Data);
+    return 4 + cell.cycles + if cell.indexed { 1 } else { 0 };
 }
 
-fn rti(memory: &[u8], register: &mut Register) -> u8 {
+fn rti<M: Memory + ?Sized>(memory: &mut M, register: &mut Register) -> u8 {
     let status_register = stack_pull(memory, register);
     let pc_low = stack_pull(memory, register);
     let pc_high = stack_pull(memory, register);
@@ -524,7 +532,7 @@ fn rti(memory: &[u8], register: &mut Register) -> u8 {
     return 6;
 }
 
-fn rts(memory: &[u8], register: &mut Register) -> u8 {
+fn rts<M: Memory + ?Sized>(memory: &mut M, register: &mut Register) -> u8 {
     let pc_low = stack_pull(memory, register);
     let pc_high = stack_pull(memory, register);
 
@@ -560,20 +568,23 @@ fn sei(register: &mut Register) -> u8 {
     return 2;
 }
 
-fn sta(memory: &mut [u8], cell: MemoryCell, register: &mut Register) -> u8 {
-    memory[cell.address] = register.a;
+fn sta<M: Memory + ?Sized>(memory: &mut M, cell: MemoryCell, register: &mut Register) -> u8 {
+    if cell.indexed {
+        memory.read(cell.uncorrected_address(), Access::Dummy);
+    }
+    memory.write(cell.address, register.a, Access::Data);
 
-    return 2 + cell.cycles;
+    return 2 + cell.cycles + if cell.indexed { 1 } else { 0 };
 }
 
-fn stx(memory: &mut [u8], cell: MemoryCell, register: &mut Register) -> u8 {
-    memory[cell.address] = register.x;
+fn stx<M: Memory + ?Sized>(memory: &mut M, cell: MemoryCell, register: &mut Register) -> u8 {
+    memory.write(cell.address, register.x, Access::Data);
 
     return 2 + cell.cycles;
 }
 
-fn sty(memory: &mut [u8], cell: MemoryCell, register: &mut Register) -> u8 {
-    memory[cell.address] = register.y;
+fn sty<M: Memory + ?Sized>(memory: &mut M, cell: MemoryCell, register: &mut Register) -> u8 {
+    memory.write(cell.address, register.y, Access::Data);
 
     return 2 + cell.cycles;
 }
@@ -646,11 +657,7 @@ fn set_nz_from_raw_result_bits(register: &mut Register, result: u8) {
 
 #[cfg(test)]
 mod tests {
-    use crate::cpu::alu;
-    use crate::cpu::addressing::Addressing;
     use crate::cpu::addressing::MemoryCell;
-    use crate::cpu::addressing::stack_push;
-    use crate::cpu::addressing::stack_pull;
     use crate::cpu::register::Register;
 
     fn cell(value: u8, in_bounds: bool, cycles: u8) -> MemoryCell {
@@ -658,6 +665,7 @@ mod tests {
             address: 0x02,
             value: value,
             in_bounds: in_bounds,
+            indexed: false,
             cycles: cycles,
             bytes: 2
         }
@@ -1307,7 +1315,6 @@ mod tests {
         let mut register = Register::new();
         register.set_pc(0x600);
 
-        let mut memory: [u8; 600] = [0x00; 600];
         let cell_in_bounds = cell(0xF2, true, 5);
         let cell_out_of_bounds = cell(0x00, false, 5);
 
@@ -1329,7 +1336,6 @@ mod tests {
         let mut register = Register::new();
         register.set_pc(0x600);
 
-        let mut memory: [u8; 600] = [0x00; 600];
         let cell_in_bounds = cell(0xF2, true, 5);
         let cell_out_of_bounds = cell(0x00, false, 5);
 
@@ -1351,7 +1357,6 @@ mod tests {
         let mut register = Register::new();
         register.set_pc(0x600);
 
-        let mut memory: [u8; 600] = [0x00; 600];
         let cell_in_bounds = cell(0xF2, true, 5);
         let cell_out_of_bounds = cell(0x00, false, 5);
 
@@ -1457,7 +1462,7 @@ mod tests {
         let mut register = Register::new();
         register.push_s();
 
-        let cycles = pla(&memory, &mut register);
+        let cycles = pla(&mut memory, &mut register);
         assert_eq!(register.s(), 0xFF);
         assert_eq!(register.a, 0x82);
         assert_eq!(register.p(), 0b1010_0000);
@@ -1473,7 +1478,7 @@ mod tests {
         let mut register = Register::new();
         register.push_s();
 
-        let cycles = plp(&memory, &mut register);
+        let cycles = plp(&mut memory, &mut register);
         assert_eq!(register.s(), 0xFF);
         assert_eq!(register.p(), 0b1010_0010);
         assert_eq!(cycles, 4);
@@ -1,7 +1,62 @@
 mod addressing;
 mod alu;
+mod bus;
+#[cfg(feature = "std")]
+mod call;
+#[cfg(feature = "std")]
+mod diagnostics;
+mod hooks;
+mod memory;
 mod mnemonics;
+mod opcodes;
 mod register;
+mod run;
+#[cfg(feature = "std")]
+mod smc;
+#[cfg(feature = "std")]
+mod trap;
+
+pub use addressing::Addressing;
+#[cfg(feature = "std")]
+pub use call::CallError;
+#[cfg(feature = "std")]
+pub use call::CallResult;
+#[cfg(feature = "std")]
+pub use call::MemoryChange;
+#[cfg(feature = "std")]
+pub use call::Regs;
+#[cfg(feature = "std")]
+pub use diagnostics::Checks;
+#[cfg(feature = "std")]
+pub use diagnostics::Diagnostic;
+pub use hooks::Interrupt;
+pub use memory::Access;
+pub use memory::Memory;
+pub use mnemonics::Mnemonics;
+pub use opcodes::AccessClass;
+pub use opcodes::OpcodeInfo;
+pub use opcodes::OPCODE_INFO;
+pub use register::Register;
+pub use register::CARRY_MASK;
+pub use register::DECIMAL_MASK;
+pub use register::INTERRUPT_MASK;
+pub use register::NEGATIVE_MASK;
+pub use register::OVERFLOW_MASK;
+pub use register::ZERO_MASK;
+pub use run::Condition;
+pub use run::StopReason;
+#[cfg(feature = "std")]
+pub use smc::SelfModifyingCode;
+#[cfg(feature = "std")]
+pub use smc::SmcKind;
+#[cfg(feature = "std")]
+pub use trap::Trap;
+
+use addressing::stack_push;
+#[cfg(feature = "std")]
+use crate::analysis::decode;
+#[cfg(feature = "std")]
+use crate::symbols::Symbols;
 
 use addressing::Addressing::Implied;
 use addressing::Addressing::Accumulator;
@@ -76,7 +131,8 @@ use mnemonics::Mnemonics::TXS;
 use mnemonics::Mnemonics::TYA;
 
 
-const OPCODES: [mnemonics::Mnemonics; 256] = [
+// Instructions by opcode, NUL for the undocumented ones.
+pub const OPCODES: [mnemonics::Mnemonics; 256] = [
     BRK(Implied),   ORA(IndirectX), NUL,            NUL, NUL,            ORA(ZeroPage),  ASL(ZeroPage),  NUL, PHP(Implied), ORA(Immediate), ASL(Accumulator), NUL, NUL,            ORA(Absolute),  ASL(Absolute),  NUL,
     BPL(Relative),  ORA(IndirectY), NUL,            NUL, NUL,            ORA(ZeroPageX), ASL(ZeroPageX), NUL, CLC(Implied), ORA(AbsoluteY), NUL,              NUL, NUL,            ORA(AbsoluteX), ASL(AbsoluteX), NUL,
     JSR(Absolute),  AND(IndirectX), NUL,            NUL, BIT(ZeroPage),  AND(ZeroPage),  ROL(ZeroPage),  NUL, PLP(Implied), AND(Immediate), ROL(Accumulator), NUL, BIT(Absolute),  AND(Absolute),  ROL(Absolute),  NUL,
@@ -96,32 +152,154 @@ const OPCODES: [mnemonics::Mnemonics; 256] = [
 ];
 
 pub struct Cpu<'a> {
-    memory: &'a mut [u8],
+    bus: bus::Bus<'a>,
     register: register::Register,
     opcodes: [mnemonics::Mnemonics; 256],
+    #[cfg(feature = "std")]
+    traps: trap::Traps<'a>,
     pub cycles: usize,
-    debug: bool
+    #[cfg(feature = "std")]
+    debug: bool,
+    #[cfg(feature = "std")]
+    debug_symbols: Symbols
 }
 
 impl<'a> Cpu<'a> {
-    pub fn new(memory: &mut [u8]) -> Cpu {
+    pub fn new(memory: &mut [u8]) -> Cpu<'_> {
         Cpu {
-            memory: memory,
+            bus: bus::Bus::new(memory),
             register: register::Register::new(),
             opcodes: OPCODES,
+            #[cfg(feature = "std")]
+            traps: trap::Traps::default(),
             cycles: 0,
-            debug: false
+            #[cfg(feature = "std")]
+            debug: false,
+            #[cfg(feature = "std")]
+            debug_symbols: Symbols::new()
         }
     }
 
+    #[cfg(feature = "std")]
     pub fn debug(&mut self) {
         self.debug = true;
     }
 
-    pub fn cold_reset(&mut self) {
-        let pc_high = self.memory[0xfffd];
-        let pc_low = self.memory[0xfffc];
+    // Debug output with names for the addresses, in the instructions and as their labels.
+    #[cfg(feature = "std")]
+    pub fn debug_with_symbols(&mut self, symbols: Symbols) {
+        self.debug = true;
+        self.debug_symbols = symbols;
+    }
+
+    // The instruction at the address as the debug output lists it, like the monitor does.
+    #[cfg(feature = "std")]
+    fn debug_instruction(&self, pc: u16) -> String {
+        let label = match self.debug_symbols.name(pc) {
+            Some(name) => format!("{}:", name),
+            None => String::new()
+        };
+        let instruction = match decode(self.bus.memory(), pc) {
+            Some(instruction) => instruction.format(&self.debug_symbols),
+            None => format!(".byte ${:02X}", self.bus.memory()[pc as usize])
+        };
+
+        return format!("${:04X} {:<10}{}", pc, label, instruction);
+    }
+
+    pub fn register(&self) -> &Register {
+        &self.register
+    }
+
+    pub fn register_mut(&mut self) -> &mut Register {
+        &mut self.register
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        self.bus.memory()
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        self.bus.memory_mut()
+    }
+
+    // Runs the handler instead of the code at given address, whenever the execution reaches it.
+    // The handler gets mutable access to registers and memory, and decides if the instruction
+    // at PC is executed afterwards, or if an RTS is simulated.
+    #[cfg(feature = "std")]
+    pub fn trap<F: FnMut(&mut Register, &mut [u8]) -> Trap + 'a>(&mut self, address: u16, handler: F) {
+        self.traps.insert(address, Box::new(handler));
+    }
+
+    #[cfg(feature = "std")]
+    pub fn remove_trap(&mut self, address: u16) {
+        self.traps.remove(address);
+    }
+
+    // Reports writes into bytes which were already executed as opcodes or operands,
+    // and execution of bytes which changed since they were executed last time.
+    #[cfg(feature = "std")]
+    pub fn detect_self_modifying_code<F: FnMut(SelfModifyingCode) + 'a>(&mut self, callback: F) {
+        let tracker = smc::Tracker::new(self.bus.len(), callback);
+        self.bus.set_smc_tracker(tracker);
+    }
+
+    // Checked mode - reports reads of never written memory, execution of never written
+    // memory, stack pointer wraps and accesses outside of the configured regions.
+    #[cfg(feature = "std")]
+    pub fn check<F: FnMut(Diagnostic) + 'a>(&mut self, checks: Checks, callback: F) {
+        let checker = diagnostics::Checker::new(self.bus.len(), checks, callback);
+        self.bus.set_checker(checker);
+    }
+
+    // Keeps the byte at the value while the CPU runs, the way a cheat pokes it over and
+    // over - writes to it are undone right away.
+    #[cfg(feature = "std")]
+    pub fn freeze(&mut self, address: u16, value: u8) {
+        self.bus.memory_mut()[address as usize] = value;
+        self.bus.frozen.insert(address, value);
+    }
+
+    #[cfg(feature = "std")]
+    pub fn unfreeze(&mut self, address: u16) {
+        self.bus.frozen.remove(&address);
+    }
+
+    #[cfg(feature = "std")]
+    pub fn frozen(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.bus.frozen.iter().map(|(&address, &value)| (address, value))
+    }
+
+    #[cfg(feature = "std")]
+    pub fn on_before_instruction<F: FnMut(&Register) + 'a>(&mut self, hook: F) {
+        self.bus.hooks.before_instruction.push(Box::new(hook));
+    }
+
+    // Called with the registers after the instruction and the cycles it took.
+    #[cfg(feature = "std")]
+    pub fn on_after_instruction<F: FnMut(&Register, usize) + 'a>(&mut self, hook: F) {
+        self.bus.hooks.after_instruction.push(Box::new(hook));
+    }
+
+    // Called with the address, the value and the kind of every memory read.
+    #[cfg(feature = "std")]
+    pub fn on_read<F: FnMut(u16, u8, Access) + 'a>(&mut self, hook: F) {
+        self.bus.hooks.read.push(Box::new(hook));
+    }
 
+    // Called with the address, the value and the kind of every memory write.
+    #[cfg(feature = "std")]
+    pub fn on_write<F: FnMut(u16, u8, Access) + 'a>(&mut self, hook: F) {
+        self.bus.hooks.write.push(Box::new(hook));
+    }
+
+    // Called with the registers after the CPU entered the interrupt.
+    #[cfg(feature = "std")]
+    pub fn on_interrupt<F: FnMut(Interrupt, &Register) + 'a>(&mut self, hook: F) {
+        self.bus.hooks.interrupt.push(Box::new(hook));
+    }
+
+    pub fn cold_reset(&mut self) {
         let pc_high = 0x04;
         let pc_low = 0x00;
         self.register.a = 0x00;
@@ -129,35 +307,124 @@ impl<'a> Cpu<'a> {
         self.register.y = 0x00;
         self.register.set_p(0b0010_0100); // Interrupt flag
         self.register.set_pc(((pc_high as u16) << 8) + pc_low as u16);
+        #[cfg(feature = "std")]
+        self.bus.hooks.interrupt(Interrupt::Reset, &self.register);
     }
 
     pub fn warm_reset(&mut self) {
-        let pc_high = self.memory[0xfffd];
-        let pc_low = self.memory[0xfffc];
+        let pc_high = self.bus.read(0xfffd, Access::Data);
+        let pc_low = self.bus.read(0xfffc, Access::Data);
 
         self.register.set_interrupt_bit(true);
         self.register.set_pc(((pc_high as u16) << 8) + pc_low as u16);
+        #[cfg(feature = "std")]
+        self.bus.hooks.interrupt(Interrupt::Reset, &self.register);
+    }
+
+    // Maskable interrupt - ignored when the I flag is set. Returns true if it was serviced.
+    pub fn irq(&mut self) -> bool {
+        if self.register.interrupt_bit() {
+            return false;
+        }
+
+        self.interrupt(Interrupt::Irq, 0xfffe);
+        return true;
+    }
+
+    pub fn nmi(&mut self) {
+        self.interrupt(Interrupt::Nmi, 0xfffa);
     }
 
     pub fn step(&mut self) -> bool {
+        self.execute();
+
+        return true;
+    }
+
+    // Runs one instruction, returns its address and opcode - none when a trap returned in
+    // place of the instruction.
+    fn execute(&mut self) -> Option<(u16, u8)> {
+        #[cfg(feature = "std")]
+        if let Some(trap::Trap::Return) = self.traps.run(&mut self.register, self.bus.memory_mut()) {
+            self.cycles += RTS(Implied).handle(&mut self.register, &mut self.bus) as usize;
+
+            return None;
+        }
+
+        #[cfg(feature = "std")]
+        self.bus.hooks.before_instruction(&self.register);
+
         let pc_start = self.register.pc();
+        // decoded before it runs, the instruction may modify itself
+        #[cfg(feature = "std")]
+        let instruction = if self.debug { Some(self.debug_instruction(pc_start)) } else { None };
         let opcode = self.read_byte();
 
-        let cycles = self.opcodes[opcode as usize].handle(&mut self.register, &mut self.memory) as usize;
+        let cycles = self.opcodes[opcode as usize].handle(&mut self.register, &mut self.bus) as usize;
 
-        if self.debug {
-            println!("${:x}: {:?}({:x}), A: 0x{:x}, X: 0x{:x}, Y: 0x{:x}, S: 0x01{:x}, top: 0x{:x} P: {:b}, cyc: {}", pc_start, self.opcodes[opcode as usize], opcode, self.register.a, self.register.x, self.register.y, self.register.s(), self.memory[(self.register.s().overflowing_add(1).0) as usize + 0x100 as usize], self.register.p(), cycles);
+        #[cfg(feature = "std")]
+        if let Some(instruction) = instruction {
+            println!("{:<32}A: 0x{:x}, X: 0x{:x}, Y: 0x{:x}, S: 0x01{:x}, top: 0x{:x} P: {:b}, cyc: {}", instruction, self.register.a, self.register.x, self.register.y, self.register.s(), self.bus.peek((self.register.s().overflowing_add(1).0) as usize + 0x100), self.register.p(), cycles);
         }
 
         self.cycles += cycles;
+        #[cfg(feature = "std")]
+        self.bus.hooks.after_instruction(&self.register, cycles);
 
-        return true;
+        #[cfg(feature = "std")]
+        if opcode == 0x00 {
+            self.bus.hooks.interrupt(Interrupt::Brk, &self.register);
+        }
+
+        return Some((pc_start, opcode));
+    }
+
+    #[cfg_attr(not(feature = "std"), allow(unused_variables))]
+    fn interrupt(&mut self, interrupt: Interrupt, vector: usize) {
+        let pc = self.register.pc();
+        stack_push(&mut self.bus, &mut self.register, (pc >> 8) as u8);
+        stack_push(&mut self.bus, &mut self.register, pc as u8);
+        let p = (self.register.p() & !0x10) | 0x20;
+        stack_push(&mut self.bus, &mut self.register, p);
+
+        let pc_low = self.bus.read(vector, Access::Data);
+        let pc_high = self.bus.read(vector + 1, Access::Data);
+
+        self.register.set_interrupt_bit(true);
+        self.register.set_pc(((pc_high as u16) << 8) + pc_low as u16);
+        self.cycles += 7;
+        #[cfg(feature = "std")]
+        self.bus.hooks.interrupt(interrupt, &self.register);
     }
 
     fn read_byte(&mut self) -> u8 {
-        let byte = self.memory[self.register.pc() as usize];
+        let byte = self.bus.read(self.register.pc() as usize, Access::Opcode);
         self.register.increment_pc();
 
         return byte;
     }
 }
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::Cpu;
+    use crate::symbols::Symbols;
+
+    #[test]
+    fn test_debug_with_symbols() {
+        // $0400: LDA #$42
+        // $0402: STA $D40A
+        // $0405: .byte $02
+        let mut memory = [0; 65536];
+        memory[0x0400..0x0406].copy_from_slice(&[0xa9, 0x42, 0x8d, 0x0a, 0xd4, 0x02]);
+        let mut symbols = Symbols::new();
+        symbols.parse("al C:0400 .start\nal C:D40A .WSYNC\n").unwrap();
+
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.debug_with_symbols(symbols);
+
+        assert_eq!(cpu.debug_instruction(0x0400), "$0400 start:    LDA #$42");
+        assert_eq!(cpu.debug_instruction(0x0402), "$0402           STA WSYNC");
+        assert_eq!(cpu.debug_instruction(0x0405), "$0405           .byte $02");
+    }
+}
@@ -0,0 +1,385 @@
+// Static description of every opcode, for tools which need to know about an instruction
+// without executing it (disassemblers, assemblers, cycle counters). Undocumented opcodes
+// are not emulated, so they have no entry.
+
+use super::addressing::Addressing;
+use super::addressing::Addressing::*;
+use super::register::CARRY_MASK;
+use super::register::DECIMAL_MASK;
+use super::register::INTERRUPT_MASK;
+use super::register::NEGATIVE_MASK;
+use super::register::OVERFLOW_MASK;
+use super::register::ZERO_MASK;
+
+const N: u8 = NEGATIVE_MASK;
+const V: u8 = OVERFLOW_MASK;
+const D: u8 = DECIMAL_MASK;
+const I: u8 = INTERRUPT_MASK;
+const Z: u8 = ZERO_MASK;
+const C: u8 = CARRY_MASK;
+
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AccessClass {
+    Internal,        // registers only
+    Read,
+    Write,
+    ReadModifyWrite,
+    Control          // changes the program flow
+}
+
+use AccessClass::*;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OpcodeInfo {
+    pub name: &'static str,
+    pub mode: Addressing,
+    pub length: u8,
+    pub cycles: u8,         // without any penalties
+    pub page_penalty: u8,   // when indexing (or a taken branch) crosses a page
+    pub branch_penalty: u8, // when the branch is taken
+    pub flags_read: u8,     // P register bits
+    pub flags_written: u8,
+    pub class: AccessClass
+}
+
+#[allow(clippy::too_many_arguments)]
+const fn op(name: &'static str, mode: Addressing, cycles: u8, page_penalty: u8, branch_penalty: u8, flags_read: u8, flags_written: u8, class: AccessClass) -> Option<OpcodeInfo> {
+    Some(OpcodeInfo {
+        name: name,
+        mode: mode,
+        length: mode.length(),
+        cycles: cycles,
+        page_penalty: page_penalty,
+        branch_penalty: branch_penalty,
+        flags_read: flags_read,
+        flags_written: flags_written,
+        class: class
+    })
+}
+
+pub const OPCODE_INFO: [Option<OpcodeInfo>; 256] = [
+    op("BRK", Implied, 7, 0, 0, N | V | D | I | Z | C, I, Control), // $00
+    op("ORA", IndirectX, 6, 0, 0, 0, N | Z, Read), // $01
+    None, // $02
+    None, // $03
+    None, // $04
+    op("ORA", ZeroPage, 3, 0, 0, 0, N | Z, Read), // $05
+    op("ASL", ZeroPage, 5, 0, 0, 0, N | Z | C, ReadModifyWrite), // $06
+    None, // $07
+    op("PHP", Implied, 3, 0, 0, N | V | D | I | Z | C, 0, Write), // $08
+    op("ORA", Immediate, 2, 0, 0, 0, N | Z, Read), // $09
+    op("ASL", Accumulator, 2, 0, 0, 0, N | Z | C, Internal), // $0a
+    None, // $0b
+    None, // $0c
+    op("ORA", Absolute, 4, 0, 0, 0, N | Z, Read), // $0d
+    op("ASL", Absolute, 6, 0, 0, 0, N | Z | C, ReadModifyWrite), // $0e
+    None, // $0f
+    op("BPL", Relative, 2, 1, 1, N, 0, Control), // $10
+    op("ORA", IndirectY, 5, 1, 0, 0, N | Z, Read), // $11
+    None, // $12
+    None, // $13
+    None, // $14
+    op("ORA", ZeroPageX, 4, 0, 0, 0, N | Z, Read), // $15
+    op("ASL", ZeroPageX, 6, 0, 0, 0, N | Z | C, ReadModifyWrite), // $16
+    None, // $17
+    op("CLC", Implied, 2, 0, 0, 0, C, Internal), // $18
+    op("ORA", AbsoluteY, 4, 1, 0, 0, N | Z, Read), // $19
+    None, // $1a
+    None, // $1b
+    None, // $1c
+    op("ORA", AbsoluteX, 4, 1, 0, 0, N | Z, Read), // $1d
+    op("ASL", AbsoluteX, 7, 0, 0, 0, N | Z | C, ReadModifyWrite), // $1e
+    None, // $1f
+    op("JSR", Absolute, 6, 0, 0, 0, 0, Control), // $20
+    op("AND", IndirectX, 6, 0, 0, 0, N | Z, Read), // $21
+    None, // $22
+    None, // $23
+    op("BIT", ZeroPage, 3, 0, 0, 0, N | V | Z, Read), // $24
+    op("AND", ZeroPage, 3, 0, 0, 0, N | Z, Read), // $25
+    op("ROL", ZeroPage, 5, 0, 0, C, N | Z | C, ReadModifyWrite), // $26
+    None, // $27
+    op("PLP", Implied, 4, 0, 0, 0, N | V | D | I | Z | C, Read), // $28
+    op("AND", Immediate, 2, 0, 0, 0, N | Z, Read), // $29
+    op("ROL", Accumulator, 2, 0, 0, C, N | Z | C, Internal), // $2a
+    None, // $2b
+    op("BIT", Absolute, 4, 0, 0, 0, N | V | Z, Read), // $2c
+    op("AND", Absolute, 4, 0, 0, 0, N | Z, Read), // $2d
+    op("ROL", Absolute, 6, 0, 0, C, N | Z | C, ReadModifyWrite), // $2e
+    None, // $2f
+    op("BMI", Relative, 2, 1, 1, N, 0, Control), // $30
+    op("AND", IndirectY, 5, 1, 0, 0, N | Z, Read), // $31
+    None, // $32
+    None, // $33
+    None, // $34
+    op("AND", ZeroPageX, 4, 0, 0, 0, N | Z, Read), // $35
+    op("ROL", ZeroPageX, 6, 0, 0, C, N | Z | C, ReadModifyWrite), // $36
+    None, // $37
+    op("SEC", Implied, 2, 0, 0, 0, C, Internal), // $38
+    op("AND", AbsoluteY, 4, 1, 0, 0, N | Z, Read), // $39
+    None, // $3a
+    None, // $3b
+    None, // $3c
+    op("AND", AbsoluteX, 4, 1, 0, 0, N | Z, Read), // $3d
+    op("ROL", AbsoluteX, 7, 0, 0, C, N | Z | C, ReadModifyWrite), // $3e
+    None, // $3f
+    op("RTI", Implied, 6, 0, 0, 0, N | V | D | I | Z | C, Control), // $40
+    op("EOR", IndirectX, 6, 0, 0, 0, N | Z, Read), // $41
+    None, // $42
+    None, // $43
+    None, // $44
+    op("EOR", ZeroPage, 3, 0, 0, 0, N | Z, Read), // $45
+    op("LSR", ZeroPage, 5, 0, 0, 0, N | Z | C, ReadModifyWrite), // $46
+    None, // $47
+    op("PHA", Implied, 3, 0, 0, 0, 0, Write), // $48
+    op("EOR", Immediate, 2, 0, 0, 0, N | Z, Read), // $49
+    op("LSR", Accumulator, 2, 0, 0, 0, N | Z | C, Internal), // $4a
+    None, // $4b
+    op("JMP", Absolute, 3, 0, 0, 0, 0, Control), // $4c
+    op("EOR", Absolute, 4, 0, 0, 0, N | Z, Read), // $4d
+    op("LSR", Absolute, 6, 0, 0, 0, N | Z | C, ReadModifyWrite), // $4e
+    None, // $4f
+    op("BVC", Relative, 2, 1, 1, V, 0, Control), // $50
+    op("EOR", IndirectY, 5, 1, 0, 0, N | Z, Read), // $51
+    None, // $52
+    None, // $53
+    None, // $54
+    op("EOR", ZeroPageX, 4, 0, 0, 0, N | Z, Read), // $55
+    op("LSR", ZeroPageX, 6, 0, 0, 0, N | Z | C, ReadModifyWrite), // $56
+    None, // $57
+    op("CLI", Implied, 2, 0, 0, 0, I, Internal), // $58
+    op("EOR", AbsoluteY, 4, 1, 0, 0, N | Z, Read), // $59
+    None, // $5a
+    None, // $5b
+    None, // $5c
+    op("EOR", AbsoluteX, 4, 1, 0, 0, N | Z, Read), // $5d
+    op("LSR", AbsoluteX, 7, 0, 0, 0, N | Z | C, ReadModifyWrite), // $5e
+    None, // $5f
+    op("RTS", Implied, 6, 0, 0, 0, 0, Control), // $60
+    op("ADC", IndirectX, 6, 0, 0, C | D, N | V | Z | C, Read), // $61
+    None, // $62
+    None, // $63
+    None, // $64
+    op("ADC", ZeroPage, 3, 0, 0, C | D, N | V | Z | C, Read), // $65
+    op("ROR", ZeroPage, 5, 0, 0, C, N | Z | C, ReadModifyWrite), // $66
+    None, // $67
+    op("PLA", Implied, 4, 0, 0, 0, N | Z, Read), // $68
+    op("ADC", Immediate, 2, 0, 0, C | D, N | V | Z | C, Read), // $69
+    op("ROR", Accumulator, 2, 0, 0, C, N | Z | C, Internal), // $6a
+    None, // $6b
+    op("JMP", Indirect, 5, 0, 0, 0, 0, Control), // $6c
+    op("ADC", Absolute, 4, 0, 0, C | D, N | V | Z | C, Read), // $6d
+    op("ROR", Absolute, 6, 0, 0, C, N | Z | C, ReadModifyWrite), // $6e
+    None, // $6f
+    op("BVS", Relative, 2, 1, 1, V, 0, Control), // $70
+    op("ADC", IndirectY, 5, 1, 0, C | D, N | V | Z | C, Read), // $71
+    None, // $72
+    None, // $73
+    None, // $74
+    op("ADC", ZeroPageX, 4, 0, 0, C | D, N | V | Z | C, Read), // $75
+    op("ROR", ZeroPageX, 6, 0, 0, C, N | Z | C, ReadModifyWrite), // $76
+    None, // $77
+    op("SEI", Implied, 2, 0, 0, 0, I, Internal), // $78
+    op("ADC", AbsoluteY, 4, 1, 0, C | D, N | V | Z | C, Read), // $79
+    None, // $7a
+    None, // $7b
+    None, // $7c
+    op("ADC", AbsoluteX, 4, 1, 0, C | D, N | V | Z | C, Read), // $7d
+    op("ROR", AbsoluteX, 7, 0, 0, C, N | Z | C, ReadModifyWrite), // $7e
+    None, // $7f
+    None, // $80
+    op("STA", IndirectX, 6, 0, 0, 0, 0, Write), // $81
+    None, // $82
+    None, // $83
+    op("STY", ZeroPage, 3, 0, 0, 0, 0, Write), // $84
+    op("STA", ZeroPage, 3, 0, 0, 0, 0, Write), // $85
+    op("STX", ZeroPage, 3, 0, 0, 0, 0, Write), // $86
+    None, // $87
+    op("DEY", Implied, 2, 0, 0, 0, N | Z, Internal), // $88
+    None, // $89
+    op("TXA", Implied, 2, 0, 0, 0, N | Z, Internal), // $8a
+    None, // $8b
+    op("STY", Absolute, 4, 0, 0, 0, 0, Write), // $8c
+    op("STA", Absolute, 4, 0, 0, 0, 0, Write), // $8d
+    op("STX", Absolute, 4, 0, 0, 0, 0, Write), // $8e
+    None, // $8f
+    op("BCC", Relative, 2, 1, 1, C, 0, Control), // $90
+    op("STA", IndirectY, 6, 0, 0, 0, 0, Write), // $91
+    None, // $92
+    None, // $93
+    op("STY", ZeroPageX, 4, 0, 0, 0, 0, Write), // $94
+    op("STA", ZeroPageX, 4, 0, 0, 0, 0, Write), // $95
+    op("STX", ZeroPageY, 4, 0, 0, 0, 0, Write), // $96
+    None, // $97
+    op("TYA", Implied, 2, 0, 0, 0, N | Z, Internal), // $98
+    op("STA", AbsoluteY, 5, 0, 0, 0, 0, Write), // $99
+    op("TXS", Implied, 2, 0, 0, 0, 0, Internal), // $9a
+    None, // $9b
+    None, // $9c
+    op("STA", AbsoluteX, 5, 0, 0, 0, 0, Write), // $9d
+    None, // $9e
+    None, // $9f
+    op("LDY", Immediate, 2, 0, 0, 0, N | Z, Read), // $a0
+    op("LDA", IndirectX, 6, 0, 0, 0, N | Z, Read), // $a1
+    op("LDX", Immediate, 2, 0, 0, 0, N | Z, Read), // $a2
+    None, // $a3
+    op("LDY", ZeroPage, 3, 0, 0, 0, N | Z, Read), // $a4
+    op("LDA", ZeroPage, 3, 0, 0, 0, N | Z, Read), // $a5
+    op("LDX", ZeroPage, 3, 0, 0, 0, N | Z, Read), // $a6
+    None, // $a7
+    op("TAY", Implied, 2, 0, 0, 0, N | Z, Internal), // $a8
+    op("LDA", Immediate, 2, 0, 0, 0, N | Z, Read), // $a9
+    op("TAX", Implied, 2, 0, 0, 0, N | Z, Internal), // $aa
+    None, // $ab
+    op("LDY", Absolute, 4, 0, 0, 0, N | Z, Read), // $ac
+    op("LDA", Absolute, 4, 0, 0, 0, N | Z, Read), // $ad
+    op("LDX", Absolute, 4, 0, 0, 0, N | Z, Read), // $ae
+    None, // $af
+    op("BCS", Relative, 2, 1, 1, C, 0, Control), // $b0
+    op("LDA", IndirectY, 5, 1, 0, 0, N | Z, Read), // $b1
+    None, // $b2
+    None, // $b3
+    op("LDY", ZeroPageX, 4, 0, 0, 0, N | Z, Read), // $b4
+    op("LDA", ZeroPageX, 4, 0, 0, 0, N | Z, Read), // $b5
+    op("LDX", ZeroPageY, 4, 0, 0, 0, N | Z, Read), // $b6
+    None, // $b7
+    op("CLV", Implied, 2, 0, 0, 0, V, Internal), // $b8
+    op("LDA", AbsoluteY, 4, 1, 0, 0, N | Z, Read), // $b9
+    op("TSX", Implied, 2, 0, 0, 0, N | Z, Internal), // $ba
+    None, // $bb
+    op("LDY", AbsoluteX, 4, 1, 0, 0, N | Z, Read), // $bc
+    op("LDA", AbsoluteX, 4, 1, 0, 0, N | Z, Read), // $bd
+    op("LDX", AbsoluteY, 4, 1, 0, 0, N | Z, Read), // $be
+    None, // $bf
+    op("CPY", Immediate, 2, 0, 0, 0, N | Z | C, Read), // $c0
+    op("CMP", IndirectX, 6, 0, 0, 0, N | Z | C, Read), // $c1
+    None, // $c2
+    None, // $c3
+    op("CPY", ZeroPage, 3, 0, 0, 0, N | Z | C, Read), // $c4
+    op("CMP", ZeroPage, 3, 0, 0, 0, N | Z | C, Read), // $c5
+    op("DEC", ZeroPage, 5, 0, 0, 0, N | Z, ReadModifyWrite), // $c6
+    None, // $c7
+    op("INY", Implied, 2, 0, 0, 0, N | Z, Internal), // $c8
+    op("CMP", Immediate, 2, 0, 0, 0, N | Z | C, Read), // $c9
+    op("DEX", Implied, 2, 0, 0, 0, N | Z, Internal), // $ca
+    None, // $cb
+    op("CPY", Absolute, 4, 0, 0, 0, N | Z | C, Read), // $cc
+    op("CMP", Absolute, 4, 0, 0, 0, N | Z | C, Read), // $cd
+    op("DEC", Absolute, 6, 0, 0, 0, N | Z, ReadModifyWrite), // $ce
+    None, // $cf
+    op("BNE", Relative, 2, 1, 1, Z, 0, Control), // $d0
+    op("CMP", IndirectY, 5, 1, 0, 0, N | Z | C, Read), // $d1
+    None, // $d2
+    None, // $d3
+    None, // $d4
+    op("CMP", ZeroPageX, 4, 0, 0, 0, N | Z | C, Read), // $d5
+    op("DEC", ZeroPageX, 6, 0, 0, 0, N | Z, ReadModifyWrite), // $d6
+    None, // $d7
+    op("CLD", Implied, 2, 0, 0, 0, D, Internal), // $d8
+    op("CMP", AbsoluteY, 4, 1, 0, 0, N | Z | C, Read), // $d9
+    None, // $da
+    None, // $db
+    None, // $dc
+    op("CMP", AbsoluteX, 4, 1, 0, 0, N | Z | C, Read), // $dd
+    op("DEC", AbsoluteX, 7, 0, 0, 0, N | Z, ReadModifyWrite), // $de
+    None, // $df
+    op("CPX", Immediate, 2, 0, 0, 0, N | Z | C, Read), // $e0
+    op("SBC", IndirectX, 6, 0, 0, C | D, N | V | Z | C, Read), // $e1
+    None, // $e2
+    None, // $e3
+    op("CPX", ZeroPage, 3, 0, 0, 0, N | Z | C, Read), // $e4
+    op("SBC", ZeroPage, 3, 0, 0, C | D, N | V | Z | C, Read), // $e5
+    op("INC", ZeroPage, 5, 0, 0, 0, N | Z, ReadModifyWrite), // $e6
+    None, // $e7
+    op("INX", Implied, 2, 0, 0, 0, N | Z, Internal), // $e8
+    op("SBC", Immediate, 2, 0, 0, C | D, N | V | Z | C, Read), // $e9
+    op("NOP", Implied, 2, 0, 0, 0, 0, Internal), // $ea
+    None, // $eb
+    op("CPX", Absolute, 4, 0, 0, 0, N | Z | C, Read), // $ec
+    op("SBC", Absolute, 4, 0, 0, C | D, N | V | Z | C, Read), // $ed
+    op("INC", Absolute, 6, 0, 0, 0, N | Z, ReadModifyWrite), // $ee
+    None, // $ef
+    op("BEQ", Relative, 2, 1, 1, Z, 0, Control), // $f0
+    op("SBC", IndirectY, 5, 1, 0, C | D, N | V | Z | C, Read), // $f1
+    None, // $f2
+    None, // $f3
+    None, // $f4
+    op("SBC", ZeroPageX, 4, 0, 0, C | D, N | V | Z | C, Read), // $f5
+    op("INC", ZeroPageX, 6, 0, 0, 0, N | Z, ReadModifyWrite), // $f6
+    None, // $f7
+    op("SED", Implied, 2, 0, 0, 0, D, Internal), // $f8
+    op("SBC", AbsoluteY, 4, 1, 0, C | D, N | V | Z | C, Read), // $f9
+    None, // $fa
+    None, // $fb
+    None, // $fc
+    op("SBC", AbsoluteX, 4, 1, 0, C | D, N | V | Z | C, Read), // $fd
+    op("INC", AbsoluteX, 7, 0, 0, 0, N | Z, ReadModifyWrite), // $fe
+    None, // $ff
+
+];
+
+#[cfg(test)]
+mod tests {
+    use super::AccessClass;
+    use super::OPCODE_INFO;
+    use crate::cpu::Addressing;
+    use crate::cpu::Cpu;
+    use std::format;
+
+    #[test]
+    fn test_names_and_modes_match_cpu() {
+        for opcode in 0..=255u8 {
+            let emulated = format!("{:?}", crate::cpu::OPCODES[opcode as usize]);
+
+            match OPCODE_INFO[opcode as usize] {
+                Some(info) => assert_eq!(format!("{}({:?})", info.name, info.mode), emulated, "${:02x}", opcode),
+                None => assert_eq!("NUL", emulated, "${:02x}", opcode)
+            }
+        }
+    }
+
+    #[test]
+    fn test_cycles_and_length_match_cpu() {
+        for opcode in 0..=255u8 {
+            let info = match OPCODE_INFO[opcode as usize] {
+                Some(info) => info,
+                None => continue
+            };
+
+            // operands point to $2010 (or $10 in zero page), no index crosses a page
+            let mut memory = [0; 65536];
+            memory[0x0400..0x0403].copy_from_slice(&[opcode, 0x10, 0x20]);
+            let mut cpu = Cpu::new(&mut memory);
+            cpu.cold_reset();
+            cpu.step();
+
+            let taken = cpu.cycles == (info.cycles + info.branch_penalty) as usize;
+            if info.mode == Addressing::Relative && taken {
+                assert_eq!(cpu.register().pc(), 0x0412, "${:02x}", opcode);
+            } else {
+                assert_eq!(cpu.cycles, info.cycles as usize, "${:02x} cycles", opcode);
+            }
+
+            if info.class != AccessClass::Control {
+                assert_eq!(cpu.register().pc(), 0x0400 + info.length as u16, "${:02x} length", opcode);
+            }
+        }
+    }
+
+    #[test]
+    fn test_page_penalty_matches_cpu() {
+        // LDA $20F0,X / STA $20F0,X
+        for &(opcode, expected) in [(0xbd, 5), (0x9d, 5)].iter() {
+            let mut memory = [0; 65536];
+            memory[0x0400..0x0403].copy_from_slice(&[opcode, 0xf0, 0x20]);
+            let mut cpu = Cpu::new(&mut memory);
+            cpu.cold_reset();
+            cpu.register_mut().x = 0x20;
+            cpu.step();
+
+            let info = OPCODE_INFO[opcode as usize].unwrap();
+            assert_eq!(cpu.cycles, expected);
+            assert_eq!(cpu.cycles, (info.cycles + info.page_penalty) as usize);
+        }
+    }
+}
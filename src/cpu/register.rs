@@ -5,16 +5,16 @@ Status register format (P):
 +---+---+---+---+---+---+---+---+
 */
 
-const NEGATIVE_MASK: u8  = 0b1000_0000;
-const OVERFLOW_MASK: u8  = 0b0100_0000;
+pub const NEGATIVE_MASK: u8  = 0b1000_0000;
+pub const OVERFLOW_MASK: u8  = 0b0100_0000;
 const HARDWIRED_MASK: u8 = 0b0010_0000;
 const BREAK_MASK: u8     = 0b0001_0000;
-const DECIMAL_MASK: u8   = 0b0000_1000;
-const INTERRUPT_MASK: u8 = 0b0000_0100;
-const ZERO_MASK: u8      = 0b0000_0010;
-const CARRY_MASK: u8     = 0b0000_0001;
+pub const DECIMAL_MASK: u8   = 0b0000_1000;
+pub const INTERRUPT_MASK: u8 = 0b0000_0100;
+pub const ZERO_MASK: u8      = 0b0000_0010;
+pub const CARRY_MASK: u8     = 0b0000_0001;
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug)]
 pub struct Register {
     pc: u16, // Program Counter
     s: u8, // Stack Pointer
@@ -24,6 +24,12 @@ pub struct Register {
     p: u8 // Status Register
 }
 
+impl Default for Register {
+    fn default() -> Register {
+        Register::new()
+    }
+}
+
 impl Register {
     pub fn new() -> Register {
         return Register { pc: 0x0600, s: 0xFF, a: 0x00, x: 0x00, y: 0x00, p: 0b0010_0000 };
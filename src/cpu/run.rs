@@ -0,0 +1,180 @@
+#[cfg(feature = "std")]
+use super::register::Register;
+use super::Cpu;
+
+// The conditions holding a `Vec` or a `Box` need the `std` feature, the rest run without allocations.
+#[non_exhaustive]
+pub enum Condition<#[cfg(feature = "std")] 'c> {
+    Pc(u16),          // PC reached given address
+    #[cfg(feature = "std")]
+    PcIn(Vec<u16>),   // PC reached any of given addresses
+    SelfJump,         // instruction jumped or branched to itself (a trap)
+    Brk,              // BRK got executed
+    Never,            // runs for the whole cycle budget
+    #[cfg(feature = "std")]
+    Check(Box<dyn FnMut(&Register) -> bool + 'c>) // checked after every instruction
+}
+
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StopReason {
+    Pc(u16),
+    SelfJump(u16),
+    Brk(u16), // address of the BRK instruction
+    #[cfg(feature = "std")]
+    Check,
+    CycleBudget
+}
+
+impl<'a> Cpu<'a> {
+    // Executes instructions until the condition is met, or until the cycle budget is used up.
+    // Conditions are checked after every instruction, so a run starting at the awaited PC
+    // does not stop right away.
+    #[cfg_attr(not(feature = "std"), allow(unused_mut))]
+    pub fn run_until(&mut self, mut condition: Condition, cycle_budget: usize) -> StopReason {
+        let cycles_limit = self.cycles + cycle_budget;
+
+        loop {
+            // the instruction which ran - a trap may have returned in its place
+            let (pc, opcode) = match self.execute() {
+                Some((pc, opcode)) => (Some(pc), Some(opcode)),
+                None => (None, None)
+            };
+
+            let new_pc = self.register.pc();
+            let reason = match condition {
+                Condition::Pc(address) if new_pc == address => Some(StopReason::Pc(new_pc)),
+                #[cfg(feature = "std")]
+                Condition::PcIn(ref addresses) if addresses.contains(&new_pc) => Some(StopReason::Pc(new_pc)),
+                Condition::SelfJump if pc == Some(new_pc) => Some(StopReason::SelfJump(new_pc)),
+                Condition::Brk if opcode == Some(0x00) => pc.map(StopReason::Brk),
+                #[cfg(feature = "std")]
+                Condition::Check(ref mut check) => if check(&self.register) { Some(StopReason::Check) } else { None },
+                _ => None
+            };
+
+            if let Some(reason) = reason {
+                return reason;
+            }
+
+            if self.cycles >= cycles_limit {
+                return StopReason::CycleBudget;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Condition;
+    use super::StopReason;
+    use crate::cpu::Cpu;
+    #[cfg(feature = "std")]
+    use crate::cpu::Trap;
+
+    // $0400: LDX #$00
+    // $0402: INX
+    // $0403: CPX #$10
+    // $0405: BNE $0402
+    // $0407: BRK
+    // $0408: JMP $0408
+    const PROGRAM: [u8; 11] = [0xa2, 0x00, 0xe8, 0xe0, 0x10, 0xd0, 0xfb, 0x00, 0x4c, 0x08, 0x04];
+
+    fn memory() -> [u8; 65536] {
+        let mut memory = [0; 65536];
+        memory[0x0400..0x0400 + PROGRAM.len()].copy_from_slice(&PROGRAM);
+        memory[0xfffe] = 0x08;
+        memory[0xffff] = 0x04;
+
+        memory
+    }
+
+    #[test]
+    fn test_run_until_pc() {
+        let mut memory = memory();
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.cold_reset();
+
+        assert_eq!(cpu.run_until(Condition::Pc(0x0407), 1000), StopReason::Pc(0x0407));
+        assert_eq!(cpu.register().x, 0x10);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_run_until_pc_in() {
+        let mut memory = memory();
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.cold_reset();
+
+        assert_eq!(cpu.run_until(Condition::PcIn(vec![0x0407, 0x0405]), 1000), StopReason::Pc(0x0405));
+        assert_eq!(cpu.register().x, 0x01);
+    }
+
+    #[test]
+    fn test_run_until_brk() {
+        let mut memory = memory();
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.cold_reset();
+
+        assert_eq!(cpu.run_until(Condition::Brk, 1000), StopReason::Brk(0x0407));
+        assert_eq!(cpu.register().pc(), 0x0408);
+    }
+
+    #[test]
+    fn test_run_until_self_jump() {
+        let mut memory = memory();
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.cold_reset();
+
+        assert_eq!(cpu.run_until(Condition::SelfJump, 1000), StopReason::SelfJump(0x0408));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_run_until_check() {
+        let mut memory = memory();
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.cold_reset();
+
+        assert_eq!(cpu.run_until(Condition::Check(Box::new(|register| register.x == 0x05)), 1000), StopReason::Check);
+        assert_eq!(cpu.register().pc(), 0x0403);
+    }
+
+    #[test]
+    fn test_run_until_cycle_budget() {
+        let mut memory = memory();
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.cold_reset();
+
+        assert_eq!(cpu.run_until(Condition::SelfJump, 20), StopReason::CycleBudget);
+        assert_eq!(cpu.cycles, 20);
+        assert_eq!(cpu.register().x, 0x03);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_run_until_brk_with_trap() {
+        // $0400: JSR $0410
+        // $0403: JMP $0403
+        // $0410: BRK, never executed - the trap returns in its place
+        let mut memory = [0; 65536];
+        memory[0x0400..0x0406].copy_from_slice(&[0x20, 0x10, 0x04, 0x4c, 0x03, 0x04]);
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.cold_reset();
+        cpu.trap(0x0410, |_register, _memory| Trap::Return);
+
+        assert_eq!(cpu.run_until(Condition::Brk, 100), StopReason::CycleBudget);
+        assert_eq!(cpu.register().pc(), 0x0403);
+    }
+
+    #[test]
+    fn test_run_until_never() {
+        let mut memory = memory();
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.cold_reset();
+
+        assert_eq!(cpu.run_until(Condition::Never, 1000), StopReason::CycleBudget);
+        assert_eq!(cpu.cycles, 1002); // the last JMP finishes past the budget
+        assert_eq!(cpu.register().pc(), 0x0408);
+    }
+}
@@ -0,0 +1,110 @@
+// Self-modifying code detection.
+//
+// Every byte fetched as an opcode or operand is remembered together with the
+// value it had at that moment. A later write changing such a byte, or a fetch
+// of a byte which changed since it was last executed, is reported as an event.
+
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SmcKind {
+    Write,  // already executed byte got overwritten with a different value
+    Execute // executed byte differs from the value it had when executed before
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SelfModifyingCode {
+    pub kind: SmcKind,
+    pub pc: u16,      // address of the instruction causing the event
+    pub address: u16, // address of the modified byte
+    pub old: u8,
+    pub new: u8
+}
+
+pub struct Tracker<'a> {
+    executed: Vec<Option<u8>>,
+    callback: Box<dyn FnMut(SelfModifyingCode) + 'a>
+}
+
+impl<'a> Tracker<'a> {
+    pub fn new<F: FnMut(SelfModifyingCode) + 'a>(size: usize, callback: F) -> Tracker<'a> {
+        Tracker {
+            executed: vec![None; size],
+            callback: Box::new(callback)
+        }
+    }
+
+    pub fn fetch(&mut self, pc: u16, address: usize, value: u8) {
+        if let Some(old) = self.executed[address] {
+            if old != value {
+                (self.callback)(SelfModifyingCode { kind: SmcKind::Execute, pc: pc, address: address as u16, old: old, new: value });
+            }
+        }
+
+        self.executed[address] = Some(value);
+    }
+
+    pub fn write(&mut self, pc: u16, address: usize, old: u8, new: u8) {
+        if self.executed[address].is_some() && old != new {
+            (self.callback)(SelfModifyingCode { kind: SmcKind::Write, pc: pc, address: address as u16, old: old, new: new });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SelfModifyingCode;
+    use super::SmcKind;
+    use crate::cpu::Cpu;
+
+    fn run(program: &[u8], steps: usize) -> Vec<SelfModifyingCode> {
+        let mut memory = [0; 65536];
+        let mut events = Vec::new();
+        memory[0x0400..0x0400 + program.len()].copy_from_slice(program);
+
+        {
+            let mut cpu = Cpu::new(&mut memory);
+            cpu.detect_self_modifying_code(|event| events.push(event));
+            cpu.cold_reset();
+
+            for _ in 0..steps {
+                cpu.step();
+            }
+        }
+
+        events
+    }
+
+    #[test]
+    fn test_write_of_same_value_is_ignored() {
+        // $0400: LDA #$A9
+        // $0402: STA $0400
+        // $0405: JMP $0400
+        let events = run(&[0xa9, 0xa9, 0x8d, 0x00, 0x04, 0x4c, 0x00, 0x04], 6);
+
+        assert_eq!(events, vec![]);
+    }
+
+    #[test]
+    fn test_write_and_execute_modified_code() {
+        // $0400: LDA #$05
+        // $0402: STA $0400
+        // $0405: JMP $0400
+        // $0400: ORA $05
+        let events = run(&[0xa9, 0x05, 0x8d, 0x00, 0x04, 0x4c, 0x00, 0x04], 4);
+
+        assert_eq!(events, vec![
+            SelfModifyingCode { kind: SmcKind::Write, pc: 0x0402, address: 0x0400, old: 0xa9, new: 0x05 },
+            SelfModifyingCode { kind: SmcKind::Execute, pc: 0x0400, address: 0x0400, old: 0xa9, new: 0x05 }
+        ]);
+    }
+
+    #[test]
+    fn test_write_to_data_is_ignored() {
+        // $0400: LDA #$42
+        // $0402: STA $0500
+        // $0405: JMP $0400
+        let events = run(&[0xa9, 0x42, 0x8d, 0x00, 0x05, 0x4c, 0x00, 0x04], 6);
+
+        assert_eq!(events, vec![]);
+    }
+}
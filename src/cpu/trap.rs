@@ -0,0 +1,100 @@
+// PC traps - Rust closures standing in for 6502 routines (high level emulation).
+// A trap runs when the execution reaches its address, before the instruction
+// there is fetched.
+
+use std::collections::HashMap;
+
+use super::register::Register;
+
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Trap {
+    Continue, // execute the instruction at PC (which the trap may have changed)
+    Return    // simulate RTS, skipping the routine
+}
+
+type TrapHandler<'a> = Box<dyn FnMut(&mut Register, &mut [u8]) -> Trap + 'a>;
+
+#[derive(Default)]
+pub struct Traps<'a> {
+    handlers: HashMap<u16, TrapHandler<'a>>
+}
+
+impl<'a> Traps<'a> {
+    pub fn insert(&mut self, address: u16, handler: TrapHandler<'a>) {
+        self.handlers.insert(address, handler);
+    }
+
+    pub fn remove(&mut self, address: u16) {
+        self.handlers.remove(&address);
+    }
+
+    pub fn run(&mut self, register: &mut Register, memory: &mut [u8]) -> Option<Trap> {
+        if self.handlers.is_empty() {
+            return None;
+        }
+
+        self.handlers.get_mut(&register.pc()).map(|handler| handler(register, memory))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Trap;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn test_trap_with_return() {
+        // $0400: JSR $E459
+        // $0403: STA $80
+        let mut memory = [0; 65536];
+        memory[0x0400..0x0405].copy_from_slice(&[0x20, 0x59, 0xe4, 0x85, 0x80]);
+        memory[0x0300] = 0x42;
+
+        {
+            let mut cpu = Cpu::new(&mut memory);
+            cpu.cold_reset();
+            cpu.trap(0xe459, |register, memory| {
+                register.a = memory[0x0300];
+                register.set_carry_bit(true);
+
+                Trap::Return
+            });
+
+            cpu.step();
+            cpu.step();
+            assert_eq!(cpu.register().pc(), 0x0403);
+            assert_eq!(cpu.register().s(), 0xff);
+            assert_eq!(cpu.cycles, 12);
+            cpu.step();
+        }
+
+        assert_eq!(memory[0x80], 0x42);
+    }
+
+    #[test]
+    fn test_trap_with_continue() {
+        // $0400: LDA #$01
+        let mut memory = [0; 65536];
+        memory[0x0400..0x0402].copy_from_slice(&[0xa9, 0x01]);
+        memory[0x0300] = 0x42;
+        let mut calls = 0;
+
+        {
+            let mut cpu = Cpu::new(&mut memory);
+            cpu.cold_reset();
+            cpu.trap(0x0400, |_register, memory| {
+                calls += 1;
+                memory[0x0401] = memory[0x0300];
+
+                Trap::Continue
+            });
+
+            cpu.step();
+            assert_eq!(cpu.register().a, 0x42);
+            assert_eq!(cpu.register().pc(), 0x0402);
+        }
+
+        assert_eq!(calls, 1);
+    }
+}
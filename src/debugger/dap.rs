@@ -0,0 +1,678 @@
+// Debug Adapter Protocol server over stdin / stdout (`atari dap`), for debugging from
+// editors.
+//
+// Messages are JSON, each one preceded by a `Content-Length: n` header and an empty
+// line. There is a single thread; the program runs while the adapter handles a
+// request, so continue and the steps return once a breakpoint, the end of the step
+// or the cycle budget stops the CPU - the budget is reported as a pause.
+//
+// Launch arguments:
+//
+//   program      memory image (64K, loaded at $0000) or Atari executable (.xex)
+//   debugInfo    ca65 debug information, `<program>.dbg` when it exists
+//   symbols      symbol files, in addition to the ones given on the command line
+//   start        entry address or symbol, instead of RUNAD or the reset vector
+//   stopOnEntry  stop before the first instruction
+
+use std::collections::BTreeMap;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::path::PathBuf;
+
+use serde_json::json;
+use serde_json::Value;
+
+use super::debug_info::DebugInfo;
+use super::Debugger;
+use super::Step;
+use super::Stop;
+use super::RUN_CYCLES;
+use crate::machine::Machine;
+use crate::symbols::Symbols;
+
+const THREAD: i64 = 1;
+const REGISTERS: i64 = 1;                // variables reference of the registers scope
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut text = String::new();
+
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (index, &byte)| bits | (byte as u32) << (16 - 8 * index));
+        for index in 0..4 {
+            if index <= chunk.len() {
+                text.push(BASE64[(bits >> (18 - 6 * index) & 0x3f) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+
+    return text;
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut bits = 0u32;
+    let mut count = 0;
+
+    for c in text.bytes().filter(|&c| c != b'=' && !c.is_ascii_whitespace()) {
+        bits = bits << 6 | BASE64.iter().position(|&digit| digit == c)? as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            data.push((bits >> count) as u8);
+        }
+    }
+
+    return Some(data);
+}
+
+pub struct Connection<R, W> {
+    reader: R,
+    writer: W,
+    seq: i64
+}
+
+impl<R: BufRead, W: Write> Connection<R, W> {
+    pub fn new(reader: R, writer: W) -> Connection<R, W> {
+        Connection { reader: reader, writer: writer, seq: 0 }
+    }
+
+    // The next message, None at the end of the input.
+    pub fn read(&mut self) -> io::Result<Option<Value>> {
+        let mut length = None;
+
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+
+            let line = line.trim_end();
+            if line.is_empty() && length.is_some() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("Content-Length:") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+
+        let mut body = vec![0; length.unwrap_or(0)];
+        self.reader.read_exact(&mut body)?;
+
+        serde_json::from_slice(&body).map(Some).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+
+        let body = message.to_string();
+        write!(self.writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.writer.flush()
+    }
+
+    pub fn respond(&mut self, request: &Value, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body
+        }))
+    }
+
+    pub fn fail(&mut self, request: &Value, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message
+        }))
+    }
+
+    pub fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+}
+
+struct Launch {
+    memory: Vec<u8>,
+    entry: u16,
+    symbols: Symbols,
+    debug_info: Option<DebugInfo>,
+    directory: PathBuf, // of the debug information, which names the sources relative to it
+    stop_on_entry: bool
+}
+
+impl Launch {
+    fn new(arguments: &Value, symbols: &Symbols) -> Result<Launch, String> {
+        let program = arguments["program"].as_str().ok_or("launch needs a program")?;
+        let machine = Machine::load(program).map_err(|error| format!("Cannot load {}: {}", program, error))?;
+        let mut entry = machine.entry;
+
+        let mut launch_symbols = Symbols::new();
+        for path in arguments["symbols"].as_array().into_iter().flatten().filter_map(|path| path.as_str()) {
+            launch_symbols.load(path).map_err(|error| format!("{}: {}", path, error))?;
+        }
+        launch_symbols.merge(symbols);
+
+        let default_debug_info = Path::new(program).with_extension("dbg");
+        let debug_info = match arguments["debugInfo"].as_str() {
+            Some(path) => Some(PathBuf::from(path)),
+            None if default_debug_info.exists() => Some(default_debug_info),
+            None => None
+        };
+        let directory = debug_info.as_ref().and_then(|path| path.parent()).map(|path| path.to_path_buf()).unwrap_or_default();
+        let debug_info = match debug_info {
+            Some(path) => Some(DebugInfo::load(&path.to_string_lossy()).map_err(|error| format!("{}: {}", path.display(), error))?),
+            None => None
+        };
+
+        if let Some(start) = arguments["start"].as_str() {
+            let symbols = debug_info.as_ref().map(|info| &info.symbols).unwrap_or(&launch_symbols);
+            entry = symbols.parse_address(start).or_else(|| launch_symbols.parse_address(start)).ok_or_else(|| format!("unknown start {}", start))?;
+        }
+
+        Ok(Launch {
+            memory: machine.memory,
+            entry: entry,
+            symbols: launch_symbols,
+            debug_info: debug_info,
+            directory: directory,
+            stop_on_entry: arguments["stopOnEntry"].as_bool().unwrap_or(false)
+        })
+    }
+}
+
+struct Session<'a> {
+    debugger: Debugger<'a>,
+    entry: u16,
+    directory: PathBuf,
+    stop_on_entry: bool,
+    source_breakpoints: BTreeMap<String, Vec<u16>>, // by the path of the source
+    function_breakpoints: Vec<u16>,
+    instruction_breakpoints: Vec<u16>
+}
+
+fn capabilities() -> Value {
+    json!({
+        "supportsConfigurationDoneRequest": true,
+        "supportsFunctionBreakpoints": true,
+        "supportsInstructionBreakpoints": true,
+        "supportsReadMemoryRequest": true,
+        "supportsWriteMemoryRequest": true,
+        "supportsSteppingGranularity": true
+    })
+}
+
+fn address_reference(address: u16) -> String {
+    format!("0x{:04X}", address)
+}
+
+impl<'a> Session<'a> {
+    fn new(memory: &'a mut [u8], launch: Launch) -> Session<'a> {
+        let mut debugger = Debugger::new(memory);
+        debugger.symbols = launch.symbols;
+        if let Some(info) = launch.debug_info {
+            debugger.load_debug_info(info);
+        }
+        debugger.cpu.cold_reset();
+        debugger.cpu.register_mut().set_pc(launch.entry);
+
+        Session {
+            debugger: debugger,
+            entry: launch.entry,
+            directory: launch.directory,
+            stop_on_entry: launch.stop_on_entry,
+            source_breakpoints: BTreeMap::new(),
+            function_breakpoints: Vec::new(),
+            instruction_breakpoints: Vec::new()
+        }
+    }
+
+    fn update_breakpoints(&mut self) {
+        self.debugger.clear_breakpoints();
+
+        let addresses = self.source_breakpoints.values().flatten()
+            .chain(self.function_breakpoints.iter())
+            .chain(self.instruction_breakpoints.iter())
+            .cloned()
+            .collect::<Vec<_>>();
+        for address in addresses {
+            self.debugger.add_breakpoint(address);
+        }
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Value {
+        let path = arguments["source"]["path"].as_str().unwrap_or("").to_string();
+        let file = self.debugger.debug_info.as_ref().and_then(|info| info.file_id(&path));
+        let mut addresses = Vec::new();
+        let mut breakpoints = Vec::new();
+
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let line = breakpoint["line"].as_u64().unwrap_or(0) as usize;
+            let found = match (&self.debugger.debug_info, file) {
+                (Some(info), Some(file)) => info.addresses(super::debug_info::SourceLine { file: file, line: line }).to_vec(),
+                _ => Vec::new()
+            };
+
+            breakpoints.push(match found.first() {
+                Some(&address) => json!({ "verified": true, "line": line, "instructionReference": address_reference(address) }),
+                None => json!({ "verified": false, "line": line, "message": "no code for this line" })
+            });
+            addresses.extend(found);
+        }
+
+        self.source_breakpoints.insert(path, addresses);
+        self.update_breakpoints();
+
+        json!({ "breakpoints": breakpoints })
+    }
+
+    // Function breakpoints are names or addresses, instruction breakpoints references
+    // with an offset.
+    fn set_address_breakpoints(&mut self, arguments: &Value, instructions: bool) -> Value {
+        let mut addresses = Vec::new();
+        let mut breakpoints = Vec::new();
+
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let address = if instructions {
+                let offset = breakpoint["offset"].as_i64().unwrap_or(0);
+                breakpoint["instructionReference"].as_str()
+                    .and_then(|reference| self.debugger.symbols.parse_address(reference))
+                    .map(|address| address.wrapping_add(offset as u16))
+            } else {
+                breakpoint["name"].as_str().and_then(|name| self.debugger.parse_location(name)).and_then(|found| found.first().cloned())
+            };
+
+            breakpoints.push(match address {
+                Some(address) => json!({ "verified": true, "instructionReference": address_reference(address) }),
+                None => json!({ "verified": false, "message": "unknown location" })
+            });
+            addresses.extend(address);
+        }
+
+        if instructions {
+            self.instruction_breakpoints = addresses;
+        } else {
+            self.function_breakpoints = addresses;
+        }
+        self.update_breakpoints();
+
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn source(&self, address: u16) -> Option<(Value, usize)> {
+        let info = self.debugger.debug_info.as_ref()?;
+        let line = info.line(address)?;
+        let name = info.files.get(&line.file)?;
+
+        let path = self.directory.join(name);
+        let path = if path.exists() { path } else { PathBuf::from(name) };
+        let file_name = Path::new(name).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+
+        Some((json!({ "name": file_name, "path": path.to_string_lossy() }), line.line))
+    }
+
+    fn stack_trace(&self, arguments: &Value) -> Value {
+        // each frame is the routine and where it is at: the PC for the innermost one,
+        // the JSR into the next inner routine for the others
+        let mut pc = self.debugger.cpu.register().pc();
+        let mut frames = Vec::new();
+        for frame in self.debugger.call_stack().iter().rev() {
+            frames.push((frame.routine, pc));
+            pc = frame.call;
+        }
+        frames.push((self.entry, pc));
+
+        let total = frames.len();
+        let start = arguments["startFrame"].as_u64().unwrap_or(0) as usize;
+        let levels = match arguments["levels"].as_u64() {
+            Some(levels) if levels > 0 => levels as usize,
+            _ => total
+        };
+
+        let frames = frames.into_iter().enumerate().skip(start).take(levels).map(|(id, (routine, pc))| {
+            let mut frame = json!({
+                "id": id,
+                "name": self.debugger.symbols.describe(routine),
+                "line": 0,
+                "column": 0,
+                "instructionPointerReference": address_reference(pc)
+            });
+            if let Some((source, line)) = self.source(pc) {
+                frame["source"] = source;
+                frame["line"] = json!(line);
+                frame["column"] = json!(1);
+            }
+            frame
+        }).collect::<Vec<_>>();
+
+        json!({ "stackFrames": frames, "totalFrames": total })
+    }
+
+    fn registers(&self) -> Value {
+        let register = self.debugger.cpu.register();
+
+        let variable = |name: &str, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+        let mut pc = variable("PC", format!("${:04X}", register.pc()));
+        pc["memoryReference"] = json!(address_reference(register.pc()));
+
+        json!({ "variables": [
+            variable("A", format!("${:02X}", register.a)),
+            variable("X", format!("${:02X}", register.x)),
+            variable("Y", format!("${:02X}", register.y)),
+            variable("S", format!("${:02X}", register.s())),
+            pc,
+            variable("P", format!("${:02X} {}", register.p(), super::flags(register.p())))
+        ]})
+    }
+
+    fn read_memory(&self, arguments: &Value) -> Option<Value> {
+        let address = self.debugger.symbols.parse_address(arguments["memoryReference"].as_str()?)? as i64 + arguments["offset"].as_i64().unwrap_or(0);
+        let count = arguments["count"].as_u64().unwrap_or(0) as i64;
+
+        let memory = self.debugger.cpu.memory();
+        let first = address.max(0).min(memory.len() as i64);
+        let last = (address + count).max(first).min(memory.len() as i64);
+
+        Some(json!({
+            "address": format!("0x{:04X}", first),
+            "unreadableBytes": count - (last - first),
+            "data": base64_encode(&memory[first as usize..last as usize])
+        }))
+    }
+
+    fn write_memory(&mut self, arguments: &Value) -> Option<Value> {
+        let address = self.debugger.symbols.parse_address(arguments["memoryReference"].as_str()?)? as i64 + arguments["offset"].as_i64().unwrap_or(0);
+        let data = base64_decode(arguments["data"].as_str()?)?;
+
+        let memory = self.debugger.cpu.memory_mut();
+        if address < 0 || address as usize + data.len() > memory.len() {
+            return None;
+        }
+        memory[address as usize..address as usize + data.len()].copy_from_slice(&data);
+
+        Some(json!({ "bytesWritten": data.len() }))
+    }
+
+    fn stopped<R: BufRead, W: Write>(&self, connection: &mut Connection<R, W>, stop: Stop, reason: &str) -> io::Result<()> {
+        let (reason, description) = match stop {
+            Stop::Breakpoint(_) => ("breakpoint", None),
+            Stop::Step => (reason, None),
+            Stop::CycleBudget => ("pause", Some("cycle budget used up"))
+        };
+
+        let mut body = json!({ "reason": reason, "threadId": THREAD, "allThreadsStopped": true });
+        if let Some(description) = description {
+            body["description"] = json!(description);
+        }
+
+        connection.event("stopped", body)
+    }
+
+    // Handles a request, false when the client disconnected.
+    fn handle<R: BufRead, W: Write>(&mut self, connection: &mut Connection<R, W>, request: &Value) -> io::Result<bool> {
+        let arguments = &request["arguments"];
+
+        let step = match request["command"].as_str().unwrap_or("") {
+            "setBreakpoints" => {
+                let body = self.set_breakpoints(arguments);
+                connection.respond(request, body)?;
+                None
+            },
+            "setFunctionBreakpoints" | "setInstructionBreakpoints" => {
+                let body = self.set_address_breakpoints(arguments, request["command"] == "setInstructionBreakpoints");
+                connection.respond(request, body)?;
+                None
+            },
+            "setExceptionBreakpoints" => {
+                connection.respond(request, json!({}))?;
+                None
+            },
+            "configurationDone" => {
+                connection.respond(request, json!({}))?;
+                if self.stop_on_entry {
+                    self.stopped(connection, Stop::Step, "entry")?;
+                    None
+                } else {
+                    Some(None)
+                }
+            },
+            "threads" => {
+                connection.respond(request, json!({ "threads": [{ "id": THREAD, "name": "6502" }] }))?;
+                None
+            },
+            "stackTrace" => {
+                connection.respond(request, self.stack_trace(arguments))?;
+                None
+            },
+            "scopes" => {
+                connection.respond(request, json!({ "scopes": [{
+                    "name": "Registers",
+                    "presentationHint": "registers",
+                    "variablesReference": REGISTERS,
+                    "expensive": false
+                }]}))?;
+                None
+            },
+            "variables" => {
+                if arguments["variablesReference"] == REGISTERS {
+                    connection.respond(request, self.registers())?;
+                } else {
+                    connection.respond(request, json!({ "variables": [] }))?;
+                }
+                None
+            },
+            "readMemory" => {
+                match self.read_memory(arguments) {
+                    Some(body) => connection.respond(request, body)?,
+                    None => connection.fail(request, "invalid memory reference")?
+                }
+                None
+            },
+            "writeMemory" => {
+                match self.write_memory(arguments) {
+                    Some(body) => connection.respond(request, body)?,
+                    None => connection.fail(request, "invalid memory write")?
+                }
+                None
+            },
+            "continue" => {
+                connection.respond(request, json!({ "allThreadsContinued": true }))?;
+                Some(None)
+            },
+            "next" => {
+                connection.respond(request, json!({}))?;
+                Some(Some(Step::Over))
+            },
+            "stepIn" => {
+                connection.respond(request, json!({}))?;
+                Some(Some(Step::Into))
+            },
+            "stepOut" => {
+                connection.respond(request, json!({}))?;
+                Some(Some(Step::Out))
+            },
+            "pause" => {
+                connection.respond(request, json!({}))?;
+                self.stopped(connection, Stop::Step, "pause")?;
+                None
+            },
+            "disconnect" | "terminate" => {
+                connection.respond(request, json!({}))?;
+                return Ok(false);
+            },
+            _ => {
+                connection.fail(request, "unsupported request")?;
+                None
+            }
+        };
+
+        // the program runs after the response, and the stop is an event
+        match step {
+            Some(None) => {
+                let stop = self.debugger.run(RUN_CYCLES);
+                self.stopped(connection, stop, "pause")?;
+            },
+            Some(Some(step)) => {
+                let stop = if arguments["granularity"] == "instruction" {
+                    self.debugger.step_instruction(step, RUN_CYCLES)
+                } else {
+                    self.debugger.step_line(step, RUN_CYCLES)
+                };
+                self.stopped(connection, stop, "step")?;
+            },
+            None => {}
+        }
+
+        return Ok(true);
+    }
+}
+
+// Serves one debug session, with the symbols used for programs without their own.
+pub fn serve<R: BufRead, W: Write>(reader: R, writer: W, symbols: &Symbols) -> io::Result<()> {
+    let mut connection = Connection::new(reader, writer);
+
+    // there is nothing to debug before the launch
+    let (request, mut launch) = loop {
+        let request = match connection.read()? {
+            Some(request) => request,
+            None => return Ok(())
+        };
+
+        match request["command"].as_str().unwrap_or("") {
+            "initialize" => connection.respond(&request, capabilities())?,
+            "launch" => match Launch::new(&request["arguments"], symbols) {
+                Ok(launch) => break (request, launch),
+                Err(error) => connection.fail(&request, &error)?
+            },
+            "disconnect" => return connection.respond(&request, json!({})),
+            _ => connection.fail(&request, "not launched")?
+        }
+    };
+
+    let mut memory = std::mem::take(&mut launch.memory);
+    let mut session = Session::new(&mut memory, launch);
+    connection.respond(&request, json!({}))?;
+    connection.event("initialized", json!({}))?;
+
+    while let Some(request) = connection.read()? {
+        if !session.handle(&mut connection, &request)? {
+            break;
+        }
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::base64_decode;
+    use super::base64_encode;
+    use super::serve;
+    use crate::debugger::debug_info::tests::DBG;
+    use crate::debugger::debug_info::tests::SOURCE;
+    use crate::debugger::tests::xex;
+    use crate::symbols::Symbols;
+    use serde_json::json;
+    use serde_json::Value;
+    use std::fs;
+    use std::io::Cursor;
+
+
+    // Runs the requests through the server and returns the messages it sent.
+    fn session(requests: &[Value]) -> Vec<Value> {
+        let mut input = Vec::new();
+        for (seq, request) in requests.iter().enumerate() {
+            let mut request = request.clone();
+            request["seq"] = json!(seq + 1);
+            request["type"] = json!("request");
+            let body = request.to_string();
+            input.extend_from_slice(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).as_bytes());
+        }
+
+        let mut output = Vec::new();
+        serve(Cursor::new(input), &mut output, &Symbols::new()).unwrap();
+
+        let mut messages = Vec::new();
+        let mut output = output.as_slice();
+        while let Some(start) = output.windows(4).position(|window| window == b"\r\n\r\n") {
+            let header = String::from_utf8_lossy(&output[..start]).to_string();
+            let length: usize = header.trim_start_matches("Content-Length: ").parse().unwrap();
+            messages.push(serde_json::from_slice(&output[start + 4..start + 4 + length]).unwrap());
+            output = &output[start + 4 + length..];
+        }
+        messages
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64_encode(&[0xa2, 0x00, 0x20, 0x09]), "ogAgCQ==");
+        assert_eq!(base64_encode(b"6502"), "NjUwMg==");
+        assert_eq!(base64_encode(b"atari"), "YXRhcmk=");
+        assert_eq!(base64_decode("YXRhcmk="), Some(b"atari".to_vec()));
+        assert_eq!(base64_decode("ogAgCQ=="), Some(vec![0xa2, 0x00, 0x20, 0x09]));
+        assert_eq!(base64_decode("!"), None);
+    }
+
+    #[test]
+    fn test_session() {
+        let directory = std::env::temp_dir().join(format!("atari-dap-{}", std::process::id()));
+        fs::create_dir_all(directory.join("src")).unwrap();
+        fs::write(directory.join("main.xex"), xex()).unwrap();
+        fs::write(directory.join("main.dbg"), DBG).unwrap();
+        fs::write(directory.join("src/main.s"), SOURCE).unwrap();
+        let source = directory.join("src/main.s").to_string_lossy().to_string();
+
+        let messages = session(&[
+            json!({ "command": "initialize", "arguments": { "adapterID": "atari" } }),
+            json!({ "command": "launch", "arguments": { "program": directory.join("main.xex"), "stopOnEntry": true } }),
+            json!({ "command": "setBreakpoints", "arguments": { "source": { "path": source }, "breakpoints": [{ "line": 9 }, { "line": 7 }] } }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "variables", "arguments": { "variablesReference": 1 } }),
+            json!({ "command": "readMemory", "arguments": { "memoryReference": "0x2000", "count": 4 } }),
+            json!({ "command": "stepOut", "arguments": { "threadId": 1 } }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "disconnect" })
+        ]);
+        fs::remove_dir_all(&directory).unwrap();
+
+        let responses = messages.iter().filter(|message| message["type"] == "response").collect::<Vec<_>>();
+        let events = messages.iter().filter(|message| message["type"] == "event").map(|message| (message["event"].as_str().unwrap(), message["body"]["reason"].clone())).collect::<Vec<_>>();
+
+        assert!(responses.iter().all(|response| response["success"] == true));
+        assert_eq!(responses[0]["body"]["supportsReadMemoryRequest"], true);
+        assert_eq!(events, vec![("initialized", Value::Null), ("stopped", json!("entry")), ("stopped", json!("breakpoint")), ("stopped", json!("step"))]);
+
+        let breakpoints = &responses[2]["body"]["breakpoints"];
+        assert_eq!((breakpoints[0]["verified"].clone(), breakpoints[1]["verified"].clone()), (json!(true), json!(false)));
+
+        let frames = &responses[4]["body"]["stackFrames"];
+        assert_eq!((frames[0]["name"].as_str(), frames[0]["line"].as_u64()), (Some("main"), Some(2)));
+        assert_eq!(frames[0]["source"]["path"], json!(source));
+
+        let frames = &responses[6]["body"]["stackFrames"];
+        assert_eq!(responses[6]["body"]["totalFrames"], 2);
+        assert_eq!((frames[0]["name"].as_str(), frames[0]["line"].as_u64()), (Some("print"), Some(9)));
+        assert_eq!((frames[1]["name"].as_str(), frames[1]["line"].as_u64()), (Some("main"), Some(3)));
+        assert_eq!(frames[1]["instructionPointerReference"], "0x2002");
+
+        let variables = &responses[7]["body"]["variables"];
+        assert_eq!((variables[1]["name"].as_str(), variables[1]["value"].as_str()), (Some("X"), Some("$00")));
+        assert_eq!(variables[4]["memoryReference"], "0x2009");
+
+        assert_eq!(responses[8]["body"]["data"], "ogAgCQ==");
+
+        let frames = &responses[10]["body"]["stackFrames"];
+        assert_eq!(frames.as_array().unwrap().len(), 1);
+        assert_eq!(frames[0]["instructionPointerReference"], "0x2005");
+    }
+}
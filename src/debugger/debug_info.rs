@@ -0,0 +1,381 @@
+// ca65 / ld65 debug information (`ld65 --dbgfile`), mapping addresses to source lines
+// and scopes.
+//
+// The file is a list of records, one per line, like
+//
+//   file  id=0,name="main.s",size=415,mtime=0x5F1E9A0B,mod=0
+//   line  id=4,file=0,line=12,span=3
+//   scope id=1,name="print",mod=0,type=scope,size=9,parent=0,span=5
+//   seg   id=0,name="CODE",start=0x002000,size=0x0014,addrsize=absolute,type=rw
+//   span  id=3,seg=0,start=2,size=3
+//
+// Spans are byte ranges relative to the start of their segment, lines and scopes
+// list the spans they produced (`span=3+4`). Lines from macro expansions have
+// type 2; the line invoking the macro is preferred for them.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::symbols::Symbols;
+
+const MACRO_LINE: u8 = 2;
+
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub enum DebugInfoError {
+    Io(String),
+    NotDebugInfo,
+    Syntax { line: usize, text: String } // line counted from 1
+}
+
+impl fmt::Display for DebugInfoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DebugInfoError::Io(error) => write!(f, "{}", error),
+            DebugInfoError::NotDebugInfo => write!(f, "not ld65 debug information"),
+            DebugInfoError::Syntax { line, text } => write!(f, "line {}: cannot parse \"{}\"", line, text)
+        }
+    }
+}
+
+impl Error for DebugInfoError {}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SourceLine {
+    pub file: usize, // file id
+    pub line: usize
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scope {
+    pub name: String, // with the names of the enclosing scopes, `outer::inner`
+    pub ranges: Vec<(u16, u16)> // first and last address
+}
+
+impl Scope {
+    pub fn contains(&self, address: u16) -> bool {
+        self.ranges.iter().any(|&(first, last)| (first..=last).contains(&address))
+    }
+
+    fn size(&self) -> usize {
+        self.ranges.iter().map(|&(first, last)| (last - first) as usize + 1).sum()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DebugInfo {
+    pub files: BTreeMap<usize, String>,
+    pub scopes: Vec<Scope>,
+    pub symbols: Symbols,
+    lines: HashMap<u16, (SourceLine, u8, usize)>, // address to line, its type and span size
+    addresses: BTreeMap<SourceLine, Vec<u16>>,    // line to the starts of its spans
+    sources: HashMap<usize, Vec<String>>          // file contents, once loaded
+}
+
+// Splits `a=1,name="x,y",span=2+3` into attributes, keeping quoted commas.
+fn attributes(text: &str) -> HashMap<&str, &str> {
+    let mut attributes = HashMap::new();
+    let mut quoted = false;
+    let mut start = 0;
+
+    for (index, c) in text.char_indices().chain(Some((text.len(), ','))) {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                let mut parts = text[start..index].splitn(2, '=');
+                if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+                    attributes.insert(key, value.trim_matches('"'));
+                }
+                start = index + 1;
+            },
+            _ => {}
+        }
+    }
+
+    attributes
+}
+
+fn number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok()
+    }
+}
+
+fn ids(text: Option<&&str>) -> Vec<usize> {
+    text.map(|text| text.split('+').filter_map(number).collect()).unwrap_or_default()
+}
+
+impl DebugInfo {
+    pub fn parse(text: &str) -> Result<DebugInfo, DebugInfoError> {
+        if !text.starts_with("version\t") {
+            return Err(DebugInfoError::NotDebugInfo);
+        }
+
+        let mut info = DebugInfo::default();
+        info.symbols.parse(text).map_err(|_| DebugInfoError::NotDebugInfo)?;
+
+        let mut segments = HashMap::new();            // id to start
+        let mut spans = HashMap::new();               // id to first and last address
+        let mut lines = Vec::new();                   // (line, type, spans)
+        let mut scopes = Vec::new();                  // (id, name, parent, spans)
+
+        for (index, record) in text.lines().enumerate() {
+            let mut parts = record.splitn(2, '\t');
+            let kind = parts.next().unwrap_or("");
+            let attributes = attributes(parts.next().unwrap_or(""));
+            let syntax = || DebugInfoError::Syntax { line: index + 1, text: record.to_string() };
+            let id = || attributes.get("id").and_then(|id| number(id)).ok_or_else(syntax);
+
+            match kind {
+                "file" => {
+                    let name = attributes.get("name").ok_or_else(syntax)?;
+                    info.files.insert(id()?, name.to_string());
+                },
+                "seg" => {
+                    let start = attributes.get("start").and_then(|start| number(start)).ok_or_else(syntax)?;
+                    segments.insert(id()?, start);
+                },
+                "span" => {
+                    let segment = attributes.get("seg").and_then(|segment| number(segment)).ok_or_else(syntax)?;
+                    let start = attributes.get("start").and_then(|start| number(start)).ok_or_else(syntax)?;
+                    let size = attributes.get("size").and_then(|size| number(size)).ok_or_else(syntax)?;
+                    spans.insert(id()?, (segment, start, size));
+                },
+                "line" => {
+                    let file = attributes.get("file").and_then(|file| number(file)).ok_or_else(syntax)?;
+                    let line = attributes.get("line").and_then(|line| number(line)).ok_or_else(syntax)?;
+                    let kind = attributes.get("type").and_then(|kind| number(kind)).unwrap_or(0) as u8;
+                    lines.push((SourceLine { file: file, line: line }, kind, ids(attributes.get("span"))));
+                },
+                "scope" => {
+                    let name = attributes.get("name").unwrap_or(&"").to_string();
+                    let parent = attributes.get("parent").and_then(|parent| number(parent));
+                    scopes.push((id()?, name, parent, ids(attributes.get("span"))));
+                },
+                _ => {}
+            }
+        }
+
+        // spans become absolute once the segments are known, they may come first
+        let spans: HashMap<usize, (u16, u16)> = spans.into_iter()
+            .filter(|(_, (_, _, size))| *size > 0)
+            .filter_map(|(id, (segment, start, size))| {
+                let first = segments.get(&segment)? + start;
+                Some((id, (first as u16, (first + size - 1) as u16)))
+            })
+            .collect();
+
+        for (line, kind, line_spans) in lines.into_iter() {
+            for span in line_spans.iter().filter_map(|span| spans.get(span)) {
+                info.addresses.entry(line).or_default().push(span.0);
+
+                let size = (span.1 - span.0) as usize + 1;
+                for address in span.0..=span.1 {
+                    let better = match info.lines.get(&address) {
+                        Some(&(_, old_kind, old_size)) => (kind == MACRO_LINE, size) < (old_kind == MACRO_LINE, old_size),
+                        None => true
+                    };
+                    if better {
+                        info.lines.insert(address, (line, kind, size));
+                    }
+                }
+            }
+        }
+
+        for addresses in info.addresses.values_mut() {
+            addresses.sort_unstable();
+            addresses.dedup();
+        }
+
+        let names: HashMap<usize, (String, Option<usize>)> = scopes.iter().map(|(id, name, parent, _)| (*id, (name.clone(), *parent))).collect();
+        for (_, name, parent, scope_spans) in scopes.iter() {
+            let mut path = vec![name.clone()];
+            let mut parent = *parent;
+            while let Some((name, grandparent)) = parent.and_then(|parent| names.get(&parent)) {
+                path.push(name.clone());
+                parent = *grandparent;
+            }
+            path.retain(|name| !name.is_empty());
+            path.reverse();
+
+            let ranges: Vec<(u16, u16)> = scope_spans.iter().filter_map(|span| spans.get(span)).cloned().collect();
+            if !ranges.is_empty() {
+                info.scopes.push(Scope { name: path.join("::"), ranges: ranges });
+            }
+        }
+
+        return Ok(info);
+    }
+
+    // Loads the debug information, and the sources relative to its directory when they exist.
+    pub fn load(path: &str) -> Result<DebugInfo, DebugInfoError> {
+        let text = fs::read_to_string(path).map_err(|error| DebugInfoError::Io(error.to_string()))?;
+        let mut info = DebugInfo::parse(&text)?;
+
+        let directory = Path::new(path).parent().unwrap_or_else(|| Path::new("."));
+        for (&id, name) in info.files.iter() {
+            let source = fs::read_to_string(name).or_else(|_| fs::read_to_string(directory.join(name)));
+            if let Ok(source) = source {
+                info.sources.insert(id, source.lines().map(|line| line.to_string()).collect());
+            }
+        }
+
+        return Ok(info);
+    }
+
+    pub fn add_source(&mut self, file: usize, source: &str) {
+        self.sources.insert(file, source.lines().map(|line| line.to_string()).collect());
+    }
+
+    pub fn line(&self, address: u16) -> Option<SourceLine> {
+        self.lines.get(&address).map(|&(line, _, _)| line)
+    }
+
+    // Text of the source line, when the source is loaded.
+    pub fn source(&self, line: SourceLine) -> Option<&str> {
+        self.sources.get(&line.file)?.get(line.line.checked_sub(1)?).map(|text| text.as_str())
+    }
+
+    pub fn file_id(&self, name: &str) -> Option<usize> {
+        // `main.s` matches `src/main.s` too, and so does `/home/user/project/src/main.s`
+        self.files.iter()
+            .find(|(_, file)| file.as_str() == name || Path::new(file).ends_with(name) || Path::new(name).ends_with(file.as_str()))
+            .map(|(&id, _)| id)
+    }
+
+    // Addresses where the code of the line starts, empty for lines without code.
+    pub fn addresses(&self, line: SourceLine) -> &[u16] {
+        self.addresses.get(&line).map(|addresses| addresses.as_slice()).unwrap_or(&[])
+    }
+
+    // Addresses for `file.s:123`.
+    pub fn parse_location(&self, text: &str) -> Option<Vec<u16>> {
+        let index = text.rfind(':')?;
+        let file = self.file_id(&text[..index])?;
+        let line = text[index + 1..].parse().ok()?;
+
+        Some(self.addresses(SourceLine { file: file, line: line }).to_vec())
+    }
+
+    // The innermost scope with the address.
+    pub fn scope(&self, address: u16) -> Option<&Scope> {
+        self.scopes.iter().filter(|scope| scope.contains(address)).min_by_key(|scope| scope.size())
+    }
+
+    // Lines which produced code, for coverage reports.
+    pub fn code_lines(&self) -> impl Iterator<Item = SourceLine> + '_ {
+        self.addresses.keys().cloned()
+    }
+
+    pub fn display(&self, line: SourceLine) -> DisplayLine<'_> {
+        DisplayLine { info: self, line: line }
+    }
+}
+
+pub struct DisplayLine<'a> {
+    info: &'a DebugInfo,
+    line: SourceLine
+}
+
+impl<'a> fmt::Display for DisplayLine<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.info.files.get(&self.line.file) {
+            Some(name) => write!(f, "{}:{}", name, self.line.line),
+            None => write!(f, "#{}:{}", self.line.file, self.line.line)
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::DebugInfo;
+    use super::DebugInfoError;
+    use super::SourceLine;
+
+    // main.s, assembled at $2000:
+    //
+    //  1  .proc main
+    //  2          ldx #0
+    //  3  loop:   jsr print
+    //  4          inx
+    //  5          bne loop
+    //  6          rts
+    //  7  .endproc
+    //  8  .proc print
+    //  9          sta $80
+    // 10          rts
+    // 11  .endproc
+    pub const SOURCE: &str = ".proc main\n        ldx #0\nloop:   jsr print\n        inx\n        bne loop\n        rts\n.endproc\n.proc print\n        sta $80\n        rts\n.endproc\n";
+    pub const DBG: &str = "version\tmajor=2,minor=0\n\
+        info\tcsym=0,file=1,lib=0,line=8,mod=1,scope=3,seg=1,span=9,sym=3,type=4\n\
+        file\tid=0,name=\"src/main.s\",size=100,mtime=0x5F1E9A0B,mod=0\n\
+        line\tid=0,file=0,line=2,span=0\n\
+        line\tid=1,file=0,line=3,span=1\n\
+        line\tid=2,file=0,line=4,span=2\n\
+        line\tid=3,file=0,line=5,span=3\n\
+        line\tid=4,file=0,line=6,span=4\n\
+        line\tid=5,file=0,line=9,span=5\n\
+        line\tid=6,file=0,line=10,span=6\n\
+        line\tid=7,file=0,line=20,type=2,count=1,span=5\n\
+        mod\tid=0,name=\"main.o\",file=0\n\
+        scope\tid=0,name=\"\",mod=0,size=13,span=8\n\
+        scope\tid=1,name=\"main\",mod=0,type=scope,size=10,parent=0,span=7\n\
+        scope\tid=2,name=\"print\",mod=0,type=scope,size=3,parent=0,span=9\n\
+        seg\tid=0,name=\"CODE\",start=0x002000,size=0x000D,addrsize=absolute,type=rw,oname=\"main.xex\",ooffs=6\n\
+        span\tid=0,seg=0,start=0,size=2\n\
+        span\tid=1,seg=0,start=2,size=3\n\
+        span\tid=2,seg=0,start=5,size=1\n\
+        span\tid=3,seg=0,start=6,size=2\n\
+        span\tid=4,seg=0,start=8,size=1\n\
+        span\tid=5,seg=0,start=9,size=2\n\
+        span\tid=6,seg=0,start=11,size=1\n\
+        span\tid=7,seg=0,start=0,size=9\n\
+        span\tid=8,seg=0,start=0,size=12\n\
+        span\tid=9,seg=0,start=9,size=3\n\
+        sym\tid=0,name=\"main\",addrsize=absolute,scope=0,def=0,val=0x2000,seg=0,type=lab\n\
+        sym\tid=1,name=\"loop\",addrsize=absolute,scope=1,def=1,val=0x2002,seg=0,type=lab\n\
+        sym\tid=2,name=\"print\",addrsize=absolute,scope=0,def=5,val=0x2009,seg=0,type=lab\n";
+
+    // the program of the debug information above
+    pub const CODE: [u8; 12] = [0xa2, 0x00, 0x20, 0x09, 0x20, 0xe8, 0xd0, 0xfa, 0x60, 0x85, 0x80, 0x60];
+
+    #[test]
+    fn test_lines() {
+        let mut info = DebugInfo::parse(DBG).unwrap();
+        info.add_source(0, SOURCE);
+
+        assert_eq!(info.line(0x2002), Some(SourceLine { file: 0, line: 3 }));
+        assert_eq!(info.line(0x2004), Some(SourceLine { file: 0, line: 3 }));
+        assert_eq!(info.line(0x2009), Some(SourceLine { file: 0, line: 9 })); // not the macro line
+        assert_eq!(info.line(0x2100), None);
+        assert_eq!(info.source(SourceLine { file: 0, line: 3 }), Some("loop:   jsr print"));
+        assert_eq!(info.display(SourceLine { file: 0, line: 3 }).to_string(), "src/main.s:3");
+
+        assert_eq!(info.parse_location("main.s:5"), Some(vec![0x2006]));
+        assert_eq!(info.parse_location("src/main.s:7"), Some(vec![]));
+        assert_eq!(info.parse_location("other.s:5"), None);
+        assert_eq!(info.parse_location("/home/user/project/src/main.s:9"), Some(vec![0x2009]));
+        assert_eq!(info.symbols.name(0x2002), Some("loop"));
+    }
+
+    #[test]
+    fn test_scopes() {
+        let info = DebugInfo::parse(DBG).unwrap();
+
+        assert_eq!(info.scope(0x2005).unwrap().name, "main");
+        assert_eq!(info.scope(0x200a).unwrap().name, "print");
+        assert_eq!(info.scope(0x200c), None);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(DebugInfo::parse("al C:2000 .main\n").unwrap_err(), DebugInfoError::NotDebugInfo);
+        assert_eq!(DebugInfo::parse("version\tmajor=2,minor=0\nspan\tid=0,seg=0\n").unwrap_err(),
+                   DebugInfoError::Syntax { line: 2, text: "span\tid=0,seg=0".to_string() });
+    }
+}
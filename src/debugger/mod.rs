@@ -0,0 +1,542 @@
+// Debugger layer around the CPU - breakpoints, stepping, a monitor line for the
+// current instruction, and execution profiles which can be read at source level
+// with ca65 debug information.
+//
+// The call stack is tracked from JSR: every JSR opens a frame, which is closed once
+// the stack pointer gets above the pushed return address again (RTS, or a routine
+// dropping it with PLA).
+
+pub mod dap;
+pub mod debug_info;
+pub mod monitor;
+pub mod search;
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+use crate::analysis::decode;
+use crate::cpu::Access;
+use crate::cpu::Condition;
+use crate::cpu::Cpu;
+use crate::cpu::StopReason;
+use crate::state::SaveState;
+use crate::state::Snapshot;
+use crate::state::StateError;
+use crate::symbols::Symbols;
+use debug_info::DebugInfo;
+use debug_info::SourceLine;
+
+const JSR: u8 = 0x20;
+
+pub const RUN_CYCLES: usize = 10 * 1_773_447; // ten seconds of PAL Atari time, for one go
+
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Stop {
+    Breakpoint(u16),
+    Step,
+    CycleBudget
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Step {
+    Into,
+    Over,
+    Out
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub call: u16,    // address of the JSR
+    pub routine: u16, // address it jumped to
+    s: u8             // stack pointer below the return address
+}
+
+#[derive(Default)]
+struct CallStack {
+    frames: Vec<Frame>,
+    pc: u16,
+    opcode: u8
+}
+
+// Executions and cycles of the instructions, by the address of their opcode.
+pub struct Profile {
+    pub executions: Vec<u64>,
+    pub cycles: Vec<u64>,
+    current: usize
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineProfile {
+    pub line: SourceLine,
+    pub executions: u64, // of its most executed instruction
+    pub cycles: u64
+}
+
+// Status flags as `NV-BDIZC`, lower case when clear.
+pub fn flags(p: u8) -> String {
+    "NV-BDIZC".chars().enumerate()
+        .map(|(index, flag)| if p & (0x80 >> index) != 0 { flag } else { flag.to_ascii_lowercase() })
+        .collect()
+}
+
+pub struct Debugger<'a> {
+    pub cpu: Cpu<'a>,
+    pub symbols: Symbols,
+    pub debug_info: Option<DebugInfo>,
+    breakpoints: BTreeSet<u16>,
+    profile: Rc<RefCell<Profile>>,
+    call_stack: Rc<RefCell<CallStack>>
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(memory: &'a mut [u8]) -> Debugger<'a> {
+        let size = memory.len();
+        let profile = Rc::new(RefCell::new(Profile { executions: vec![0; size], cycles: vec![0; size], current: 0 }));
+        let mut cpu = Cpu::new(memory);
+
+        let before = profile.clone();
+        cpu.on_before_instruction(move |register| {
+            let mut profile = before.borrow_mut();
+            profile.current = register.pc() as usize;
+            let current = profile.current;
+            profile.executions[current] += 1;
+        });
+
+        let after = profile.clone();
+        cpu.on_after_instruction(move |_, cycles| {
+            let mut profile = after.borrow_mut();
+            let current = profile.current;
+            profile.cycles[current] += cycles as u64;
+        });
+
+        let call_stack = Rc::new(RefCell::new(CallStack::default()));
+
+        let opcodes = call_stack.clone();
+        cpu.on_read(move |address, value, access| {
+            if access == Access::Opcode {
+                let mut call_stack = opcodes.borrow_mut();
+                call_stack.pc = address;
+                call_stack.opcode = value;
+            }
+        });
+
+        let frames = call_stack.clone();
+        cpu.on_after_instruction(move |register, _| {
+            let mut call_stack = frames.borrow_mut();
+            if call_stack.opcode == JSR {
+                let call = call_stack.pc;
+                call_stack.frames.push(Frame { call: call, routine: register.pc(), s: register.s() });
+            }
+            while call_stack.frames.last().map(|frame| register.s() > frame.s).unwrap_or(false) {
+                call_stack.frames.pop();
+            }
+            call_stack.opcode = 0;
+        });
+
+        Debugger {
+            cpu: cpu,
+            symbols: Symbols::new(),
+            debug_info: None,
+            breakpoints: BTreeSet::new(),
+            profile: profile,
+            call_stack: call_stack
+        }
+    }
+
+    // Uses the debug information for lines, and its symbols before the ones loaded so far.
+    pub fn load_debug_info(&mut self, info: DebugInfo) {
+        let mut symbols = info.symbols.clone();
+        symbols.merge(&self.symbols);
+        self.symbols = symbols;
+        self.debug_info = Some(info);
+    }
+
+    // Addresses for a breakpoint location: `file.s:123` with debug information, a symbol
+    // (`loop`, `print+2`) or an address.
+    pub fn parse_location(&self, text: &str) -> Option<Vec<u16>> {
+        if let Some(addresses) = self.debug_info.as_ref().and_then(|info| info.parse_location(text)) {
+            return Some(addresses);
+        }
+
+        self.symbols.parse_address(text).map(|address| vec![address])
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.breakpoints.remove(&address);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().cloned()
+    }
+
+    // Runs until a breakpoint, a breakpoint at the current PC does not stop right away.
+    pub fn run(&mut self, cycle_budget: usize) -> Stop {
+        let breakpoints = self.breakpoints.iter().cloned().collect();
+
+        match self.cpu.run_until(Condition::PcIn(breakpoints), cycle_budget) {
+            StopReason::Pc(address) => Stop::Breakpoint(address),
+            _ => Stop::CycleBudget
+        }
+    }
+
+    pub fn step(&mut self) -> Stop {
+        self.cpu.step();
+        Stop::Step
+    }
+
+    // Open JSR frames, the outermost first.
+    // Resumes a saved state. The frames of the call stack belong to the code which ran
+    // before, the calls which led to the loaded state are not known.
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), StateError> {
+        self.cpu.load_state(state)?;
+        *self.call_stack.borrow_mut() = CallStack::default();
+        self.profile.borrow_mut().current = 0;
+
+        return Ok(());
+    }
+
+    pub fn call_stack(&self) -> Vec<Frame> {
+        self.call_stack.borrow().frames.clone()
+    }
+
+    // Runs until the check holds or a breakpoint is reached.
+    fn run_to<F: FnMut(&crate::cpu::Register) -> bool>(&mut self, mut check: F, cycle_budget: usize) -> Stop {
+        let breakpoints = self.breakpoints.clone();
+        let mut breakpoint = None;
+
+        let reason = self.cpu.run_until(Condition::Check(Box::new(|register| {
+            if breakpoints.contains(&register.pc()) {
+                breakpoint = Some(register.pc());
+                return true;
+            }
+            check(register)
+        })), cycle_budget);
+
+        match (reason, breakpoint) {
+            (StopReason::CycleBudget, _) => Stop::CycleBudget,
+            (_, Some(address)) => Stop::Breakpoint(address),
+            _ => Stop::Step
+        }
+    }
+
+    // One instruction; over steps JSR as a whole, out runs until the current routine returns.
+    // Outside of any known routine there is nothing to step out of, out then does nothing.
+    pub fn step_instruction(&mut self, step: Step, cycle_budget: usize) -> Stop {
+        let pc = self.cpu.register().pc();
+        let s = self.cpu.register().s();
+
+        match step {
+            Step::Over if self.cpu.memory()[pc as usize] == JSR => {
+                let next = pc.wrapping_add(3);
+                self.run_to(|register| register.pc() == next && register.s() == s, cycle_budget)
+            },
+            Step::Out if !self.call_stack.borrow().frames.is_empty() => {
+                let depth = self.call_stack.borrow().frames.len();
+                let call_stack = self.call_stack.clone();
+                self.run_to(move |_| call_stack.borrow().frames.len() < depth, cycle_budget)
+            },
+            Step::Out => Stop::Step,
+            _ => self.step()
+        }
+    }
+
+    // Steps instructions until the code of another source line starts. Without debug
+    // information for the code that is a single instruction.
+    pub fn step_line(&mut self, step: Step, cycle_budget: usize) -> Stop {
+        let start = self.source_line(self.cpu.register().pc());
+        let cycles_limit = self.cpu.cycles + cycle_budget;
+
+        loop {
+            let stop = self.step_instruction(step, cycles_limit.saturating_sub(self.cpu.cycles));
+            let line = self.source_line(self.cpu.register().pc());
+
+            if stop != Stop::Step || start.is_none() || (line.is_some() && line != start) || step == Step::Out {
+                return stop;
+            }
+
+            if self.cpu.cycles >= cycles_limit {
+                return Stop::CycleBudget;
+            }
+        }
+    }
+
+    // Source line of the address, when there is debug information for it.
+    pub fn source_line(&self, address: u16) -> Option<SourceLine> {
+        self.debug_info.as_ref()?.line(address)
+    }
+
+    // The monitor line for the instruction at PC: address, label, disassembly and the
+    // source line next to it.
+    pub fn monitor_line(&self) -> String {
+        let pc = self.cpu.register().pc();
+        let label = match self.symbols.name(pc) {
+            Some(name) => format!("{}:", name),
+            None => String::new()
+        };
+        let instruction = match decode(self.cpu.memory(), pc) {
+            Some(instruction) => instruction.format(&self.symbols),
+            None => format!(".byte ${:02X}", self.cpu.memory()[pc as usize])
+        };
+        let mut line = format!("${:04X} {:<10}{}", pc, label, instruction);
+
+        if let (Some(info), Some(source)) = (self.debug_info.as_ref(), self.source_line(pc)) {
+            line = format!("{:<32}; {}", line, info.display(source));
+            if let Some(text) = info.source(source) {
+                line = format!("{}  {}", line, text.trim());
+            }
+        }
+
+        return line.trim_end().to_string();
+    }
+
+    pub fn profile(&self) -> std::cell::Ref<'_, Profile> {
+        self.profile.borrow()
+    }
+
+    pub fn reset_profile(&mut self) {
+        let mut profile = self.profile.borrow_mut();
+        profile.executions.iter_mut().for_each(|count| *count = 0);
+        profile.cycles.iter_mut().for_each(|count| *count = 0);
+    }
+
+    // Executed source lines with their cost, most expensive first.
+    pub fn line_profile(&self) -> Vec<LineProfile> {
+        let info = match self.debug_info.as_ref() {
+            Some(info) => info,
+            None => return Vec::new()
+        };
+        let profile = self.profile.borrow();
+        let mut lines: BTreeMap<SourceLine, LineProfile> = BTreeMap::new();
+
+        for (address, &executions) in profile.executions.iter().enumerate().filter(|(_, &executions)| executions > 0) {
+            if let Some(line) = info.line(address as u16) {
+                let entry = lines.entry(line).or_insert(LineProfile { line: line, executions: 0, cycles: 0 });
+                entry.executions = entry.executions.max(executions);
+                entry.cycles += profile.cycles[address];
+            }
+        }
+
+        let mut lines: Vec<LineProfile> = lines.into_values().collect();
+        lines.sort_by(|a, b| b.cycles.cmp(&a.cycles).then(a.line.cmp(&b.line)));
+        lines
+    }
+
+    // Cycles spent directly in each scope (procedure), most expensive first.
+    pub fn scope_profile(&self) -> Vec<(String, u64)> {
+        let info = match self.debug_info.as_ref() {
+            Some(info) => info,
+            None => return Vec::new()
+        };
+        let profile = self.profile.borrow();
+        let mut scopes: BTreeMap<String, u64> = BTreeMap::new();
+
+        for (address, &cycles) in profile.cycles.iter().enumerate().filter(|(_, &cycles)| cycles > 0) {
+            let name = info.scope(address as u16).map(|scope| scope.name.clone()).unwrap_or_default();
+            *scopes.entry(name).or_insert(0) += cycles;
+        }
+
+        let mut scopes: Vec<(String, u64)> = scopes.into_iter().collect();
+        scopes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        scopes
+    }
+
+    // Every source line which produced code, and whether it got executed.
+    pub fn coverage(&self) -> Vec<(SourceLine, bool)> {
+        let info = match self.debug_info.as_ref() {
+            Some(info) => info,
+            None => return Vec::new()
+        };
+        let profile = self.profile.borrow();
+
+        info.code_lines()
+            .map(|line| (line, info.addresses(line).iter().any(|&address| profile.executions[address as usize] > 0)))
+            .collect()
+    }
+
+    pub fn profile_report(&self) -> String {
+        let info = match self.debug_info.as_ref() {
+            Some(info) => info,
+            None => return String::new()
+        };
+        let mut report = String::new();
+
+        for (scope, cycles) in self.scope_profile() {
+            let scope = if scope.is_empty() { "(global)".to_string() } else { scope };
+            report.push_str(&format!("{:>10} {}\n", cycles, scope));
+        }
+
+        for line in self.line_profile() {
+            let text = info.source(line.line).unwrap_or("").trim();
+            report.push_str(&format!("{:>10} {:>8}x {}  {}\n", line.cycles, line.executions, info.display(line.line), text));
+        }
+
+        report
+    }
+
+    pub fn coverage_report(&self) -> String {
+        let info = match self.debug_info.as_ref() {
+            Some(info) => info,
+            None => return String::new()
+        };
+        let coverage = self.coverage();
+        let executed = coverage.iter().filter(|(_, executed)| *executed).count();
+        let mut report = format!("{} of {} lines executed\n", executed, coverage.len());
+
+        for (line, _) in coverage.iter().filter(|(_, executed)| !executed) {
+            report.push_str(&format!("not executed: {}  {}\n", info.display(*line), info.source(*line).unwrap_or("").trim()));
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::debug_info::tests::CODE;
+    use super::debug_info::tests::DBG;
+    use super::debug_info::tests::SOURCE;
+    use super::debug_info::DebugInfo;
+    use super::debug_info::SourceLine;
+    use super::Debugger;
+    use super::Frame;
+    use super::Step;
+    use super::Stop;
+    use crate::state::SaveState;
+    use crate::state::Snapshot;
+
+    pub fn xex() -> Vec<u8> {
+        let mut xex = vec![0xff, 0xff, 0x00, 0x20, 0x0b, 0x20];
+        xex.extend_from_slice(&CODE);
+        xex.extend_from_slice(&[0xe0, 0x02, 0xe1, 0x02, 0x00, 0x20]);
+        xex
+    }
+
+    pub fn memory() -> Vec<u8> {
+        let mut memory = vec![0; 65536];
+        memory[0x2000..0x2000 + CODE.len()].copy_from_slice(&CODE);
+        memory
+    }
+
+    pub fn debugger(memory: &mut [u8]) -> Debugger<'_> {
+        let mut debugger = Debugger::new(memory);
+        let mut info = DebugInfo::parse(DBG).unwrap();
+        info.add_source(0, SOURCE);
+        debugger.load_debug_info(info);
+        debugger.cpu.cold_reset();
+        debugger.cpu.register_mut().set_pc(0x2000);
+        debugger
+    }
+
+    #[test]
+    fn test_breakpoints_on_source_lines() {
+        let mut memory = memory();
+        let mut debugger = debugger(&mut memory);
+
+        assert_eq!(debugger.parse_location("main.s:9"), Some(vec![0x2009]));
+        assert_eq!(debugger.parse_location("loop"), Some(vec![0x2002]));
+        assert_eq!(debugger.parse_location("$2005"), Some(vec![0x2005]));
+
+        debugger.add_breakpoint(0x2009);
+        assert_eq!(debugger.run(10_000), Stop::Breakpoint(0x2009));
+        assert_eq!(debugger.source_line(0x2009), Some(SourceLine { file: 0, line: 9 }));
+        assert_eq!(debugger.monitor_line(), "$2009 print:    STA $80         ; src/main.s:9  sta $80");
+
+        // continuing from the breakpoint does not stop at it right away
+        assert_eq!(debugger.step(), Stop::Step);
+        assert_eq!(debugger.monitor_line(), "$200B           RTS             ; src/main.s:10  rts");
+        assert_eq!(debugger.run(10_000), Stop::Breakpoint(0x2009));
+        assert_eq!(debugger.cpu.register().x, 1);
+    }
+
+    #[test]
+    fn test_profile_and_coverage() {
+        let mut memory = memory();
+        let mut debugger = debugger(&mut memory);
+        debugger.add_breakpoint(0x2008); // RTS of main, after 256 loops
+
+        assert_eq!(debugger.run(100_000), Stop::Breakpoint(0x2008));
+
+        let lines = debugger.line_profile();
+        assert_eq!(lines[0].line, SourceLine { file: 0, line: 3 }); // JSR 256 * 6
+        assert_eq!((lines[0].executions, lines[0].cycles), (256, 1536));
+        assert_eq!(debugger.scope_profile(), vec![("main".to_string(), 2817), ("print".to_string(), 2304)]);
+
+        let coverage = debugger.coverage();
+        assert_eq!(coverage.len(), 8); // with the macro line
+        assert_eq!(coverage.iter().filter(|(_, executed)| !executed).map(|(line, _)| line.line).collect::<Vec<_>>(), vec![6]);
+        assert!(debugger.coverage_report().starts_with("7 of 8 lines executed\nnot executed: src/main.s:6  rts\n"));
+        assert!(debugger.profile_report().contains("      2817 main\n"));
+    }
+
+    #[test]
+    fn test_call_stack_and_stepping() {
+        let mut memory = memory();
+        let mut debugger = debugger(&mut memory);
+
+        debugger.step_instruction(Step::Into, 1000); // LDX
+        assert_eq!(debugger.step_instruction(Step::Into, 1000), Stop::Step); // JSR
+        assert_eq!(debugger.cpu.register().pc(), 0x2009);
+        assert_eq!(debugger.call_stack(), vec![Frame { call: 0x2002, routine: 0x2009, s: 0xfd }]);
+
+        assert_eq!(debugger.step_instruction(Step::Out, 1000), Stop::Step);
+        assert_eq!(debugger.cpu.register().pc(), 0x2005);
+        assert!(debugger.call_stack().is_empty());
+
+        // nothing to step out of
+        let cycles = debugger.cpu.cycles;
+        assert_eq!(debugger.step_instruction(Step::Out, 1000), Stop::Step);
+        assert_eq!((debugger.cpu.register().pc(), debugger.cpu.cycles), (0x2005, cycles));
+
+        // over the JSR, stopping at a breakpoint inside of the routine
+        debugger.step_instruction(Step::Into, 1000); // INX
+        debugger.step_instruction(Step::Into, 1000); // BNE
+        assert_eq!(debugger.step_instruction(Step::Over, 1000), Stop::Step);
+        assert_eq!(debugger.cpu.register().pc(), 0x2005);
+        debugger.step_instruction(Step::Into, 1000);
+        debugger.step_instruction(Step::Into, 1000);
+        debugger.add_breakpoint(0x200b);
+        assert_eq!(debugger.step_instruction(Step::Over, 1000), Stop::Breakpoint(0x200b));
+        assert_eq!(debugger.call_stack().len(), 1);
+    }
+
+    #[test]
+    fn test_load_state_drops_call_stack() {
+        let mut memory = memory();
+        let mut debugger = debugger(&mut memory);
+        let mut state = SaveState::new();
+        debugger.cpu.save_state(&mut state);
+
+        debugger.step_instruction(Step::Into, 1000); // LDX
+        debugger.step_instruction(Step::Into, 1000); // JSR
+        assert_eq!(debugger.call_stack().len(), 1);
+
+        debugger.load_state(&state).unwrap();
+        assert!(debugger.call_stack().is_empty());
+        assert_eq!(debugger.step_instruction(Step::Out, 1000), Stop::Step);
+        assert_eq!(debugger.cpu.register().pc(), 0x2000);
+    }
+
+    #[test]
+    fn test_step_line() {
+        let mut memory = memory();
+        let mut debugger = debugger(&mut memory);
+
+        assert_eq!(debugger.step_line(Step::Over, 1000), Stop::Step);
+        assert_eq!(debugger.source_line(debugger.cpu.register().pc()), Some(SourceLine { file: 0, line: 3 }));
+        assert_eq!(debugger.step_line(Step::Into, 1000), Stop::Step);
+        assert_eq!(debugger.source_line(debugger.cpu.register().pc()), Some(SourceLine { file: 0, line: 9 }));
+        assert_eq!(debugger.step_line(Step::Out, 1000), Stop::Step);
+        assert_eq!(debugger.cpu.register().pc(), 0x2005);
+    }
+}
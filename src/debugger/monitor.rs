@@ -0,0 +1,275 @@
+// Line based monitor on top of the debugger (`atari monitor`). Each command line gives
+// its output as text, so the monitor works the same from a terminal and from tests.
+
+use super::search::Filter;
+use super::search::Search;
+use super::Debugger;
+use super::Step;
+use super::Stop;
+use super::RUN_CYCLES;
+use crate::state::atari800;
+use crate::state::SaveState;
+use crate::state::Snapshot;
+use crate::state::StateError;
+
+const HELP: &str = "\
+g                    run until a breakpoint
+s                    step an instruction
+n                    step over a JSR
+o                    step out of the routine
+b [location]         set a breakpoint (file.s:12, symbol or address), or list them
+d <location>         delete a breakpoint
+r                    registers
+m <address> [count]  memory dump
+search [first last]  start a memory search, over all of the memory by default
+search <filter>      keep the candidates which are equal, changed, increased,
+                     decreased since the last search, or equal to a value
+search list          candidates and their values
+freeze [address [value]]  keep a byte at its value, or list the frozen bytes
+unfreeze <address>   release a frozen byte
+save <file>          save the CPU and memory state
+load <file>          resume a saved state, or an Atari800 one (.a8s)
+q                    quit
+
+Addresses and values are hexadecimal, with or without `$`.
+";
+
+const LISTED_CANDIDATES: usize = 32;
+
+// `$1f` or `1f`, hexadecimal like the addresses
+fn parse_value(text: &str) -> Option<u8> {
+    u8::from_str_radix(text.strip_prefix('$').unwrap_or(text), 16).ok()
+}
+
+#[derive(Default)]
+pub struct Monitor {
+    search: Option<Search>
+}
+
+impl Monitor {
+    pub fn new() -> Monitor {
+        Monitor::default()
+    }
+
+    // Executes the command line and returns its output, None for quit.
+    pub fn execute(&mut self, debugger: &mut Debugger, line: &str) -> Option<String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let location = |debugger: &Debugger, index: usize| words.get(index).and_then(|text| debugger.parse_location(text)).filter(|addresses| !addresses.is_empty());
+        let address = |debugger: &Debugger, index: usize| words.get(index).and_then(|text| debugger.symbols.parse_address(text));
+
+        let output = match words.as_slice() {
+            [] => String::new(),
+            ["q"] => return None,
+            ["h"] | ["?"] => HELP.to_string(),
+            ["g"] => go(debugger, None),
+            ["s"] => go(debugger, Some(Step::Into)),
+            ["n"] => go(debugger, Some(Step::Over)),
+            ["o"] => go(debugger, Some(Step::Out)),
+            ["b"] => debugger.breakpoints().map(|address| format!("{}\n", debugger.symbols.describe(address))).collect(),
+            ["b", _] | ["d", _] => match location(debugger, 1) {
+                Some(addresses) => {
+                    for address in addresses {
+                        if words[0] == "b" {
+                            debugger.add_breakpoint(address);
+                        } else {
+                            debugger.remove_breakpoint(address);
+                        }
+                    }
+                    String::new()
+                },
+                None => format!("unknown location {}\n", words[1])
+            },
+            ["r"] => registers(debugger),
+            ["m", _] | ["m", _, _] => match (address(debugger, 1), words.get(2).map(|count| count.parse::<usize>().ok())) {
+                (Some(start), None) => dump(debugger, start, 16),
+                (Some(start), Some(Some(count))) => dump(debugger, start, count),
+                _ => "?\n".to_string()
+            },
+            ["search"] => {
+                self.search = Some(Search::new(debugger.cpu.memory()));
+                format!("{} candidates\n", debugger.cpu.memory().len())
+            },
+            ["search", "list"] => match &self.search {
+                Some(search) => {
+                    let mut output: String = search.candidates().iter().take(LISTED_CANDIDATES)
+                        .map(|&address| format!("${:04X} {:<8} ${:02X}\n", address, debugger.symbols.name(address).unwrap_or(""), debugger.cpu.memory()[address as usize]))
+                        .collect();
+                    if search.candidates().len() > LISTED_CANDIDATES {
+                        output += &format!("... {} more\n", search.candidates().len() - LISTED_CANDIDATES);
+                    }
+                    output
+                },
+                None => "no search, start one with `search`\n".to_string()
+            },
+            ["search", filter] => match (&mut self.search, Filter::parse(filter)) {
+                (Some(search), Some(filter)) => format!("{} candidates\n", search.filter(debugger.cpu.memory(), filter)),
+                (None, _) => "no search, start one with `search`\n".to_string(),
+                (_, None) => format!("unknown filter {}\n", filter)
+            },
+            ["search", _, _] => match (address(debugger, 1), address(debugger, 2)) {
+                (Some(first), Some(last)) if first <= last => {
+                    self.search = Some(Search::in_range(debugger.cpu.memory(), first..=last));
+                    format!("{} candidates\n", (last - first) as usize + 1)
+                },
+                _ => "?\n".to_string()
+            },
+            ["freeze"] => debugger.cpu.frozen()
+                .map(|(address, value)| format!("{} ${:02X}\n", debugger.symbols.describe(address), value))
+                .collect(),
+            ["freeze", _] | ["freeze", _, _] => {
+                let value = match words.get(2) {
+                    Some(text) => parse_value(text),
+                    None => address(debugger, 1).map(|address| debugger.cpu.memory()[address as usize])
+                };
+                match (address(debugger, 1), value) {
+                    (Some(address), Some(value)) => {
+                        debugger.cpu.freeze(address, value);
+                        String::new()
+                    },
+                    _ => "?\n".to_string()
+                }
+            },
+            ["unfreeze", _] => match address(debugger, 1) {
+                Some(address) => {
+                    debugger.cpu.unfreeze(address);
+                    String::new()
+                },
+                None => "?\n".to_string()
+            },
+            ["save", path] => {
+                let mut state = SaveState::new();
+                debugger.cpu.save_state(&mut state);
+                match state.save(path) {
+                    Ok(()) => String::new(),
+                    Err(error) => format!("{}\n", error)
+                }
+            },
+            ["load", path] => {
+                // our own save states, or Atari800 ones with what could not be used
+                let loaded = match SaveState::load(path) {
+                    Err(StateError::NotSaveState) => atari800::load(path).map(|import| (import.state, import.unused)),
+                    loaded => loaded.map(|state| (state, Vec::new()))
+                };
+                match loaded.and_then(|(state, unused)| debugger.load_state(&state).map(|_| unused)) {
+                    Ok(unused) => unused.iter().map(|unused| format!("not used: {}\n", unused)).collect::<String>() + &format!("{}\n", debugger.monitor_line()),
+                    Err(error) => format!("{}\n", error)
+                }
+            },
+            _ => format!("unknown command {}, h for help\n", words[0])
+        };
+
+        return Some(output);
+    }
+}
+
+// Runs, or steps, and shows where the CPU stopped.
+fn go(debugger: &mut Debugger, step: Option<Step>) -> String {
+    let stop = match step {
+        Some(step) => debugger.step_instruction(step, RUN_CYCLES),
+        None => debugger.run(RUN_CYCLES)
+    };
+    let reason = match stop {
+        Stop::Breakpoint(_) => "breakpoint\n",
+        Stop::Step => "",
+        Stop::CycleBudget => "cycle budget used up\n"
+    };
+
+    format!("{}{}\n", reason, debugger.monitor_line())
+}
+
+fn registers(debugger: &Debugger) -> String {
+    let register = debugger.cpu.register();
+
+    format!("PC=${:04X} A=${:02X} X=${:02X} Y=${:02X} S=${:02X} P={} cycles={}\n",
+        register.pc(), register.a, register.x, register.y, register.s(), super::flags(register.p()), debugger.cpu.cycles)
+}
+
+fn dump(debugger: &Debugger, start: u16, count: usize) -> String {
+    let memory = debugger.cpu.memory();
+    let mut output = String::new();
+
+    for line in (0..count).step_by(16) {
+        let address = start as usize + line;
+        if address >= memory.len() {
+            break;
+        }
+
+        let bytes = &memory[address..(address + 16.min(count - line)).min(memory.len())];
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        output += &format!("${:04X}  {}\n", address, hex.join(" "));
+    }
+
+    return output;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Monitor;
+    use crate::debugger::tests::debugger;
+    use crate::debugger::tests::memory;
+
+    #[test]
+    fn test_commands() {
+        let mut memory = memory();
+        let mut debugger = debugger(&mut memory);
+        let mut monitor = Monitor::new();
+        let mut run = |line: &str| monitor.execute(&mut debugger, line).unwrap();
+
+        assert_eq!(run("b main.s:9"), "");
+        assert_eq!(run("b"), "print\n");
+        assert_eq!(run("g"), "breakpoint\n$2009 print:    STA $80         ; src/main.s:9  sta $80\n");
+        assert_eq!(run("r"), "PC=$2009 A=$00 X=$00 Y=$00 S=$FD P=nv-bdIZc cycles=8\n");
+        assert_eq!(run("m 2000 20"), "$2000  A2 00 20 09 20 E8 D0 FA 60 85 80 60 00 00 00 00\n$2010  00 00 00 00\n");
+        assert_eq!(run("d print"), "");
+        assert_eq!(run("o"), "$2005           INX             ; src/main.s:4  inx\n");
+        assert_eq!(run("x"), "unknown command x, h for help\n");
+        assert_eq!(monitor.execute(&mut debugger, "q"), None);
+    }
+
+    #[test]
+    fn test_search_and_freeze() {
+        // X counts the loops, the print routine stores it at $80 with STX
+        let mut memory = memory();
+        memory[0x2009] = 0x86;
+        let mut debugger = debugger(&mut memory);
+        let mut monitor = Monitor::new();
+        let mut run = |line: &str| monitor.execute(&mut debugger, line).unwrap();
+
+        assert_eq!(run("search increased"), "no search, start one with `search`\n");
+        assert_eq!(run("search 80 8f"), "16 candidates\n");
+        assert_eq!(run("b 200b"), "");
+        run("g");
+        run("g");
+        assert_eq!(run("search 1"), "1 candidates\n");
+        run("g");
+        assert_eq!(run("search increased"), "1 candidates\n");
+        assert_eq!(run("search list"), "$0080          $02\n");
+
+        assert_eq!(run("freeze 80 10"), "");
+        assert_eq!(run("freeze"), "$0080 $10\n");
+        run("g");
+        assert_eq!(run("m 80 1"), "$0080  10\n");
+        assert_eq!(run("unfreeze 80"), "");
+        run("g");
+        assert_eq!(run("m 80 1"), "$0080  04\n");
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("atari-monitor-{}.sav", std::process::id())).to_string_lossy().to_string();
+        let mut memory = memory();
+        let mut debugger = debugger(&mut memory);
+        let mut monitor = Monitor::new();
+        let mut run = |line: &str| monitor.execute(&mut debugger, line).unwrap();
+
+        run("s");
+        assert_eq!(run(&format!("save {}", path)), "");
+        run("s");
+        run("s");
+        assert_eq!(run(&format!("load {}", path)), "$2002 loop:     JSR print       ; src/main.s:3  loop:   jsr print\n");
+        assert_eq!(run("r"), "PC=$2002 A=$00 X=$00 Y=$00 S=$FF P=nv-bdIZc cycles=2\n");
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(run(&format!("load {}", path)).starts_with(&format!("{}: ", path)));
+    }
+}
@@ -0,0 +1,138 @@
+// Memory search ("cheat finder") - narrows down where a game keeps a value like the
+// lives or the score. Every filter compares the memory with the snapshot taken by the
+// previous one, keeps the candidates which changed the given way and takes a new
+// snapshot. Found bytes can then be frozen with `Cpu::freeze`.
+
+use std::ops::RangeInclusive;
+
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Filter {
+    Equal,
+    Changed,
+    Increased,
+    Decreased,
+    Value(u8)
+}
+
+impl Filter {
+    // `equal`, `changed`, `increased`, `decreased` or a value (`3`, `$03`).
+    pub fn parse(text: &str) -> Option<Filter> {
+        match text {
+            "equal" | "=" => Some(Filter::Equal),
+            "changed" | "!=" => Some(Filter::Changed),
+            "increased" | "+" => Some(Filter::Increased),
+            "decreased" | "-" => Some(Filter::Decreased),
+            _ => u8::from_str_radix(text.strip_prefix('$').unwrap_or(text), 16).ok().map(Filter::Value)
+        }
+    }
+
+    fn matches(&self, previous: u8, current: u8) -> bool {
+        match *self {
+            Filter::Equal => current == previous,
+            Filter::Changed => current != previous,
+            Filter::Increased => current > previous,
+            Filter::Decreased => current < previous,
+            Filter::Value(value) => current == value
+        }
+    }
+}
+
+pub struct Search {
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>
+}
+
+impl Search {
+    // Starts with every address as a candidate.
+    pub fn new(memory: &[u8]) -> Search {
+        Search::in_range(memory, 0..=(memory.len() - 1) as u16)
+    }
+
+    pub fn in_range(memory: &[u8], range: RangeInclusive<u16>) -> Search {
+        Search {
+            snapshot: memory.to_vec(),
+            candidates: range.collect()
+        }
+    }
+
+    // Keeps the candidates matching the filter, and returns how many are left.
+    pub fn filter(&mut self, memory: &[u8], filter: Filter) -> usize {
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|&address| filter.matches(snapshot[address as usize], memory[address as usize]));
+        self.snapshot.copy_from_slice(memory);
+
+        return self.candidates.len();
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+
+    // The value in the last snapshot.
+    pub fn value(&self, address: u16) -> u8 {
+        self.snapshot[address as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Filter;
+    use super::Search;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn test_filters() {
+        let mut memory = vec![0; 0x100];
+        memory[0x80] = 3;
+        memory[0x90] = 3;
+        memory[0xa0] = 7;
+
+        let mut search = Search::new(&memory);
+        assert_eq!(search.candidates().len(), 0x100);
+        assert_eq!(search.filter(&memory, Filter::Value(3)), 2);
+
+        // a life lost
+        memory[0x80] = 2;
+        memory[0x90] = 4;
+        assert_eq!(search.filter(&memory, Filter::Decreased), 1);
+        assert_eq!(search.candidates(), &[0x80]);
+        assert_eq!(search.filter(&memory, Filter::Equal), 1);
+        assert_eq!(search.value(0x80), 2);
+
+        let mut search = Search::in_range(&memory, 0x90..=0xa0);
+        memory[0xa0] = 8;
+        assert_eq!(search.filter(&memory, Filter::Changed), 1);
+        assert_eq!(search.candidates(), &[0xa0]);
+
+        assert_eq!(Filter::parse("increased"), Some(Filter::Increased));
+        assert_eq!(Filter::parse("$1f"), Some(Filter::Value(0x1f)));
+        assert_eq!(Filter::parse("12"), Some(Filter::Value(0x12)));
+        assert_eq!(Filter::parse("more"), None);
+    }
+
+    #[test]
+    fn test_freeze() {
+        // $0400: DEC $80
+        // $0402: DEC $81
+        // $0404: JMP $0400
+        let mut memory = vec![0; 65536];
+        memory[0x0400..0x0407].copy_from_slice(&[0xc6, 0x80, 0xc6, 0x81, 0x4c, 0x00, 0x04]);
+        memory[0x81] = 5;
+
+        {
+            let mut cpu = Cpu::new(&mut memory);
+            cpu.cold_reset();
+            cpu.freeze(0x80, 3);
+            for _ in 0..9 {
+                cpu.step();
+            }
+            assert_eq!(cpu.frozen().collect::<Vec<_>>(), vec![(0x80, 3)]);
+
+            cpu.unfreeze(0x80);
+            cpu.step();
+        }
+
+        assert_eq!((memory[0x80], memory[0x81]), (2, 2));
+    }
+}
@@ -0,0 +1,32 @@
+// Atari 8-bit emulation - the 6502 CPU with the machine around it, and the tools built
+// on top of it: code analysis, an assembler, a debugger, save states and symbol tables.
+// The `atari` command line program is a client of this library.
+//
+// Without the default `std` feature only the CPU core is built, as `no_std` and without
+// allocations, for running the 6502 on a microcontroller.
+#![cfg_attr(not(feature = "std"), no_std)]
+#![allow(clippy::needless_return, clippy::redundant_field_names, clippy::upper_case_acronyms, clippy::bool_assert_comparison)]
+
+// The tests of the CPU core use std, also without the feature.
+#[cfg(all(test, not(feature = "std")))]
+extern crate std;
+
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod assembler;
+pub mod cpu;
+#[cfg(feature = "std")]
+pub mod debugger;
+#[cfg(feature = "std")]
+pub mod machine;
+#[cfg(feature = "std")]
+pub mod sim65;
+#[cfg(feature = "std")]
+pub mod state;
+#[cfg(feature = "std")]
+pub mod symbols;
+
+pub use cpu::Cpu;
+#[cfg(feature = "std")]
+pub use machine::Machine;
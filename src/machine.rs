@@ -0,0 +1,186 @@
+// The machine - 64K of memory with a program loaded into it, and where to start it.
+// A `Cpu` (or a `Debugger`) borrows the memory to run it.
+//
+// Programs are memory images, loaded at $0000 and started at the reset vector, or Atari
+// executables - segments of `start`, `end` and the bytes, optionally preceded by $FFFF,
+// started at RUNAD when a segment sets it, at the first segment otherwise. A segment setting
+// INITAD has its init routine called right away, with the segments loaded so far, as DOS does.
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+
+use crate::cpu::CallError;
+use crate::cpu::Cpu;
+use crate::cpu::Regs;
+use crate::debugger::Debugger;
+
+const MEMORY_SIZE: usize = 65536;
+const RUNAD: usize = 0x02e0;
+const INITAD: usize = 0x02e2;
+const INIT_CYCLE_LIMIT: usize = 1_000_000; // over half a second of the Atari
+const RESET_VECTOR: usize = 0xfffc;
+
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoadError {
+    Io(String),
+    InvalidSegment { offset: usize }, // header of a segment, offset in the file
+    CutShort { start: u16, end: u16 },
+    Init { address: u16, error: CallError }, // init routine did not return
+    NoSegments
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "{}", error),
+            LoadError::InvalidSegment { offset } => write!(f, "invalid segment header at offset {}", offset),
+            LoadError::CutShort { start, end } => write!(f, "segment ${:04X}-${:04X} is cut short", start, end),
+            LoadError::Init { address, error } => write!(f, "init routine at ${:04X} failed: {}", address, error),
+            LoadError::NoSegments => write!(f, "no segments")
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Init { error, .. } => Some(error),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Machine {
+    pub memory: Vec<u8>,
+    pub entry: u16
+}
+
+impl Default for Machine {
+    fn default() -> Machine {
+        Machine { memory: vec![0; MEMORY_SIZE], entry: 0 }
+    }
+}
+
+impl Machine {
+    pub fn new() -> Machine {
+        Machine::default()
+    }
+
+    pub fn from_program(data: &[u8]) -> Result<Machine, LoadError> {
+        let mut machine = Machine::new();
+
+        if !data.starts_with(&[0xff, 0xff]) {
+            let size = data.len().min(MEMORY_SIZE);
+            machine.memory[..size].copy_from_slice(&data[..size]);
+            machine.entry = u16::from_le_bytes([machine.memory[RESET_VECTOR], machine.memory[RESET_VECTOR + 1]]);
+            return Ok(machine);
+        }
+
+        let word = |offset: usize| data.get(offset..offset + 2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as usize);
+        let mut offset = 0;
+        let mut first = None;
+        let mut run = None;
+
+        while offset < data.len() {
+            if word(offset) == Some(0xffff) {
+                offset += 2;
+            }
+
+            let (start, end) = match (word(offset), word(offset + 2)) {
+                (Some(start), Some(end)) if start <= end => (start, end),
+                _ => return Err(LoadError::InvalidSegment { offset: offset })
+            };
+            offset += 4;
+
+            let bytes = data.get(offset..offset + end - start + 1).ok_or(LoadError::CutShort { start: start as u16, end: end as u16 })?;
+            machine.memory[start..=end].copy_from_slice(bytes);
+            offset += bytes.len();
+
+            first = first.or(Some(start as u16));
+            if (start..=end).contains(&RUNAD) && (start..=end).contains(&(RUNAD + 1)) {
+                run = Some(u16::from_le_bytes([machine.memory[RUNAD], machine.memory[RUNAD + 1]]));
+            }
+            if (start..=end).contains(&INITAD) && (start..=end).contains(&(INITAD + 1)) {
+                let init = u16::from_le_bytes([machine.memory[INITAD], machine.memory[INITAD + 1]]);
+                let mut cpu = Cpu::new(&mut machine.memory);
+                cpu.cold_reset();
+                cpu.call(init, Regs::default(), INIT_CYCLE_LIMIT).map_err(|error| LoadError::Init { address: init, error: error })?;
+            }
+        }
+
+        machine.entry = run.or(first).ok_or(LoadError::NoSegments)?;
+        return Ok(machine);
+    }
+
+    pub fn load(path: &str) -> Result<Machine, LoadError> {
+        let data = fs::read(path).map_err(|error| LoadError::Io(format!("{}: {}", path, error)))?;
+        Machine::from_program(&data)
+    }
+
+    // A CPU after a cold reset, at the entry.
+    pub fn cpu(&mut self) -> Cpu<'_> {
+        let mut cpu = Cpu::new(&mut self.memory);
+        cpu.cold_reset();
+        cpu.register_mut().set_pc(self.entry);
+
+        return cpu;
+    }
+
+    pub fn debugger(&mut self) -> Debugger<'_> {
+        let entry = self.entry;
+        let mut debugger = Debugger::new(&mut self.memory);
+        debugger.cpu.cold_reset();
+        debugger.cpu.register_mut().set_pc(entry);
+
+        return debugger;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LoadError;
+    use super::Machine;
+    use crate::cpu::CallError;
+    use crate::debugger::debug_info::tests::CODE;
+    use crate::debugger::tests::xex;
+
+    #[test]
+    fn test_load_program() {
+        let mut machine = Machine::from_program(&xex()).unwrap();
+        assert_eq!(machine.entry, 0x2000);
+        assert_eq!(&machine.memory[0x2000..0x200c], &CODE);
+        assert_eq!(machine.cpu().register().pc(), 0x2000);
+
+        assert_eq!(Machine::from_program(&[0xff, 0xff, 0x00, 0x20, 0x05, 0x20, 0xea]), Err(LoadError::CutShort { start: 0x2000, end: 0x2005 }));
+        assert_eq!(Machine::from_program(&[0xff, 0xff, 0x00, 0x20]).unwrap_err().to_string(), "invalid segment header at offset 2");
+
+        let mut image = vec![0; 65536];
+        image[0xfffc] = 0x34;
+        image[0xfffd] = 0x12;
+        assert_eq!(Machine::from_program(&image).unwrap().entry, 0x1234);
+    }
+
+    #[test]
+    fn test_load_program_with_init() {
+        // $0600: LDA #$42
+        // $0602: STA $80
+        // $0604: RTS
+        // INITAD = $0600
+        // $0610: LDA $80, run from the first segment
+        let program = [
+            0xff, 0xff, 0x00, 0x06, 0x04, 0x06, 0xa9, 0x42, 0x85, 0x80, 0x60,
+            0xe2, 0x02, 0xe3, 0x02, 0x00, 0x06,
+            0x10, 0x06, 0x11, 0x06, 0xa5, 0x80
+        ];
+        let machine = Machine::from_program(&program).unwrap();
+        assert_eq!(machine.memory[0x80], 0x42);
+        assert_eq!(machine.entry, 0x0600);
+
+        // $0600: JMP $0600
+        let program = [0xff, 0xff, 0x00, 0x06, 0x02, 0x06, 0x4c, 0x00, 0x06, 0xe2, 0x02, 0xe3, 0x02, 0x00, 0x06];
+        assert_eq!(Machine::from_program(&program), Err(LoadError::Init { address: 0x0600, error: CallError::CycleLimit { pc: 0x0600 } }));
+    }
+}
@@ -1,33 +1,381 @@
-mod cpu;
-use cpu::Cpu;
+// Command line client of the library.
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use atari::analysis;
+use atari::cpu::Condition;
+use atari::cpu::Cpu;
+use atari::cpu::StopReason;
+use atari::debugger;
+use atari::machine::Machine;
+use atari::sim65;
+use atari::state;
+use atari::symbols::Symbols;
+use std::env;
+use std::io;
 use std::io::prelude::*;
+use std::fs;
 use std::fs::File;
-use std::time::{Duration, Instant};
+use std::process;
+
+const CYCLES_PER_SECOND: usize = 1_773_447; // PAL Atari
+const SIM65_CYCLE_LIMIT: usize = 3600 * CYCLES_PER_SECOND;
+
+// atari sim65 <program> [arguments...]
+fn sim65(args: &[String]) -> i32 {
+    if args.is_empty() {
+        eprintln!("Usage: atari sim65 <program> [arguments...]");
+        return 2;
+    }
+
+    let image = match fs::read(&args[0]) {
+        Ok(image) => image,
+        Err(error) => {
+            eprintln!("Cannot read {}: {}", args[0], error);
+            return 2;
+        }
+    };
+
+    let result = sim65::Program::parse(&image)
+        .and_then(|program| sim65::run(&program, sim65::Host::new(args.to_vec(), Box::new(io::stdout())), SIM65_CYCLE_LIMIT));
+
+    match result {
+        Ok(exit_code) => exit_code as i32,
+        Err(error) => {
+            eprintln!("{}: {}", args[0], error);
+            2
+        }
+    }
+}
+
+// Options in front of the command, which leaves the arguments of the command (and the
+// ones passed to sim65 programs) alone.
+struct Options {
+    symbols: Symbols,
+    trace: bool
+}
+
+// Removes the options from the arguments. `--symbols <file>` files are loaded, followed
+// by the built-in Atari names unless there is `--no-atari-symbols`. `--trace` prints
+// every instruction of the default run, with the symbol names.
+fn parse_options(args: &mut Vec<String>) -> Result<Options, String> {
+    let mut symbols = Symbols::new();
+    let mut builtin = true;
+    let mut trace = false;
+
+    while args.len() > 1 && args[1].starts_with("--") {
+        let option = args.remove(1);
+        match option.as_str() {
+            "--symbols" => {
+                if args.len() < 2 {
+                    return Err("--symbols needs a file".to_string());
+                }
+                let path = args.remove(1);
+                symbols.load(&path).map_err(|error| format!("{}: {}", path, error))?;
+            },
+            "--no-atari-symbols" => builtin = false,
+            "--trace" => trace = true,
+            _ => return Err(format!("Unknown option {}", option))
+        }
+    }
+
+    if builtin {
+        symbols.merge(&Symbols::atari());
+    }
+
+    return Ok(Options { symbols: symbols, trace: trace });
+}
+
+fn load_image(path: &str) -> Result<Vec<u8>, String> {
+    let mut image = fs::read(path).map_err(|error| format!("Cannot read {}: {}", path, error))?;
+    image.resize(65536, 0);
+
+    return Ok(image);
+}
+
+// atari cycles <image> <start> <end>
+fn cycles(args: &[String], symbols: &Symbols) -> i32 {
+    let range = match (args.get(1).and_then(|text| symbols.parse_address(text)), args.get(2).and_then(|text| symbols.parse_address(text))) {
+        (Some(start), Some(end)) if start <= end => start..=end,
+        _ => {
+            eprintln!("Usage: atari cycles <image> <start> <end>");
+            return 2;
+        }
+    };
+
+    let memory = match load_image(&args[0]) {
+        Ok(memory) => memory,
+        Err(error) => {
+            eprintln!("{}", error);
+            return 2;
+        }
+    };
+
+    match analysis::timing::analyse(&memory, range) {
+        Ok(timing) => {
+            print!("{}", timing.to_text(symbols));
+            0
+        },
+        Err(error) => {
+            eprintln!("{}", error);
+            1
+        }
+    }
+}
+
+// atari flow <image> [--dot | --calls] [--table <address>:<count>]... [entry]...
+fn flow(args: &[String], symbols: &Symbols) -> i32 {
+    let mut options = analysis::flow::Options { vectors: true, ..Default::default() };
+    let mut format = "text";
+    let mut rest = args[1..].iter();
+
+    while let Some(arg) = rest.next() {
+        let parsed = match arg.as_str() {
+            "--dot" | "--calls" => {
+                format = arg;
+                Some(())
+            },
+            "--table" => rest.next().and_then(|table| {
+                let mut parts = table.splitn(2, ':');
+                let address = symbols.parse_address(parts.next()?)?;
+                let count = parts.next()?.parse().ok()?;
+                options.tables.push(analysis::flow::JumpTable { address: address, count: count });
+                Some(())
+            }),
+            _ => symbols.parse_address(arg).map(|entry| options.entries.push(entry))
+        };
+
+        if parsed.is_none() {
+            eprintln!("Usage: atari flow <image> [--dot | --calls] [--table <address>:<count>]... [entry]...");
+            return 2;
+        }
+    }
+
+    let memory = match load_image(&args[0]) {
+        Ok(memory) => memory,
+        Err(error) => {
+            eprintln!("{}", error);
+            return 2;
+        }
+    };
+
+    let flow = analysis::flow::analyse(&memory, &options);
+    match format {
+        "--dot" => print!("{}", flow.to_dot(symbols)),
+        "--calls" => print!("{}", flow.call_graph_dot(symbols)),
+        _ => print!("{}", flow.to_text(symbols))
+    }
+
+    return 0;
+}
+
+// atari export <image> <start> <end> [--mads] [entry]...
+fn export(args: &[String], symbols: &Symbols) -> i32 {
+    let usage = "Usage: atari export <image> <start> <end> [--mads] [entry]...";
+    let range = match (args.get(1).and_then(|text| symbols.parse_address(text)), args.get(2).and_then(|text| symbols.parse_address(text))) {
+        (Some(start), Some(end)) if start <= end => start..=end,
+        _ => {
+            eprintln!("{}", usage);
+            return 2;
+        }
+    };
+
+    let mut options = analysis::flow::Options { vectors: true, ..Default::default() };
+    let mut syntax = analysis::export::Syntax::Ca65;
+    for arg in args[3..].iter() {
+        match (arg.as_str(), symbols.parse_address(arg)) {
+            ("--mads", _) => syntax = analysis::export::Syntax::Mads,
+            (_, Some(entry)) => options.entries.push(entry),
+            _ => {
+                eprintln!("{}", usage);
+                return 2;
+            }
+        }
+    }
+
+    let memory = match load_image(&args[0]) {
+        Ok(memory) => memory,
+        Err(error) => {
+            eprintln!("{}", error);
+            return 2;
+        }
+    };
+    let flow = analysis::flow::analyse(&memory, &options);
+    let source = analysis::export::export(&memory, range.clone(), &flow, syntax, symbols);
+
+    if let Err(error) = analysis::export::verify(&memory, range, &source) {
+        eprintln!("Round trip failed: {}", error);
+        return 1;
+    }
+
+    print!("{}", source);
+    return 0;
+}
+
+// atari monitor <program> - memory image or executable, with `<program>.dbg` when it exists
+fn monitor(args: &[String], symbols: &Symbols) -> i32 {
+    let mut machine = match Machine::load(&args[0]) {
+        Ok(machine) => machine,
+        Err(error) => {
+            eprintln!("Cannot load {}: {}", args[0], error);
+            return 2;
+        }
+    };
+
+    let mut debugger = machine.debugger();
+    debugger.symbols = symbols.clone();
+    let debug_info = std::path::Path::new(&args[0]).with_extension("dbg");
+    if debug_info.exists() {
+        match debugger::debug_info::DebugInfo::load(&debug_info.to_string_lossy()) {
+            Ok(info) => debugger.load_debug_info(info),
+            Err(error) => eprintln!("{}: {}", debug_info.display(), error)
+        }
+    }
+
+    let mut monitor = debugger::monitor::Monitor::new();
+    println!("{}", debugger.monitor_line());
+
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+        match monitor.execute(&mut debugger, &line) {
+            Some(output) => print!("{}", output),
+            None => break
+        }
+    }
+
+    return 0;
+}
+
+// atari hashes <program> <interval> <cycles> - a state hash log, for `atari diverge`
+fn hashes(args: &[String]) -> i32 {
+    let (interval, cycles) = match (args.get(1).and_then(|text| text.parse().ok()), args.get(2).and_then(|text| text.parse().ok())) {
+        (Some(interval), Some(cycles)) if interval > 0 => (interval, cycles),
+        _ => {
+            eprintln!("Usage: atari hashes <program> <interval> <cycles>");
+            return 2;
+        }
+    };
+
+    let mut machine = match Machine::load(&args[0]) {
+        Ok(machine) => machine,
+        Err(error) => {
+            eprintln!("Cannot load {}: {}", args[0], error);
+            return 2;
+        }
+    };
+
+    let mut cpu = machine.cpu();
+
+    let stdout = io::stdout();
+    match state::hash::log_hashes(&mut cpu, interval, cycles, &mut stdout.lock()) {
+        Ok(()) => 0,
+        Err(error) => {
+            eprintln!("{}", error);
+            2
+        }
+    }
+}
+
+// atari diverge <log> <log> - the first line where two hash logs differ
+fn diverge(args: &[String]) -> i32 {
+    let open = |path: &String| File::open(path).map(io::BufReader::new).map_err(|error| format!("Cannot read {}: {}", path, error));
+
+    let divergence = match (open(&args[0]), open(&args[1])) {
+        (Ok(left), Ok(right)) => state::hash::first_divergence(left, right).map_err(|error| error.to_string()),
+        (Err(error), _) | (_, Err(error)) => Err(error)
+    };
+
+    match divergence {
+        Ok(None) => 0,
+        Ok(Some(divergence)) => {
+            let line = |line: Option<String>| line.unwrap_or_else(|| "end of log".to_string());
+            println!("line {}:\n< {}\n> {}", divergence.line, line(divergence.left), line(divergence.right));
+            1
+        },
+        Err(error) => {
+            eprintln!("{}", error);
+            2
+        }
+    }
+}
 
 fn main() {
+    let mut args: Vec<String> = env::args().collect();
+    let options = match parse_options(&mut args) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(2);
+        }
+    };
+
+    let symbols = options.symbols;
+
+    if args.len() > 1 && args[1] == "sim65" {
+        process::exit(sim65(&args[2..]));
+    }
+
+    if args.len() > 2 && args[1] == "cycles" {
+        process::exit(cycles(&args[2..], &symbols));
+    }
+
+    if args.len() > 2 && args[1] == "flow" {
+        process::exit(flow(&args[2..], &symbols));
+    }
+
+    if args.len() > 2 && args[1] == "export" {
+        process::exit(export(&args[2..], &symbols));
+    }
+
+    if args.len() > 2 && args[1] == "hashes" {
+        process::exit(hashes(&args[2..]));
+    }
+
+    if args.len() > 3 && args[1] == "diverge" {
+        process::exit(diverge(&args[2..]));
+    }
+
+    if args.len() > 2 && args[1] == "monitor" {
+        process::exit(monitor(&args[2..], &symbols));
+    }
+
+    // atari dap - Debug Adapter Protocol over stdin / stdout
+    if args.len() > 1 && args[1] == "dap" {
+        let stdin = io::stdin();
+        if let Err(error) = debugger::dap::serve(stdin.lock(), io::stdout(), &symbols) {
+            eprintln!("dap: {}", error);
+            process::exit(2);
+        }
+        return;
+    }
+
     let mut memory = [0; 65536];
 
     let mut file = File::open("examples/test.mem").unwrap();
     let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer);
-    for i in (0..65536) {
-        memory[i] = buffer[i];
-    }
+    file.read_to_end(&mut buffer).unwrap();
+    memory.copy_from_slice(&buffer[..65536]);
 
     let mut cpu = Cpu::new(&mut memory);
 
-    //cpu.debug();
+    if options.trace {
+        cpu.debug_with_symbols(symbols.clone());
+    }
     cpu.cold_reset();
-    let now = Instant::now();
-    let mut elapsed = now.elapsed().as_secs();
 
-    while cpu.step() {
-        let new_elapsed = now.elapsed().as_secs();
+    loop {
+        let reason = cpu.run_until(Condition::SelfJump, CYCLES_PER_SECOND);
+        println!("Used cycles: {}", cpu.cycles);
 
-        if (new_elapsed != elapsed) {
-            elapsed = new_elapsed;
-            println!("Used cycles: {}", cpu.cycles);
+        if reason != StopReason::CycleBudget {
+            println!("Stopped: {:?}", reason);
+            break;
         }
     }
-    println!("Used cycles: {}", cpu.cycles);
 }
@@ -0,0 +1,434 @@
+// Headless runner for cc65 programs linked for sim65 (`cl65 -t sim6502`).
+//
+// The binary starts with a 12 byte header, followed by the code:
+//
+//   "sim65"  magic
+//   $02      header version
+//   $00      cpu (0 - 6502, 1 - 65C02)
+//   $00      zero page address of the C stack pointer
+//   $0200    load address (little endian)
+//   $0200    reset address (little endian)
+//
+// The C library talks to the host by JSR-ing to the paravirtualization addresses
+// at $FFF4-$FFF9. Each of them is served by a PC trap, which does the work in Rust
+// and returns to the caller. Arguments are passed the cc65 way - the last one in
+// A/X, the rest on the C stack.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::prelude::*;
+use std::rc::Rc;
+
+use crate::cpu::Condition;
+use crate::cpu::Cpu;
+use crate::cpu::Register;
+use crate::cpu::Trap;
+
+const MAGIC: &[u8] = b"sim65";
+const VERSION: u8 = 2;
+const HEADER_SIZE: usize = 12;
+const CPU_6502: u8 = 0;
+
+const PV_OPEN: u16 = 0xfff4;
+const PV_CLOSE: u16 = 0xfff5;
+const PV_READ: u16 = 0xfff6;
+const PV_WRITE: u16 = 0xfff7;
+const PV_ARGS: u16 = 0xfff8;
+const PV_EXIT: u16 = 0xfff9;
+
+// open() flags, as defined by cc65's fcntl.h
+const O_RDONLY: u16 = 0x01;
+const O_WRONLY: u16 = 0x02;
+const O_CREAT: u16 = 0x10;
+const O_TRUNC: u16 = 0x20;
+const O_APPEND: u16 = 0x40;
+const O_EXCL: u16 = 0x80;
+
+const FAILURE: u16 = 0xffff; // -1
+const FIRST_FD: u16 = 3; // 0-2 are stdin, stdout and stderr
+const CYCLES_PER_CHECK: usize = 1_000_000;
+
+#[non_exhaustive]
+#[derive(Debug, PartialEq)]
+pub enum Sim65Error {
+    NotSim65,                // missing or truncated header
+    UnsupportedVersion(u8),
+    UnsupportedCpu(u8),      // only the 6502 is emulated
+    TooLarge,                // code does not fit between the load address and the end of memory
+    CycleLimit               // program did not exit within the cycle limit
+}
+
+impl fmt::Display for Sim65Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Sim65Error::NotSim65 => write!(f, "not a sim65 program"),
+            Sim65Error::UnsupportedVersion(version) => write!(f, "sim65 version {} is not supported", version),
+            Sim65Error::UnsupportedCpu(cpu) => write!(f, "CPU {} is not supported, only the 6502", cpu),
+            Sim65Error::TooLarge => write!(f, "program does not fit into memory"),
+            Sim65Error::CycleLimit => write!(f, "program did not exit within the cycle limit")
+        }
+    }
+}
+
+impl Error for Sim65Error {}
+
+#[derive(Debug, PartialEq)]
+pub struct Program {
+    pub sp: u8,
+    pub load: u16,
+    pub reset: u16,
+    pub code: Vec<u8>
+}
+
+impl Program {
+    pub fn parse(image: &[u8]) -> Result<Program, Sim65Error> {
+        if image.len() < HEADER_SIZE || &image[..MAGIC.len()] != MAGIC {
+            return Err(Sim65Error::NotSim65);
+        }
+
+        if image[5] != VERSION {
+            return Err(Sim65Error::UnsupportedVersion(image[5]));
+        }
+
+        if image[6] != CPU_6502 {
+            return Err(Sim65Error::UnsupportedCpu(image[6]));
+        }
+
+        let load = u16::from_le_bytes([image[8], image[9]]);
+        let code = image[HEADER_SIZE..].to_vec();
+
+        if load as usize + code.len() > 0x10000 {
+            return Err(Sim65Error::TooLarge);
+        }
+
+        return Ok(Program {
+            sp: image[7],
+            load: load,
+            reset: u16::from_le_bytes([image[10], image[11]]),
+            code: code
+        });
+    }
+}
+
+// What the program sees of the outside world.
+pub struct Host<'a> {
+    args: Vec<String>, // including the program name
+    stdout: Box<dyn Write + 'a>,
+    files: HashMap<u16, File>,
+    exit_code: Option<u8>
+}
+
+impl<'a> Host<'a> {
+    pub fn new(args: Vec<String>, stdout: Box<dyn Write + 'a>) -> Host<'a> {
+        Host {
+            args: args,
+            stdout: stdout,
+            files: HashMap::new(),
+            exit_code: None
+        }
+    }
+
+    fn open(&mut self, path: &str, flags: u16) -> u16 {
+        let mut options = OpenOptions::new();
+        options
+            .read(flags & O_RDONLY != 0)
+            .write(flags & O_WRONLY != 0)
+            .append(flags & O_APPEND != 0)
+            .truncate(flags & O_TRUNC != 0);
+
+        if flags & O_EXCL != 0 {
+            options.create_new(true);
+        } else if flags & O_CREAT != 0 {
+            options.create(true);
+        }
+
+        let file = match options.open(path) {
+            Ok(file) => file,
+            Err(_) => return FAILURE
+        };
+
+        let fd = (FIRST_FD..FAILURE).find(|fd| !self.files.contains_key(fd)).unwrap();
+        self.files.insert(fd, file);
+
+        return fd;
+    }
+
+    fn close(&mut self, fd: u16) -> u16 {
+        match self.files.remove(&fd) {
+            Some(_) => 0,
+            None if fd < FIRST_FD => 0,
+            None => FAILURE
+        }
+    }
+
+    fn read(&mut self, fd: u16, buffer: &mut [u8]) -> u16 {
+        let result = match fd {
+            0 => io::stdin().read(buffer),
+            _ => match self.files.get_mut(&fd) {
+                Some(file) => file.read(buffer),
+                None => return FAILURE
+            }
+        };
+
+        return result.map(|count| count as u16).unwrap_or(FAILURE);
+    }
+
+    fn write(&mut self, fd: u16, buffer: &[u8]) -> u16 {
+        let result = match fd {
+            1 => self.stdout.write_all(buffer).and_then(|_| self.stdout.flush()),
+            2 => io::stderr().write_all(buffer),
+            _ => match self.files.get_mut(&fd) {
+                Some(file) => file.write_all(buffer),
+                None => return FAILURE
+            }
+        };
+
+        return result.map(|_| buffer.len() as u16).unwrap_or(FAILURE);
+    }
+}
+
+fn word(memory: &[u8], address: u16) -> u16 {
+    u16::from_le_bytes([memory[address as usize], memory[address.wrapping_add(1) as usize]])
+}
+
+fn set_word(memory: &mut [u8], address: u16, value: u16) {
+    let bytes = value.to_le_bytes();
+    memory[address as usize] = bytes[0];
+    memory[address.wrapping_add(1) as usize] = bytes[1];
+}
+
+fn ax(register: &Register) -> u16 {
+    u16::from_le_bytes([register.a, register.x])
+}
+
+fn set_ax(register: &mut Register, value: u16) {
+    let bytes = value.to_le_bytes();
+    register.a = bytes[0];
+    register.x = bytes[1];
+}
+
+// Takes a parameter off the C stack, `size` is how many bytes it took there.
+fn pop_parameter(memory: &mut [u8], sp: u8, size: u16) -> u16 {
+    let stack = word(memory, sp as u16);
+    set_word(memory, sp as u16, stack.wrapping_add(size));
+
+    return word(memory, stack);
+}
+
+fn string(memory: &[u8], address: u16) -> String {
+    let bytes: Vec<u8> = memory[address as usize..].iter().take_while(|&&byte| byte != 0).cloned().collect();
+
+    return String::from_utf8_lossy(&bytes).into_owned();
+}
+
+// Copies argv onto the C stack, below the stack pointer, and writes its address to `argv`.
+fn push_args(memory: &mut [u8], sp: u8, args: &[String], argv: u16) -> u16 {
+    let count = args.len() as u16;
+    let mut pointer = word(memory, sp as u16).wrapping_sub((count + 1) * 2);
+    let mut stack = pointer;
+    set_word(memory, argv, pointer);
+
+    for arg in args.iter() {
+        stack = stack.wrapping_sub(arg.len() as u16 + 1);
+        for (offset, &byte) in arg.as_bytes().iter().chain([0].iter()).enumerate() {
+            memory[stack.wrapping_add(offset as u16) as usize] = byte;
+        }
+
+        set_word(memory, pointer, stack);
+        pointer = pointer.wrapping_add(2);
+    }
+
+    set_word(memory, pointer, 0);
+    set_word(memory, sp as u16, stack);
+
+    return count;
+}
+
+fn install<'a, 'h: 'a>(cpu: &mut Cpu<'a>, sp: u8, host: &Rc<RefCell<Host<'h>>>) {
+    let shared = Rc::clone(host);
+    cpu.trap(PV_OPEN, move |register, memory| {
+        // open(name, flags, ...) - Y holds the size of the parameters, mode is optional
+        let parameters = register.y as u16;
+        if parameters > 4 {
+            pop_parameter(memory, sp, parameters - 4);
+        }
+        let flags = pop_parameter(memory, sp, 2);
+        let name = pop_parameter(memory, sp, 2);
+
+        let path = string(memory, name);
+        set_ax(register, shared.borrow_mut().open(&path, flags));
+
+        Trap::Return
+    });
+
+    let shared = Rc::clone(host);
+    cpu.trap(PV_CLOSE, move |register, _memory| {
+        let fd = ax(register);
+        set_ax(register, shared.borrow_mut().close(fd));
+
+        Trap::Return
+    });
+
+    let shared = Rc::clone(host);
+    cpu.trap(PV_READ, move |register, memory| {
+        // read(fd, buffer, count)
+        let count = ax(register) as usize;
+        let buffer = pop_parameter(memory, sp, 2) as usize;
+        let fd = pop_parameter(memory, sp, 2);
+
+        let end = (buffer + count).min(memory.len());
+        set_ax(register, shared.borrow_mut().read(fd, &mut memory[buffer..end]));
+
+        Trap::Return
+    });
+
+    let shared = Rc::clone(host);
+    cpu.trap(PV_WRITE, move |register, memory| {
+        // write(fd, buffer, count)
+        let count = ax(register) as usize;
+        let buffer = pop_parameter(memory, sp, 2) as usize;
+        let fd = pop_parameter(memory, sp, 2);
+
+        let end = (buffer + count).min(memory.len());
+        set_ax(register, shared.borrow_mut().write(fd, &memory[buffer..end]));
+
+        Trap::Return
+    });
+
+    let shared = Rc::clone(host);
+    cpu.trap(PV_ARGS, move |register, memory| {
+        let argv = ax(register);
+        let count = push_args(memory, sp, &shared.borrow().args, argv);
+        set_ax(register, count);
+
+        Trap::Return
+    });
+
+    let shared = Rc::clone(host);
+    cpu.trap(PV_EXIT, move |register, _memory| {
+        shared.borrow_mut().exit_code = Some(register.a);
+
+        Trap::Return
+    });
+}
+
+// Runs the program until it calls exit(), returns its exit code.
+pub fn run(program: &Program, host: Host, cycle_limit: usize) -> Result<u8, Sim65Error> {
+    let mut memory = vec![0; 0x10000];
+    let load = program.load as usize;
+    memory[load..load + program.code.len()].copy_from_slice(&program.code);
+    set_word(&mut memory, 0xfffc, program.reset);
+
+    let host = Rc::new(RefCell::new(host));
+    let mut cpu = Cpu::new(&mut memory);
+    install(&mut cpu, program.sp, &host);
+    cpu.cold_reset();
+    cpu.register_mut().set_pc(program.reset);
+
+    loop {
+        if let Some(exit_code) = host.borrow().exit_code {
+            return Ok(exit_code);
+        }
+
+        if cpu.cycles >= cycle_limit {
+            return Err(Sim65Error::CycleLimit);
+        }
+
+        let budget = (cycle_limit - cpu.cycles).min(CYCLES_PER_CHECK);
+        let exited = Rc::clone(&host);
+        cpu.run_until(Condition::Check(Box::new(move |_| exited.borrow().exit_code.is_some())), budget);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run;
+    use super::Host;
+    use super::Program;
+    use super::Sim65Error;
+
+    fn image(load: u16, code: &[u8]) -> Vec<u8> {
+        let mut image = b"sim65\x02\x00\x02".to_vec();
+        image.extend_from_slice(&load.to_le_bytes());
+        image.extend_from_slice(&load.to_le_bytes());
+        image.extend_from_slice(code);
+
+        image
+    }
+
+    #[test]
+    fn test_parse() {
+        let program = Program::parse(&image(0x0200, &[0xea])).unwrap();
+
+        assert_eq!(program, Program { sp: 0x02, load: 0x0200, reset: 0x0200, code: vec![0xea] });
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let mut image = image(0x0200, &[0xea]);
+
+        assert_eq!(Program::parse(&image[..11]), Err(Sim65Error::NotSim65));
+        image[6] = 0x01;
+        assert_eq!(Program::parse(&image), Err(Sim65Error::UnsupportedCpu(0x01)));
+        image[5] = 0x01;
+        assert_eq!(Program::parse(&image), Err(Sim65Error::UnsupportedVersion(0x01)));
+    }
+
+    #[test]
+    fn test_write_and_exit() {
+        // $0200: LDA #$12 / STA $02 / LDA #$02 / STA $03  - sp = $0212
+        // $0208: LDA #$05 / LDX #$00 / JSR $FFF7        - write(1, $0216, 5)
+        // $020F: JSR $FFF9                              - exit(5)
+        // $0212: .word $0216, $0001                     - C stack
+        // $0216: "hello"
+        let program = Program::parse(&image(0x0200, &[
+            0xa9, 0x12, 0x85, 0x02, 0xa9, 0x02, 0x85, 0x03,
+            0xa9, 0x05, 0xa2, 0x00, 0x20, 0xf7, 0xff,
+            0x20, 0xf9, 0xff,
+            0x16, 0x02, 0x01, 0x00,
+            b'h', b'e', b'l', b'l', b'o'
+        ])).unwrap();
+        let mut output = Vec::new();
+
+        let exit_code = run(&program, Host::new(vec!["hello".to_string()], Box::new(&mut output)), 1000);
+
+        assert_eq!(exit_code, Ok(5));
+        assert_eq!(output, b"hello");
+    }
+
+    #[test]
+    fn test_args() {
+        // $0200: LDA #$00 / STA $02 / LDA #$04 / STA $03  - sp = $0400
+        // $0208: LDA #$00 / LDX #$03 / JSR $FFF8        - argv at $0300
+        // $020F: LDA $0300 / CMP #$FA / BNE $021E       - argv = $03FA
+        // $0216: LDA $02 / CMP #$F2 / BNE $021E         - sp below the strings
+        // $021C: TXA                                    - argc high byte
+        // $021D: NOP
+        // $021E: JSR $FFF9                              - exit(0) when all matched
+        let program = Program::parse(&image(0x0200, &[
+            0xa9, 0x00, 0x85, 0x02, 0xa9, 0x04, 0x85, 0x03,
+            0xa9, 0x00, 0xa2, 0x03, 0x20, 0xf8, 0xff,
+            0xad, 0x00, 0x03, 0xc9, 0xfa, 0xd0, 0x08,
+            0xa5, 0x02, 0xc9, 0xf2, 0xd0, 0x02,
+            0x8a,
+            0xea,
+            0x20, 0xf9, 0xff
+        ])).unwrap();
+        let args = vec!["prog".to_string(), "ab".to_string()];
+
+        assert_eq!(run(&program, Host::new(args, Box::new(Vec::new())), 1000), Ok(0));
+    }
+
+    #[test]
+    fn test_cycle_limit() {
+        // $0200: JMP $0200
+        let program = Program::parse(&image(0x0200, &[0x4c, 0x00, 0x02])).unwrap();
+
+        assert_eq!(run(&program, Host::new(Vec::new(), Box::new(Vec::new())), 1000), Err(Sim65Error::CycleLimit));
+    }
+}
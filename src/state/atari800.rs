@@ -0,0 +1,327 @@
+// Atari800 emulator save states (`.a8s`), mapped onto the CPU and memory of a save
+// state of this project.
+//
+// Unlike our save states these are not chunked: after the "ATARI800" header, the state
+// version and the verbose flag, the sections of the emulator follow each other
+// without lengths, and what a section holds depends on the version and the machine
+// type. Sections are read in the order Atari800 writes them, as of versions 7 and 8
+// (Atari800 2.x to 4.x), and everything this project does not emulate yet is reported
+// as unused. Files are usually gzip compressed.
+//
+// Numbers: UBYTE, UWORD little endian, INT 4 bytes little endian with the sign in the
+// top bit (sign and magnitude), file names are a UWORD length and the bytes.
+
+use std::fs;
+use std::io::Read;
+
+use flate2::read::GzDecoder;
+
+use super::SaveState;
+use super::StateError;
+use super::CPU;
+use super::RAM;
+
+const MAGIC: &[u8; 8] = b"ATARI800";
+const VERSIONS: std::ops::RangeInclusive<u8> = 7..=8;
+const GZIP: [u8; 2] = [0x1f, 0x8b];
+
+const MACHINE_800: u8 = 0;
+const MACHINE_XLXE: u8 = 1;
+
+const RAMBO_OR_COMPY_SHOP_KB: i32 = 320;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Import {
+    pub state: SaveState,
+    pub unused: Vec<String> // what was in the file but has no place in our state
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], StateError> {
+        let bytes = self.data.get(self.offset..self.offset + count).ok_or(StateError::Truncated)?;
+        self.offset += count;
+
+        return Ok(bytes);
+    }
+
+    fn ubyte(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn uword(&mut self) -> Result<u16, StateError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn int(&mut self) -> Result<i32, StateError> {
+        let bytes = self.bytes(4)?;
+        let magnitude = i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3] & 0x7f]);
+
+        Ok(if bytes[3] & 0x80 != 0 { -magnitude } else { magnitude })
+    }
+
+    fn count(&mut self) -> Result<usize, StateError> {
+        let value = self.int()?;
+        if value < 0 {
+            return Err(StateError::Invalid { chunk: *b"A8S ", message: format!("negative count {} at offset {}", value, self.offset - 4) });
+        }
+
+        return Ok(value as usize);
+    }
+
+    fn file_name(&mut self) -> Result<String, StateError> {
+        let length = self.uword()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(length)?).to_string())
+    }
+}
+
+// Decompresses the file when needed.
+fn decompress(data: &[u8]) -> Result<Vec<u8>, StateError> {
+    if !data.starts_with(&GZIP) {
+        return Ok(data.to_vec());
+    }
+
+    let mut decompressed = Vec::new();
+    GzDecoder::new(data).read_to_end(&mut decompressed).map_err(|error| StateError::Io(format!("gzip: {}", error)))?;
+
+    return Ok(decompressed);
+}
+
+pub fn import(data: &[u8]) -> Result<Import, StateError> {
+    let data = decompress(data)?;
+    if !data.starts_with(MAGIC) {
+        return Err(StateError::NotSaveState);
+    }
+
+    let mut reader = Reader { data: &data, offset: MAGIC.len() };
+    let version = reader.ubyte()?;
+    if !VERSIONS.contains(&version) {
+        return Err(StateError::UnsupportedVersion(version as u16));
+    }
+    let verbose = reader.ubyte()? != 0;
+    let mut unused = Vec::new();
+
+    // Atari800: TV system, machine type, XL/XE switches
+    let pal = reader.ubyte()? != 0;
+    let machine = reader.ubyte()?;
+    if machine == MACHINE_XLXE {
+        reader.bytes(6)?;
+    }
+    unused.push(format!("{} {} machine", if pal { "PAL" } else { "NTSC" }, match machine {
+        MACHINE_800 => "400/800",
+        MACHINE_XLXE => "XL/XE",
+        _ => "5200"
+    }));
+
+    // cartridge, a negative type when there is a piggyback cartridge
+    let cartridge = reader.int()?;
+    if cartridge != 0 {
+        let file = reader.file_name()?;
+        reader.int()?;
+        unused.push(format!("cartridge type {} ({})", cartridge.abs(), file));
+    }
+    if cartridge < 0 {
+        let piggyback = reader.int()?;
+        let file = reader.file_name()?;
+        reader.int()?;
+        unused.push(format!("piggyback cartridge type {} ({})", piggyback, file));
+    }
+
+    // SIO: eight drives
+    for drive in 1..=8 {
+        let status = reader.int()?;
+        let file = reader.file_name()?;
+        if status != 0 && !file.is_empty() && file != "Off" && file != "Empty" {
+            unused.push(format!("disk drive D{}: ({})", drive, file));
+        }
+    }
+
+    // ANTIC: registers and the display list state
+    reader.bytes(14 + 2 * 2 + 3 * 4)?;
+    unused.push("ANTIC registers".to_string());
+
+    // CPU, with the memory between the registers and PC
+    let a = reader.ubyte()?;
+    let p = reader.ubyte()?;
+    let s = reader.ubyte()?;
+    let x = reader.ubyte()?;
+    let y = reader.ubyte()?;
+    if reader.ubyte()? != 0 {
+        unused.push("pending IRQ".to_string());
+    }
+
+    if machine == MACHINE_800 {
+        let axlon_banks = reader.count()?;
+        if axlon_banks > 0 {
+            reader.int()?;
+            reader.int()?;
+            reader.bytes(axlon_banks * 0x4000)?;
+            unused.push(format!("Axlon RAM, {} banks", axlon_banks));
+        }
+        let mosaic_banks = reader.count()?;
+        if mosaic_banks > 0 {
+            reader.int()?;
+            reader.bytes(mosaic_banks * 0x1000)?;
+            unused.push(format!("Mosaic RAM, {} banks", mosaic_banks));
+        }
+    }
+
+    let base_kb = reader.int()?;
+    let memory = reader.bytes(65536)?;
+    reader.bytes(65536)?; // RAM, ROM or hardware, for each address
+    if base_kb < 64 {
+        unused.push(format!("{}K of RAM", base_kb));
+    }
+
+    if machine == MACHINE_XLXE {
+        if verbose {
+            reader.bytes(0x2000)?;
+        }
+        reader.bytes(0x2000)?;
+        if verbose {
+            reader.bytes(0x4000)?;
+        }
+        reader.bytes(0x4000)?;
+        if verbose {
+            reader.bytes(0x2000)?;
+        }
+        unused.push("RAM under the BASIC and OS ROMs".to_string());
+    }
+
+    let xe_banks = reader.count()?;
+    if base_kb + 16 * xe_banks as i32 == RAMBO_OR_COMPY_SHOP_KB {
+        reader.int()?;
+    }
+    let portb = reader.ubyte()?;
+    reader.int()?; // cartridge area enabled
+    if xe_banks > 0 {
+        reader.bytes((1 + xe_banks) * 0x4000)?;
+        if machine == MACHINE_XLXE && xe_banks == 4 {
+            reader.bytes(192 * 1024)?; // unused, for files of older versions
+        }
+        unused.push(format!("extended RAM, {} banks", xe_banks));
+    }
+    if machine == MACHINE_XLXE {
+        reader.int()?; // 1200XL / XEGS RAM mapping
+        unused.push(format!("PORTB banking ${:02X}", portb));
+    }
+
+    let pc = reader.uword()?;
+
+    if reader.offset < data.len() {
+        unused.push(format!("GTIA, PIA, POKEY and peripheral state, {} bytes", data.len() - reader.offset));
+    }
+
+    let mut state = SaveState::new();
+    let mut cpu = pc.to_le_bytes().to_vec();
+    cpu.extend_from_slice(&[s, a, x, y, p]);
+    cpu.extend_from_slice(&0u64.to_le_bytes()); // Atari800 does not count cycles since power on
+    state.add(CPU, 1, cpu);
+    state.add(RAM, 1, memory.to_vec());
+
+    Ok(Import { state: state, unused: unused })
+}
+
+pub fn load(path: &str) -> Result<Import, StateError> {
+    let data = fs::read(path).map_err(|error| StateError::Io(format!("{}: {}", path, error)))?;
+    import(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::import;
+    use crate::cpu::Cpu;
+    use crate::state::Snapshot;
+    use crate::state::StateError;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn int(value: i32) -> Vec<u8> {
+        let mut bytes = value.abs().to_le_bytes();
+        if value < 0 {
+            bytes[3] |= 0x80;
+        }
+        bytes.to_vec()
+    }
+
+    fn file_name(name: &str) -> Vec<u8> {
+        let mut bytes = (name.len() as u16).to_le_bytes().to_vec();
+        bytes.extend_from_slice(name.as_bytes());
+        bytes
+    }
+
+    // A 130XE with a cartridge and a disk, not verbose.
+    fn a8s() -> Vec<u8> {
+        let mut data = b"ATARI800\x08\x00".to_vec();
+        data.extend_from_slice(&[1, 1, 0, 0, 0, 0, 0, 0]);
+        data.extend(int(1));
+        data.extend(file_name("game.rom"));
+        data.extend(int(0));
+        data.extend(int(1));
+        data.extend(file_name("dos.atr"));
+        for _ in 0..7 {
+            data.extend(int(0));
+            data.extend(file_name("Off"));
+        }
+        data.extend(vec![0; 30]);
+
+        data.extend_from_slice(&[0x42, 0x35, 0xf0, 0x01, 0x02, 0x00]);
+        data.extend(int(64));
+        let mut memory = vec![0; 65536];
+        memory[0x2000] = 0xea;
+        memory[0xfffc] = 0x01;
+        data.extend(memory);
+        data.extend(vec![0; 65536]);
+        data.extend(vec![0; 0x2000 + 0x4000]);
+        data.extend(int(4));
+        data.push(0xe3);
+        data.extend(int(0));
+        data.extend(vec![0; 5 * 0x4000 + 192 * 1024]);
+        data.extend(int(0));
+        data.extend_from_slice(&[0x00, 0x20]);
+        data.extend(vec![0; 100]);
+        data
+    }
+
+    #[test]
+    fn test_import() {
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::fast());
+        gzip.write_all(&a8s()).unwrap();
+        let compressed = gzip.finish().unwrap();
+
+        let imported = import(&compressed).unwrap();
+        assert_eq!(imported, import(&a8s()).unwrap());
+        assert_eq!(imported.unused, vec![
+            "PAL XL/XE machine",
+            "cartridge type 1 (game.rom)",
+            "disk drive D1: (dos.atr)",
+            "ANTIC registers",
+            "RAM under the BASIC and OS ROMs",
+            "extended RAM, 4 banks",
+            "PORTB banking $E3",
+            "GTIA, PIA, POKEY and peripheral state, 100 bytes"
+        ]);
+
+        let mut memory = vec![0; 65536];
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.load_state(&imported.state).unwrap();
+        let register = cpu.register();
+        assert_eq!((register.pc(), register.a, register.x, register.y, register.s(), register.p()), (0x2000, 0x42, 0x01, 0x02, 0xf0, 0x35));
+        assert_eq!((cpu.memory()[0x2000], cpu.memory()[0xfffc]), (0xea, 0x01));
+    }
+
+    #[test]
+    fn test_errors() {
+        let data = a8s();
+        assert_eq!(import(&data[..70000]), Err(StateError::Truncated));
+        assert_eq!(import(b"ATARI800\x03\x00"), Err(StateError::UnsupportedVersion(3)));
+        assert_eq!(import(b"ATARISAV\x01\x00"), Err(StateError::NotSaveState));
+        assert!(matches!(import(&[0x1f, 0x8b, 0x08]), Err(StateError::Io(_))));
+    }
+}
@@ -0,0 +1,164 @@
+// Deterministic hashes of the emulator state, for finding where two runs (or the same
+// program in two builds) diverge without storing full traces.
+//
+// The hash is 64 bit FNV-1a over the chunks of the save state, so every component
+// which saves its state is covered. A hash log has a line per interval, `cycles hash`
+// in hex, and two logs are compared line by line.
+
+use std::io;
+use std::io::prelude::*;
+
+use super::SaveState;
+use super::Snapshot;
+use crate::cpu::Condition;
+use crate::cpu::Cpu;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+pub struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Fnv {
+        Fnv(FNV_OFFSET)
+    }
+}
+
+impl Fnv {
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl SaveState {
+    pub fn hash(&self) -> u64 {
+        let mut fnv = Fnv::default();
+
+        for chunk in self.chunks.iter() {
+            fnv.write(&chunk.id);
+            fnv.write(&chunk.version.to_le_bytes());
+            fnv.write(&(chunk.data.len() as u32).to_le_bytes());
+            fnv.write(&chunk.data);
+        }
+
+        return fnv.finish();
+    }
+}
+
+pub fn state_hash<S: Snapshot + ?Sized>(component: &S) -> u64 {
+    let mut state = SaveState::new();
+    component.save_state(&mut state);
+
+    return state.hash();
+}
+
+// Runs the CPU for the cycles, writing a hash line whenever another interval of cycles
+// passed. Instructions are not split, so a line is written after the instruction which
+// reached the interval, with the cycles at that point.
+pub fn log_hashes<W: Write>(cpu: &mut Cpu, interval: usize, cycles: usize, log: &mut W) -> io::Result<()> {
+    let end = cpu.cycles + cycles;
+    let mut next = cpu.cycles + interval;
+
+    while next <= end {
+        if cpu.cycles < next {
+            cpu.run_until(Condition::Never, next - cpu.cycles);
+        }
+        writeln!(log, "{} {:016x}", cpu.cycles, state_hash(cpu))?;
+        next += interval;
+    }
+
+    return Ok(());
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    pub line: usize, // counted from 1
+    pub left: Option<String>,
+    pub right: Option<String> // None when the log ended
+}
+
+// The first line where the logs differ.
+pub fn first_divergence<A: BufRead, B: BufRead>(left: A, right: B) -> io::Result<Option<Divergence>> {
+    let mut left = left.lines();
+    let mut right = right.lines();
+    let mut line = 0;
+
+    loop {
+        line += 1;
+        let (a, b) = (left.next().transpose()?, right.next().transpose()?);
+
+        if a.is_none() && b.is_none() {
+            return Ok(None);
+        }
+        if a != b {
+            return Ok(Some(Divergence { line: line, left: a, right: b }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::first_divergence;
+    use super::log_hashes;
+    use super::state_hash;
+    use super::Divergence;
+    use super::Fnv;
+    use crate::cpu::Cpu;
+
+    // $0400: INC $80
+    // $0402: JMP $0400
+    const PROGRAM: [u8; 5] = [0xe6, 0x80, 0x4c, 0x00, 0x04];
+
+    fn log(memory: &mut [u8]) -> String {
+        let mut cpu = Cpu::new(memory);
+        cpu.cold_reset();
+
+        let mut log = Vec::new();
+        log_hashes(&mut cpu, 100, 400, &mut log).unwrap();
+        String::from_utf8(log).unwrap()
+    }
+
+    #[test]
+    fn test_fnv() {
+        let mut fnv = Fnv::default();
+        assert_eq!(fnv.finish(), 0xcbf29ce484222325);
+        fnv.write(b"a");
+        assert_eq!(fnv.finish(), 0xaf63dc4c8601ec8c);
+        fnv.write(b"tari");
+        let mut once = Fnv::default();
+        once.write(b"atari");
+        assert_eq!(fnv.finish(), once.finish());
+    }
+
+    #[test]
+    fn test_hash_log() {
+        let mut memory = vec![0; 65536];
+        memory[0x0400..0x0405].copy_from_slice(&PROGRAM);
+
+        let first = log(&mut memory.clone());
+        assert_eq!(first, log(&mut memory.clone()));
+        assert_eq!(first.lines().map(|line| line.split(' ').next().unwrap()).collect::<Vec<_>>(), vec!["101", "200", "301", "400"]);
+
+        // the same program, starting with another value at $80
+        memory[0x80] = 0x10;
+        let other = log(&mut memory);
+        assert_eq!(first_divergence(first.as_bytes(), first.as_bytes()).unwrap(), None);
+        let divergence = first_divergence(first.as_bytes(), other.as_bytes()).unwrap().unwrap();
+        assert_eq!(divergence.line, 1);
+        assert_eq!(first_divergence(first.as_bytes(), &first.as_bytes()[..first.len() - 21]).unwrap(), Some(Divergence {
+            line: 4,
+            left: first.lines().nth(3).map(|line| line.to_string()),
+            right: None
+        }));
+
+        let mut memory = vec![0; 65536];
+        let cpu = Cpu::new(&mut memory);
+        assert_eq!(state_hash(&cpu), state_hash(&cpu));
+    }
+}
@@ -0,0 +1,305 @@
+// Save states - the emulator state in a versioned binary file, for bookmarking a point
+// of a long boot or a game and resuming it later.
+//
+// The file starts with the magic and the format version, followed by chunks:
+//
+//   "ATARISAV"  u16 format version
+//   id (4 ASCII bytes)  u16 chunk version  u32 length  data
+//   ...
+//
+// Numbers are little endian. Each component saves its own chunk, and newer versions of
+// a chunk only ever append to its data, so older builds read the part they know and
+// skip the rest. Chunks with unknown ids are kept as they are.
+//
+//   "CPU "  pc u16, s, a, x, y, p, cycles u64
+//   "RAM "  the memory, as seen by the CPU
+
+pub mod atari800;
+pub mod hash;
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+
+use crate::cpu::Cpu;
+
+const MAGIC: &[u8; 8] = b"ATARISAV";
+pub const VERSION: u16 = 1;
+
+pub const CPU: [u8; 4] = *b"CPU ";
+pub const RAM: [u8; 4] = *b"RAM ";
+
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub enum StateError {
+    Io(String),
+    NotSaveState,
+    UnsupportedVersion(u16),
+    Truncated,
+    MissingChunk([u8; 4]),
+    Invalid { chunk: [u8; 4], message: String }
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::Io(error) => write!(f, "{}", error),
+            StateError::NotSaveState => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => write!(f, "save state version {} is not supported", version),
+            StateError::Truncated => write!(f, "save state is cut short"),
+            StateError::MissingChunk(id) => write!(f, "no {} chunk", String::from_utf8_lossy(id).trim_end()),
+            StateError::Invalid { chunk, message } => write!(f, "{} chunk: {}", String::from_utf8_lossy(chunk).trim_end(), message)
+        }
+    }
+}
+
+impl Error for StateError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chunk {
+    pub id: [u8; 4],
+    pub version: u16,
+    pub data: Vec<u8>
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SaveState {
+    pub chunks: Vec<Chunk>
+}
+
+// A component of the machine with state of its own.
+pub trait Snapshot {
+    fn save_state(&self, state: &mut SaveState);
+    fn load_state(&mut self, state: &SaveState) -> Result<(), StateError>;
+}
+
+// Reads the fields of a chunk in order.
+pub struct Reader<'a> {
+    chunk: &'a Chunk,
+    offset: usize
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(chunk: &'a Chunk) -> Reader<'a> {
+        Reader { chunk: chunk, offset: 0 }
+    }
+
+    pub fn bytes(&mut self, count: usize) -> Result<&'a [u8], StateError> {
+        let bytes = self.chunk.data.get(self.offset..self.offset + count).ok_or_else(|| StateError::Invalid {
+            chunk: self.chunk.id,
+            message: format!("{} bytes expected at offset {}", count, self.offset)
+        })?;
+        self.offset += count;
+
+        return Ok(bytes);
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+impl SaveState {
+    pub fn new() -> SaveState {
+        SaveState::default()
+    }
+
+    // Adds the chunk, replacing an earlier one with the same id.
+    pub fn add(&mut self, id: [u8; 4], version: u16, data: Vec<u8>) {
+        let chunk = Chunk { id: id, version: version, data: data };
+
+        match self.chunks.iter_mut().find(|chunk| chunk.id == id) {
+            Some(old) => *old = chunk,
+            None => self.chunks.push(chunk)
+        }
+    }
+
+    pub fn chunk(&self, id: [u8; 4]) -> Option<&Chunk> {
+        self.chunks.iter().find(|chunk| chunk.id == id)
+    }
+
+    pub fn require(&self, id: [u8; 4]) -> Result<&Chunk, StateError> {
+        self.chunk(id).ok_or(StateError::MissingChunk(id))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+
+        for chunk in self.chunks.iter() {
+            bytes.extend_from_slice(&chunk.id);
+            bytes.extend_from_slice(&chunk.version.to_le_bytes());
+            bytes.extend_from_slice(&(chunk.data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&chunk.data);
+        }
+
+        return bytes;
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<SaveState, StateError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(StateError::NotSaveState);
+        }
+
+        let version = bytes.get(8..10).map(|version| u16::from_le_bytes([version[0], version[1]])).ok_or(StateError::Truncated)?;
+        if version > VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let mut state = SaveState::new();
+        let mut offset = 10;
+        while offset < bytes.len() {
+            let header = bytes.get(offset..offset + 10).ok_or(StateError::Truncated)?;
+            let id = [header[0], header[1], header[2], header[3]];
+            let version = u16::from_le_bytes([header[4], header[5]]);
+            let length = u32::from_le_bytes([header[6], header[7], header[8], header[9]]) as usize;
+            offset += 10;
+
+            let data = bytes.get(offset..offset + length).ok_or(StateError::Truncated)?;
+            state.chunks.push(Chunk { id: id, version: version, data: data.to_vec() });
+            offset += length;
+        }
+
+        return Ok(state);
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), StateError> {
+        fs::write(path, self.to_bytes()).map_err(|error| StateError::Io(format!("{}: {}", path, error)))
+    }
+
+    pub fn load(path: &str) -> Result<SaveState, StateError> {
+        let bytes = fs::read(path).map_err(|error| StateError::Io(format!("{}: {}", path, error)))?;
+        SaveState::from_bytes(&bytes)
+    }
+}
+
+impl<'a> Snapshot for Cpu<'a> {
+    fn save_state(&self, state: &mut SaveState) {
+        let register = self.register();
+        let mut data = register.pc().to_le_bytes().to_vec();
+        data.extend_from_slice(&[register.s(), register.a, register.x, register.y, register.p()]);
+        data.extend_from_slice(&(self.cycles as u64).to_le_bytes());
+
+        state.add(CPU, 1, data);
+        state.add(RAM, 1, self.memory().to_vec());
+    }
+
+    fn load_state(&mut self, state: &SaveState) -> Result<(), StateError> {
+        let ram = state.require(RAM)?;
+        if ram.data.len() != self.memory().len() {
+            return Err(StateError::Invalid { chunk: RAM, message: format!("{} bytes of memory, expected {}", ram.data.len(), self.memory().len()) });
+        }
+
+        let mut cpu = Reader::new(state.require(CPU)?);
+        let pc = cpu.u16()?;
+        let registers = cpu.bytes(5)?;
+        let cycles = cpu.u64()?;
+
+        let register = self.register_mut();
+        register.set_pc(pc);
+        register.set_s(registers[0]);
+        register.a = registers[1];
+        register.x = registers[2];
+        register.y = registers[3];
+        register.set_p(registers[4]);
+        self.cycles = cycles as usize;
+        self.memory_mut().copy_from_slice(&ram.data);
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SaveState;
+    use super::Snapshot;
+    use super::StateError;
+    use super::CPU;
+    use super::RAM;
+    use crate::cpu::Cpu;
+
+    // $0400: LDX #$05
+    // $0402: DEX
+    // $0403: BNE $0402
+    const PROGRAM: [u8; 5] = [0xa2, 0x05, 0xca, 0xd0, 0xfd];
+
+    fn memory() -> Vec<u8> {
+        let mut memory = vec![0; 65536];
+        memory[0x0400..0x0405].copy_from_slice(&PROGRAM);
+        memory
+    }
+
+    #[test]
+    fn test_save_and_resume() {
+        let mut memory = memory();
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.cold_reset();
+        cpu.step();
+        cpu.step();
+        cpu.memory_mut()[0x80] = 0x42;
+
+        let mut state = SaveState::new();
+        cpu.save_state(&mut state);
+        let bytes = state.to_bytes();
+        assert_eq!(&bytes[..10], b"ATARISAV\x01\x00");
+        assert_eq!(&bytes[10..20], b"CPU \x01\x00\x0f\x00\x00\x00");
+
+        for _ in 0..4 {
+            cpu.step();
+        }
+        let (pc, x, cycles) = (cpu.register().pc(), cpu.register().x, cpu.cycles);
+
+        let mut other_memory = vec![0; 65536];
+        let mut other = Cpu::new(&mut other_memory);
+        other.load_state(&SaveState::from_bytes(&bytes).unwrap()).unwrap();
+        assert_eq!((other.register().x, other.memory()[0x80]), (4, 0x42));
+        for _ in 0..4 {
+            other.step();
+        }
+
+        assert_eq!((other.register().pc(), other.register().x, other.cycles), (pc, x, cycles));
+    }
+
+    #[test]
+    fn test_compatibility() {
+        let mut memory = memory();
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.cold_reset();
+
+        // a newer build with one more CPU field and a new component
+        let mut state = SaveState::new();
+        cpu.save_state(&mut state);
+        state.chunks[0].version = 2;
+        state.chunks[0].data.push(0xff);
+        state.add(*b"XTRA", 1, vec![1, 2, 3]);
+
+        let loaded = SaveState::from_bytes(&state.to_bytes()).unwrap();
+        assert_eq!(loaded, state);
+        assert_eq!(cpu.load_state(&loaded), Ok(()));
+
+        let mut bytes = state.to_bytes();
+        bytes.truncate(bytes.len() - 1);
+        assert_eq!(SaveState::from_bytes(&bytes), Err(StateError::Truncated));
+        assert_eq!(SaveState::from_bytes(b"ATARISAV\x02\x00"), Err(StateError::UnsupportedVersion(2)));
+        assert_eq!(SaveState::from_bytes(b"PK\x03\x04"), Err(StateError::NotSaveState));
+
+        state.chunks.retain(|chunk| chunk.id != RAM);
+        assert_eq!(cpu.load_state(&state), Err(StateError::MissingChunk(RAM)));
+        state.add(RAM, 1, vec![0; 16]);
+        assert_eq!(cpu.load_state(&state).unwrap_err().to_string(), "RAM chunk: 16 bytes of memory, expected 65536");
+        state.add(CPU, 1, vec![0; 3]);
+        state.add(RAM, 1, vec![0; 65536]);
+        assert_eq!(cpu.load_state(&state).unwrap_err().to_string(), "CPU chunk: 5 bytes expected at offset 2");
+    }
+}
@@ -0,0 +1,339 @@
+// Names of the Atari 400/800/XL/XE hardware registers, OS variables and ROM vectors,
+// as in the OS equate files and Mapping the Atari.
+
+// Hardware registers: address, name when read, name when written. Registers without
+// one of the directions have an empty name there.
+pub const HARDWARE: &[(u16, &str, &str)] = &[
+    // GTIA
+    (0xd000, "M0PF", "HPOSP0"),
+    (0xd001, "M1PF", "HPOSP1"),
+    (0xd002, "M2PF", "HPOSP2"),
+    (0xd003, "M3PF", "HPOSP3"),
+    (0xd004, "P0PF", "HPOSM0"),
+    (0xd005, "P1PF", "HPOSM1"),
+    (0xd006, "P2PF", "HPOSM2"),
+    (0xd007, "P3PF", "HPOSM3"),
+    (0xd008, "M0PL", "SIZEP0"),
+    (0xd009, "M1PL", "SIZEP1"),
+    (0xd00a, "M2PL", "SIZEP2"),
+    (0xd00b, "M3PL", "SIZEP3"),
+    (0xd00c, "P0PL", "SIZEM"),
+    (0xd00d, "P1PL", "GRAFP0"),
+    (0xd00e, "P2PL", "GRAFP1"),
+    (0xd00f, "P3PL", "GRAFP2"),
+    (0xd010, "TRIG0", "GRAFP3"),
+    (0xd011, "TRIG1", "GRAFM"),
+    (0xd012, "TRIG2", "COLPM0"),
+    (0xd013, "TRIG3", "COLPM1"),
+    (0xd014, "PAL", "COLPM2"),
+    (0xd015, "", "COLPM3"),
+    (0xd016, "", "COLPF0"),
+    (0xd017, "", "COLPF1"),
+    (0xd018, "", "COLPF2"),
+    (0xd019, "", "COLPF3"),
+    (0xd01a, "", "COLBK"),
+    (0xd01b, "", "PRIOR"),
+    (0xd01c, "", "VDELAY"),
+    (0xd01d, "", "GRACTL"),
+    (0xd01e, "", "HITCLR"),
+    (0xd01f, "CONSOL", "CONSOL"),
+    // POKEY
+    (0xd200, "POT0", "AUDF1"),
+    (0xd201, "POT1", "AUDC1"),
+    (0xd202, "POT2", "AUDF2"),
+    (0xd203, "POT3", "AUDC2"),
+    (0xd204, "POT4", "AUDF3"),
+    (0xd205, "POT5", "AUDC3"),
+    (0xd206, "POT6", "AUDF4"),
+    (0xd207, "POT7", "AUDC4"),
+    (0xd208, "ALLPOT", "AUDCTL"),
+    (0xd209, "KBCODE", "STIMER"),
+    (0xd20a, "RANDOM", "SKRES"),
+    (0xd20b, "", "POTGO"),
+    (0xd20d, "SERIN", "SEROUT"),
+    (0xd20e, "IRQST", "IRQEN"),
+    (0xd20f, "SKSTAT", "SKCTL"),
+    // PIA
+    (0xd300, "PORTA", "PORTA"),
+    (0xd301, "PORTB", "PORTB"),
+    (0xd302, "PACTL", "PACTL"),
+    (0xd303, "PBCTL", "PBCTL"),
+    // ANTIC
+    (0xd400, "", "DMACTL"),
+    (0xd401, "", "CHACTL"),
+    (0xd402, "", "DLISTL"),
+    (0xd403, "", "DLISTH"),
+    (0xd404, "", "HSCROL"),
+    (0xd405, "", "VSCROL"),
+    (0xd407, "", "PMBASE"),
+    (0xd409, "", "CHBASE"),
+    (0xd40a, "", "WSYNC"),
+    (0xd40b, "VCOUNT", ""),
+    (0xd40c, "PENH", ""),
+    (0xd40d, "PENV", ""),
+    (0xd40e, "", "NMIEN"),
+    (0xd40f, "NMIST", "NMIRES"),
+    // ROM vectors of the handlers
+    (0xe400, "EDITRV", "EDITRV"),
+    (0xe410, "SCRENV", "SCRENV"),
+    (0xe420, "KEYBDV", "KEYBDV"),
+    (0xe430, "PRINTV", "PRINTV"),
+    (0xe440, "CASETV", "CASETV"),
+    // 6502 vectors
+    (0xfffa, "NMIVEC", "NMIVEC"),
+    (0xfffc, "RESVEC", "RESVEC"),
+    (0xfffe, "IRQVEC", "IRQVEC"),
+    // cartridge
+    (0xbffa, "CARTCS", "CARTCS"),
+    (0xbffc, "CART", "CART"),
+    (0xbffd, "CARTFG", "CARTFG"),
+    (0xbffe, "CARTAD", "CARTAD")
+];
+
+// OS zero page, page 2 and 3 variables and the ROM jump vectors.
+pub const OS: &[(u16, &str)] = &[
+    // zero page
+    (0x0000, "LINZBS"),
+    (0x0002, "CASINI"),
+    (0x0004, "RAMLO"),
+    (0x0006, "TRAMSZ"),
+    (0x0007, "TSTDAT"),
+    (0x0008, "WARMST"),
+    (0x0009, "BOOTQ"),
+    (0x000a, "DOSVEC"),
+    (0x000c, "DOSINI"),
+    (0x000e, "APPMHI"),
+    (0x0010, "POKMSK"),
+    (0x0011, "BRKKEY"),
+    (0x0012, "RTCLOK"),
+    (0x0015, "BUFADR"),
+    (0x0017, "ICCOMT"),
+    (0x0018, "DSKFMS"),
+    (0x001a, "DSKUTL"),
+    (0x001c, "PTIMOT"),
+    (0x001d, "PBPNT"),
+    (0x001e, "PBUFSZ"),
+    (0x001f, "PTEMP"),
+    (0x0020, "ICHIDZ"),
+    (0x0021, "ICDNOZ"),
+    (0x0022, "ICCOMZ"),
+    (0x0023, "ICSTAZ"),
+    (0x0024, "ICBALZ"),
+    (0x0025, "ICBAHZ"),
+    (0x0026, "ICPTLZ"),
+    (0x0027, "ICPTHZ"),
+    (0x0028, "ICBLLZ"),
+    (0x0029, "ICBLHZ"),
+    (0x002a, "ICAX1Z"),
+    (0x002b, "ICAX2Z"),
+    (0x002c, "ICAX3Z"),
+    (0x002d, "ICAX4Z"),
+    (0x002e, "ICAX5Z"),
+    (0x002f, "ICAX6Z"),
+    (0x0030, "STATUS"),
+    (0x0031, "CHKSUM"),
+    (0x0032, "BUFRLO"),
+    (0x0033, "BUFRHI"),
+    (0x0034, "BFENLO"),
+    (0x0035, "BFENHI"),
+    (0x0036, "CRETRY"),
+    (0x0037, "DRETRY"),
+    (0x0038, "BUFRFL"),
+    (0x0039, "RECVDN"),
+    (0x003a, "XMTDON"),
+    (0x003b, "CHKSNT"),
+    (0x003c, "NOCKSM"),
+    (0x003d, "BPTR"),
+    (0x003e, "FTYPE"),
+    (0x003f, "FEOF"),
+    (0x0040, "FREQ"),
+    (0x0041, "SOUNDR"),
+    (0x0042, "CRITIC"),
+    (0x0043, "FMSZPG"),
+    (0x004d, "ATRACT"),
+    (0x004e, "DRKMSK"),
+    (0x004f, "COLRSH"),
+    (0x0050, "TEMP"),
+    (0x0051, "HOLD1"),
+    (0x0052, "LMARGN"),
+    (0x0053, "RMARGN"),
+    (0x0054, "ROWCRS"),
+    (0x0055, "COLCRS"),
+    (0x0057, "DINDEX"),
+    (0x0058, "SAVMSC"),
+    (0x005a, "OLDROW"),
+    (0x005b, "OLDCOL"),
+    (0x005d, "OLDCHR"),
+    (0x005e, "OLDADR"),
+    (0x0062, "PALNTS"),
+    (0x0063, "LOGCOL"),
+    (0x0064, "ADRESS"),
+    (0x0066, "MLTTMP"),
+    (0x0068, "SAVADR"),
+    (0x006a, "RAMTOP"),
+    (0x006b, "BUFCNT"),
+    (0x006c, "BUFSTR"),
+    (0x006e, "BITMSK"),
+    (0x006f, "SHFAMT"),
+    (0x0070, "ROWAC"),
+    (0x0072, "COLAC"),
+    (0x0074, "ENDPT"),
+    (0x0076, "DELTAR"),
+    (0x0077, "DELTAC"),
+    (0x0079, "KEYDEF"),
+    (0x007b, "SWPFLG"),
+    (0x007c, "HOLDCH"),
+    (0x007d, "INSDAT"),
+    (0x007e, "COUNTR"),
+    // page 2
+    (0x0200, "VDSLST"),
+    (0x0202, "VPRCED"),
+    (0x0204, "VINTER"),
+    (0x0206, "VBREAK"),
+    (0x0208, "VKEYBD"),
+    (0x020a, "VSERIN"),
+    (0x020c, "VSEROR"),
+    (0x020e, "VSEROC"),
+    (0x0210, "VTIMR1"),
+    (0x0212, "VTIMR2"),
+    (0x0214, "VTIMR4"),
+    (0x0216, "VIMIRQ"),
+    (0x0218, "CDTMV1"),
+    (0x021a, "CDTMV2"),
+    (0x021c, "CDTMV3"),
+    (0x021e, "CDTMV4"),
+    (0x0220, "CDTMV5"),
+    (0x0222, "VVBLKI"),
+    (0x0224, "VVBLKD"),
+    (0x0226, "CDTMA1"),
+    (0x0228, "CDTMA2"),
+    (0x022a, "CDTMF3"),
+    (0x022b, "SRTIMR"),
+    (0x022c, "CDTMF4"),
+    (0x022d, "INTEMP"),
+    (0x022e, "CDTMF5"),
+    (0x022f, "SDMCTL"),
+    (0x0230, "SDLSTL"),
+    (0x0231, "SDLSTH"),
+    (0x0232, "SSKCTL"),
+    (0x0233, "LCOUNT"),
+    (0x0234, "LPENH"),
+    (0x0235, "LPENV"),
+    (0x0236, "BRKKY"),
+    (0x0238, "VPIRQ"),
+    (0x023a, "CDEVIC"),
+    (0x023b, "CCOMND"),
+    (0x023c, "CAUX1"),
+    (0x023d, "CAUX2"),
+    (0x023f, "ERRFLG"),
+    (0x0240, "DFLAGS"),
+    (0x0241, "DBSECT"),
+    (0x0242, "BOOTAD"),
+    (0x0244, "COLDST"),
+    (0x0246, "DSKTIM"),
+    (0x026f, "GPRIOR"),
+    (0x0270, "PADDL0"),
+    (0x0271, "PADDL1"),
+    (0x0272, "PADDL2"),
+    (0x0273, "PADDL3"),
+    (0x0274, "PADDL4"),
+    (0x0275, "PADDL5"),
+    (0x0276, "PADDL6"),
+    (0x0277, "PADDL7"),
+    (0x0278, "STICK0"),
+    (0x0279, "STICK1"),
+    (0x027a, "STICK2"),
+    (0x027b, "STICK3"),
+    (0x027c, "PTRIG0"),
+    (0x027d, "PTRIG1"),
+    (0x027e, "PTRIG2"),
+    (0x027f, "PTRIG3"),
+    (0x0280, "PTRIG4"),
+    (0x0281, "PTRIG5"),
+    (0x0282, "PTRIG6"),
+    (0x0283, "PTRIG7"),
+    (0x0284, "STRIG0"),
+    (0x0285, "STRIG1"),
+    (0x0286, "STRIG2"),
+    (0x0287, "STRIG3"),
+    (0x02a2, "ESCFLG"),
+    (0x02be, "SHFLOK"),
+    (0x02bf, "BOTSCR"),
+    (0x02c0, "PCOLR0"),
+    (0x02c1, "PCOLR1"),
+    (0x02c2, "PCOLR2"),
+    (0x02c3, "PCOLR3"),
+    (0x02c4, "COLOR0"),
+    (0x02c5, "COLOR1"),
+    (0x02c6, "COLOR2"),
+    (0x02c7, "COLOR3"),
+    (0x02c8, "COLOR4"),
+    (0x02e0, "RUNAD"),
+    (0x02e2, "INITAD"),
+    (0x02e4, "RAMSIZ"),
+    (0x02e5, "MEMTOP"),
+    (0x02e7, "MEMLO"),
+    (0x02ea, "DVSTAT"),
+    (0x02ee, "CBAUDL"),
+    (0x02ef, "CBAUDH"),
+    (0x02f0, "CRSINH"),
+    (0x02f1, "KEYDEL"),
+    (0x02f2, "CH1"),
+    (0x02f3, "CHACT"),
+    (0x02f4, "CHBAS"),
+    (0x02fa, "CHAR"),
+    (0x02fb, "ATACHR"),
+    (0x02fc, "CH"),
+    (0x02fd, "FILDAT"),
+    (0x02fe, "DSPFLG"),
+    (0x02ff, "SSFLAG"),
+    // page 3
+    (0x0300, "DDEVIC"),
+    (0x0301, "DUNIT"),
+    (0x0302, "DCOMND"),
+    (0x0303, "DSTATS"),
+    (0x0304, "DBUFLO"),
+    (0x0305, "DBUFHI"),
+    (0x0306, "DTIMLO"),
+    (0x0307, "DUNUSE"),
+    (0x0308, "DBYTLO"),
+    (0x0309, "DBYTHI"),
+    (0x030a, "DAUX1"),
+    (0x030b, "DAUX2"),
+    (0x031a, "HATABS"),
+    (0x0340, "ICHID"),
+    (0x0341, "ICDNO"),
+    (0x0342, "ICCOM"),
+    (0x0343, "ICSTA"),
+    (0x0344, "ICBAL"),
+    (0x0345, "ICBAH"),
+    (0x0346, "ICPTL"),
+    (0x0347, "ICPTH"),
+    (0x0348, "ICBLL"),
+    (0x0349, "ICBLH"),
+    (0x034a, "ICAX1"),
+    (0x034b, "ICAX2"),
+    (0x034c, "ICAX3"),
+    (0x034d, "ICAX4"),
+    (0x034e, "ICAX5"),
+    (0x034f, "ICAX6"),
+    (0x03c0, "PRNBUF"),
+    (0x03fd, "CASBUF"),
+    // ROM jump vectors
+    (0xe450, "DISKIV"),
+    (0xe453, "DSKINV"),
+    (0xe456, "CIOV"),
+    (0xe459, "SIOV"),
+    (0xe45c, "SETVBV"),
+    (0xe45f, "SYSVBV"),
+    (0xe462, "XITVBV"),
+    (0xe465, "SIOINV"),
+    (0xe468, "SENDEV"),
+    (0xe46b, "INTINV"),
+    (0xe46e, "CIOINV"),
+    (0xe471, "BLKBDV"),
+    (0xe474, "WARMSV"),
+    (0xe477, "COLDSV"),
+    (0xe47a, "RBLOKV"),
+    (0xe47d, "CSOPIV")
+];
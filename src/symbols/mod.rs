@@ -0,0 +1,380 @@
+// Symbol tables - names for addresses, loaded from assembler label files.
+//
+// Understood formats, detected line by line (ca65 .dbg by its header):
+//
+//   00 2000 START                         MADS .lab, the first column is the bank
+//   al C:2000 .start                      VICE labels, as written by ld65 -Ln
+//   sym id=0,name="start",...,val=0x2000  ca65 / ld65 .dbg
+//   start = $2000                         plain lists, also `equ`
+//
+// Symbols can belong to a bank of banked memory. Lookups without a bank see only
+// the symbols without one, lookups in a bank prefer that bank's symbols. MADS bank
+// 0 is the main memory, so it counts as no bank.
+//
+// Hardware registers often mean something else when read than when written, so a
+// symbol can be limited to one direction of access.
+
+mod atari;
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Direction {
+    Both,
+    Read,
+    Write
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u16,
+    pub bank: Option<u8>,
+    pub direction: Direction
+}
+
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub enum SymbolError {
+    Io(String),
+    Syntax { line: usize, text: String } // line counted from 1
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolError::Io(error) => write!(f, "{}", error),
+            SymbolError::Syntax { line, text } => write!(f, "line {}: cannot parse \"{}\"", line, text)
+        }
+    }
+}
+
+impl Error for SymbolError {}
+
+#[derive(Clone, Debug, Default)]
+pub struct Symbols {
+    by_address: BTreeMap<u16, Vec<Symbol>>, // in the order of loading, the first one is preferred
+    by_name: HashMap<String, Symbol>
+}
+
+fn parse_number(text: &str) -> Option<u16> {
+    let text = text.trim();
+
+    if let Some(hex) = text.strip_prefix('$').or_else(|| text.strip_prefix("0x")).or_else(|| text.strip_prefix("0X")) {
+        return u16::from_str_radix(hex, 16).ok();
+    }
+
+    return text.parse().ok();
+}
+
+fn is_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '.' || c == '@' || c == '?') &&
+        text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '@' || c == '?')
+}
+
+// MADS: bank, address, name
+fn parse_mads(line: &str) -> Option<Symbol> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() != 3 || !is_name(fields[2]) {
+        return None;
+    }
+
+    let bank = u8::from_str_radix(fields[0], 16).ok()?;
+    let address = u16::from_str_radix(fields[1], 16).ok()?;
+
+    Some(Symbol { name: fields[2].to_string(), address: address, bank: if bank == 0 { None } else { Some(bank) }, direction: Direction::Both })
+}
+
+// VICE: al C:address .name
+fn parse_vice(line: &str) -> Option<Symbol> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() != 3 || fields[0] != "al" {
+        return None;
+    }
+
+    let address = fields[1].trim_start_matches("C:");
+    let address = u16::from_str_radix(address, 16).ok()?;
+    let name = fields[2].trim_start_matches('.');
+
+    Some(Symbol { name: name.to_string(), address: address, bank: None, direction: Direction::Both })
+}
+
+// ca65: sym id=0,name="start",addrsize=absolute,...,val=0x2000,type=lab
+fn parse_dbg(line: &str) -> Option<Symbol> {
+    let attributes = line.strip_prefix("sym")?.trim_start();
+    let mut name = None;
+    let mut value = None;
+
+    for attribute in attributes.split(',') {
+        let mut parts = attribute.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some("name"), Some(text)) => name = Some(text.trim_matches('"')),
+            (Some("val"), Some(text)) => value = parse_number(text),
+            _ => {}
+        }
+    }
+
+    Some(Symbol { name: name?.to_string(), address: value?, bank: None, direction: Direction::Both })
+}
+
+// Plain: name = $address, or name equ $address
+fn parse_plain(line: &str) -> Option<Symbol> {
+    let (name, value) = match line.find('=') {
+        Some(index) => (&line[..index], &line[index + 1..]),
+        None => {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 3 || !fields[1].eq_ignore_ascii_case("equ") {
+                return None;
+            }
+            (fields[0], fields[2])
+        }
+    };
+
+    let name = name.trim();
+    if !is_name(name) {
+        return None;
+    }
+
+    Some(Symbol { name: name.to_string(), address: parse_number(value)?, bank: None, direction: Direction::Both })
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    // The built-in names of the Atari hardware registers, OS variables and ROM vectors.
+    pub fn atari() -> Symbols {
+        let mut symbols = Symbols::new();
+
+        for &(address, read, write) in atari::HARDWARE.iter() {
+            let directions = if read == write {
+                vec![(read, Direction::Both)]
+            } else {
+                vec![(read, Direction::Read), (write, Direction::Write)]
+            };
+
+            for (name, direction) in directions.into_iter().filter(|(name, _)| !name.is_empty()) {
+                symbols.insert(Symbol { name: name.to_string(), address: address, bank: None, direction: direction });
+            }
+        }
+
+        for &(address, name) in atari::OS.iter() {
+            symbols.insert(Symbol { name: name.to_string(), address: address, bank: None, direction: Direction::Both });
+        }
+
+        symbols
+    }
+
+    // Adds the other symbols after these, which stay preferred.
+    pub fn merge(&mut self, other: &Symbols) {
+        for symbol in other.iter() {
+            self.insert(symbol.clone());
+        }
+    }
+
+    pub fn insert(&mut self, symbol: Symbol) {
+        let symbols = self.by_address.entry(symbol.address).or_default();
+        if symbols.contains(&symbol) {
+            return;
+        }
+
+        symbols.push(symbol.clone());
+        self.by_name.entry(symbol.name.clone()).or_insert(symbol);
+    }
+
+    // Adds the symbols of a label file, returns how many lines defined one.
+    pub fn parse(&mut self, text: &str) -> Result<usize, SymbolError> {
+        let dbg = text.starts_with("version\t");
+        let mut count = 0;
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+
+            let symbol = if dbg {
+                parse_dbg(line)
+            } else {
+                if line.is_empty() || line.starts_with(';') || line.starts_with("mads ") || line == "Label table:" {
+                    continue;
+                }
+
+                let line = line.split(';').next().unwrap().trim();
+                let symbol = parse_vice(line).or_else(|| parse_mads(line)).or_else(|| parse_plain(line));
+                if symbol.is_none() {
+                    return Err(SymbolError::Syntax { line: index + 1, text: line.to_string() });
+                }
+                symbol
+            };
+
+            if let Some(symbol) = symbol {
+                self.insert(symbol);
+                count += 1;
+            }
+        }
+
+        return Ok(count);
+    }
+
+    pub fn load(&mut self, path: &str) -> Result<usize, SymbolError> {
+        let text = fs::read_to_string(path).map_err(|error| SymbolError::Io(error.to_string()))?;
+        return self.parse(&text);
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_address.values().map(|symbols| symbols.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_address.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.by_address.values().flat_map(|symbols| symbols.iter())
+    }
+
+    // Prefers symbols of the bank over unbanked ones, then the direction over both
+    // directions over the other direction.
+    fn find(&self, address: u16, bank: Option<u8>, direction: Direction) -> Option<&str> {
+        let symbols = self.by_address.get(&address)?;
+
+        symbols.iter()
+            .filter(|symbol| symbol.bank.is_none() || symbol.bank == bank)
+            .min_by_key(|symbol| {
+                let direction = if symbol.direction == direction { 0 } else if symbol.direction == Direction::Both { 1 } else { 2 };
+                (symbol.bank.is_none(), direction)
+            })
+            .map(|symbol| symbol.name.as_str())
+    }
+
+    // Name of the address outside of any bank.
+    pub fn name(&self, address: u16) -> Option<&str> {
+        self.find(address, None, Direction::Both)
+    }
+
+    // Name of the address as it is read or written, `WSYNC` rather than `VCOUNT` for a store to $D40A.
+    pub fn name_for(&self, address: u16, direction: Direction) -> Option<&str> {
+        self.find(address, None, direction)
+    }
+
+    // Name of the address with the bank switched in, falling back to the unbanked names.
+    pub fn name_in_bank(&self, address: u16, bank: u8) -> Option<&str> {
+        self.find(address, Some(bank), Direction::Both)
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.by_name.get(name)
+    }
+
+    // The name, or the address in hex when there is none.
+    pub fn describe(&self, address: u16) -> String {
+        match self.name(address) {
+            Some(name) => name.to_string(),
+            None => format!("${:04x}", address)
+        }
+    }
+
+    // Parses an address typed by the user: a name, a name with an offset (`name+3`),
+    // or hex with an optional $ or 0x prefix. Names win over bare hex like `add`.
+    pub fn parse_address(&self, text: &str) -> Option<u16> {
+        let text = text.trim();
+
+        if let Some(symbol) = self.by_name.get(text) {
+            return Some(symbol.address);
+        }
+
+        if let Some(index) = text.rfind('+').filter(|&index| index > 0) {
+            let base = self.parse_address(&text[..index])?;
+            let offset = parse_number(&text[index + 1..])?;
+            return Some(base.wrapping_add(offset));
+        }
+
+        let digits = text.trim_start_matches('$').trim_start_matches("0x");
+        return u16::from_str_radix(digits, 16).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Direction;
+    use super::Symbol;
+    use super::SymbolError;
+    use super::Symbols;
+
+    #[test]
+    fn test_mads_and_vice() {
+        let mut symbols = Symbols::new();
+        let mads = "mads 2.1.0 build 8 (23 Dec 19)\nLabel table:\n00\t2000\tSTART\n00\t2010\tLOOP\n01\t4000\tBANKED\n";
+        assert_eq!(symbols.parse(mads), Ok(3));
+        assert_eq!(symbols.parse("al C:3000 .print\nal C:2000 .main\n"), Ok(2));
+
+        assert_eq!(symbols.name(0x2000), Some("START"));
+        assert_eq!(symbols.name(0x3000), Some("print"));
+        assert_eq!(symbols.name(0x4000), None);
+        assert_eq!(symbols.name_in_bank(0x4000, 1), Some("BANKED"));
+        assert_eq!(symbols.name_in_bank(0x2010, 1), Some("LOOP"));
+        assert_eq!(symbols.symbol("main"), Some(&Symbol { name: "main".to_string(), address: 0x2000, bank: None, direction: Direction::Both }));
+        assert_eq!(symbols.len(), 5);
+    }
+
+    #[test]
+    fn test_dbg_and_plain() {
+        let mut symbols = Symbols::new();
+        let dbg = "version\tmajor=2,minor=0\n\
+                   file\tid=0,name=\"main.s\",size=100,mtime=0x5E000000,mod=0\n\
+                   sym\tid=0,name=\"start\",addrsize=absolute,scope=0,def=3,ref=5,val=0x2000,seg=0,type=lab\n\
+                   sym\tid=1,name=\"chrout\",addrsize=absolute,scope=0,def=4,type=imp\n";
+        assert_eq!(symbols.parse(dbg), Ok(1));
+        assert_eq!(symbols.parse("; OS\nWSYNC = $D40A\nSDLSTL equ $0230 ; display list\nVAL=10\n"), Ok(3));
+
+        assert_eq!(symbols.describe(0x2000), "start");
+        assert_eq!(symbols.describe(0xd40a), "WSYNC");
+        assert_eq!(symbols.describe(0x000a), "VAL");
+        assert_eq!(symbols.describe(0x0231), "$0231");
+
+        assert_eq!(symbols.parse("start: lda #1"), Err(SymbolError::Syntax { line: 1, text: "start: lda #1".to_string() }));
+    }
+
+    #[test]
+    fn test_atari() {
+        let symbols = Symbols::atari();
+
+        assert_eq!(symbols.describe(0xd40a), "WSYNC");
+        assert_eq!(symbols.describe(0x0230), "SDLSTL");
+        assert_eq!(symbols.describe(0xe456), "CIOV");
+        assert_eq!(symbols.name_for(0xd000, Direction::Read), Some("M0PF"));
+        assert_eq!(symbols.name_for(0xd000, Direction::Write), Some("HPOSP0"));
+        assert_eq!(symbols.name_for(0xd20a, Direction::Read), Some("RANDOM"));
+        assert_eq!(symbols.name_for(0xd01f, Direction::Write), Some("CONSOL"));
+        assert_eq!(symbols.name_for(0xd40b, Direction::Write), Some("VCOUNT")); // no write name
+        assert_eq!(symbols.parse_address("NMIEN"), Some(0xd40e));
+
+        // every name stands for one address
+        for symbol in symbols.iter() {
+            assert_eq!(symbols.symbol(&symbol.name).unwrap().address, symbol.address, "{}", symbol.name);
+        }
+
+        let mut user = Symbols::new();
+        user.parse("frame = $D40A\n").unwrap();
+        user.merge(&symbols);
+        assert_eq!(user.describe(0xd40a), "frame");
+        assert_eq!(user.describe(0xd40b), "VCOUNT");
+    }
+
+    #[test]
+    fn test_parse_address() {
+        let mut symbols = Symbols::new();
+        symbols.parse("add = $0600\nSDLSTL = $0230\n").unwrap();
+
+        assert_eq!(symbols.parse_address("add"), Some(0x0600));
+        assert_eq!(symbols.parse_address("SDLSTL+1"), Some(0x0231));
+        assert_eq!(symbols.parse_address("SDLSTL+$10"), Some(0x0240));
+        assert_eq!(symbols.parse_address("$E459"), Some(0xe459));
+        assert_eq!(symbols.parse_address("0xe459"), Some(0xe459));
+        assert_eq!(symbols.parse_address("e459"), Some(0xe459));
+        assert_eq!(symbols.parse_address("nothing"), None);
+    }
+}
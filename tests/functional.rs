@@ -0,0 +1,111 @@
+// Klaus Dormann's 6502 test suite - https://github.com/Klaus2m5/6502_65C02_functional_tests
+//
+// Every test traps (jumps or branches to itself) when it is done. A trap at the
+// success label means all tests passed, any other trap points at the failed test.
+// The decimal test (Bruce Clark's, which the suite uses) is assembled from its source
+// in examples/ with the built-in assembler.
+//
+// Klaus Dormann's interrupt test is not included. In its place runs a local test of
+// this project (examples/local_interrupt_test.s), which drives IRQ and NMI through a
+// feedback port the same way.
+
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use std::cell::Cell;
+use std::fs;
+
+use atari::cpu::Condition;
+use atari::cpu::Cpu;
+use atari::cpu::StopReason;
+
+const CYCLE_LIMIT: usize = 200_000_000;
+
+const TEST_CASE: usize = 0x0200; // number of the current test in the functional and local interrupt tests
+const FUNCTIONAL_SUCCESS: u16 = 0x3469;
+const LOCAL_INTERRUPT_SUCCESS: u16 = 0x04bb;
+const LOCAL_INTERRUPT_PORT: u16 = 0xbffc; // bit 0 drives IRQ, bit 1 drives NMI
+const DECIMAL_START: u16 = 0x0200;
+const DECIMAL_ERROR: usize = 0x000b;
+
+fn load(path: &str) -> Vec<u8> {
+    let image = fs::read(path).unwrap_or_else(|error| panic!("Cannot read {}: {}", path, error));
+    assert_eq!(image.len(), 65536, "{} is not a 64KB memory image", path);
+
+    image
+}
+
+fn run_until_trap(cpu: &mut Cpu) -> u16 {
+    match cpu.run_until(Condition::SelfJump, CYCLE_LIMIT) {
+        StopReason::SelfJump(pc) => pc,
+        reason => panic!("No trap within {} cycles ({:?}), PC: ${:04x}", CYCLE_LIMIT, reason, cpu.register().pc())
+    }
+}
+
+#[test]
+fn test_functional() {
+    let mut memory = load("examples/test.mem");
+
+    let trap = {
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.cold_reset();
+
+        run_until_trap(&mut cpu)
+    };
+
+    assert_eq!(trap, FUNCTIONAL_SUCCESS, "Functional test ${:02x} failed at ${:04x}", memory[TEST_CASE], trap);
+}
+
+#[test]
+fn test_decimal() {
+    let mut memory = load("examples/6502_decimal_test.mem");
+
+    let trap = {
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.cold_reset();
+        cpu.register_mut().set_pc(DECIMAL_START);
+
+        run_until_trap(&mut cpu)
+    };
+
+    assert_eq!(memory[DECIMAL_ERROR], 0, "Decimal test failed at ${:04x}", trap);
+}
+
+#[test]
+fn test_local_interrupt() {
+    let mut memory = load("examples/local_interrupt_test.mem");
+    let port = Cell::new(0u8);
+
+    let trap = {
+        let mut cpu = Cpu::new(&mut memory);
+        cpu.on_write(|address, value, _access| {
+            if address == LOCAL_INTERRUPT_PORT {
+                port.set(value);
+            }
+        });
+        cpu.cold_reset();
+
+        let mut nmi_line = false;
+        loop {
+            // NMI triggers on the rising edge, IRQ as long as the line is held
+            if port.get() & 0x02 != 0 && !nmi_line {
+                cpu.nmi();
+            }
+            nmi_line = port.get() & 0x02 != 0;
+
+            if port.get() & 0x01 != 0 {
+                cpu.irq();
+            }
+
+            let pc = cpu.register().pc();
+            cpu.step();
+
+            if cpu.register().pc() == pc {
+                break pc;
+            }
+
+            assert!(cpu.cycles < CYCLE_LIMIT, "No trap within {} cycles, PC: ${:04x}", CYCLE_LIMIT, pc);
+        }
+    };
+
+    assert_eq!(trap, LOCAL_INTERRUPT_SUCCESS, "Local interrupt test ${:02x} failed at ${:04x}", memory[TEST_CASE], trap);
+}
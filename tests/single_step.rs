@@ -0,0 +1,205 @@
+// Per-instruction test vectors in the SingleStepTests format - https://github.com/SingleStepTests/65x02
+//
+// Every opcode has its own file (like `a9.json`), holding a list of vectors. A vector
+// is the initial state, the expected state after executing one instruction, and the
+// bus activity for every cycle:
+//
+// {
+//   "name": "a9 5c 28",
+//   "initial": { "pc": 1024, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36, "ram": [[1024, 169], [1025, 92]] },
+//   "final":   { "pc": 1026, "s": 253, "a": 92, "x": 0, "y": 0, "p": 36, "ram": [[1024, 169], [1025, 92]] },
+//   "cycles":  [[1024, 169, "read"], [1025, 92, "read"]]
+// }
+//
+// A few vectors for some opcodes, made from the documented NMOS bus cycles ("64doc")
+// rather than with this emulator, are shipped in tests/single_step and always run with
+// the bus activity compared. For the full suite, point SINGLE_STEP_TESTS at the
+// directory with the files (defaults to tests/single_step/6502/v1) and run
+// `cargo test -- --ignored`. Set SINGLE_STEP_BUS=1 to compare the bus activity there too.
+
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use serde_json::Value;
+
+use atari::cpu::Cpu;
+
+const DEFAULT_DIRECTORY: &str = "tests/single_step/6502/v1";
+const BUNDLED_DIRECTORY: &str = "tests/single_step";
+const REPORTED_FAILURES: usize = 5; // per opcode
+
+// Opcodes implemented by the CPU, the rest is reported as skipped.
+const DOCUMENTED: [u8; 151] = [
+    0x00, 0x01, 0x05, 0x06, 0x08, 0x09, 0x0a, 0x0d, 0x0e, 0x10, 0x11, 0x15, 0x16, 0x18, 0x19, 0x1d,
+    0x1e, 0x20, 0x21, 0x24, 0x25, 0x26, 0x28, 0x29, 0x2a, 0x2c, 0x2d, 0x2e, 0x30, 0x31, 0x35, 0x36,
+    0x38, 0x39, 0x3d, 0x3e, 0x40, 0x41, 0x45, 0x46, 0x48, 0x49, 0x4a, 0x4c, 0x4d, 0x4e, 0x50, 0x51,
+    0x55, 0x56, 0x58, 0x59, 0x5d, 0x5e, 0x60, 0x61, 0x65, 0x66, 0x68, 0x69, 0x6a, 0x6c, 0x6d, 0x6e,
+    0x70, 0x71, 0x75, 0x76, 0x78, 0x79, 0x7d, 0x7e, 0x81, 0x84, 0x85, 0x86, 0x88, 0x8a, 0x8c, 0x8d,
+    0x8e, 0x90, 0x91, 0x94, 0x95, 0x96, 0x98, 0x99, 0x9a, 0x9d, 0xa0, 0xa1, 0xa2, 0xa4, 0xa5, 0xa6,
+    0xa8, 0xa9, 0xaa, 0xac, 0xad, 0xae, 0xb0, 0xb1, 0xb4, 0xb5, 0xb6, 0xb8, 0xb9, 0xba, 0xbc, 0xbd,
+    0xbe, 0xc0, 0xc1, 0xc4, 0xc5, 0xc6, 0xc8, 0xc9, 0xca, 0xcc, 0xcd, 0xce, 0xd0, 0xd1, 0xd5, 0xd6,
+    0xd8, 0xd9, 0xdd, 0xde, 0xe0, 0xe1, 0xe4, 0xe5, 0xe6, 0xe8, 0xe9, 0xea, 0xec, 0xed, 0xee, 0xf0,
+    0xf1, 0xf5, 0xf6, 0xf8, 0xf9, 0xfd, 0xfe
+];
+
+struct State {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(usize, u8)>
+}
+
+fn number(value: &Value) -> u64 {
+    value.as_u64().unwrap_or_else(|| panic!("Expected a number, got {}", value))
+}
+
+fn state(value: &Value) -> State {
+    State {
+        pc: number(&value["pc"]) as u16,
+        s: number(&value["s"]) as u8,
+        a: number(&value["a"]) as u8,
+        x: number(&value["x"]) as u8,
+        y: number(&value["y"]) as u8,
+        p: number(&value["p"]) as u8,
+        ram: value["ram"].as_array().unwrap().iter().map(|cell| (number(&cell[0]) as usize, number(&cell[1]) as u8)).collect()
+    }
+}
+
+fn bus_log(value: &Value) -> Vec<(u16, u8, bool)> {
+    value.as_array().unwrap().iter().map(|cycle| {
+        (number(&cycle[0]) as u16, number(&cycle[1]) as u8, cycle[2].as_str() == Some("write"))
+    }).collect()
+}
+
+// Returns the list of differences between the expected and the actual outcome.
+fn run(vector: &Value, check_bus: bool) -> Vec<String> {
+    let initial = state(&vector["initial"]);
+    let expected = state(&vector["final"]);
+    let expected_bus = bus_log(&vector["cycles"]);
+
+    let mut memory = vec![0u8; 65536];
+    for &(address, value) in initial.ram.iter() {
+        memory[address] = value;
+    }
+
+    let bus = RefCell::new(Vec::new());
+    let mut errors = Vec::new();
+
+    {
+        let mut cpu = Cpu::new(&mut memory);
+        {
+            let register = cpu.register_mut();
+            register.set_pc(initial.pc);
+            register.set_s(initial.s);
+            register.a = initial.a;
+            register.x = initial.x;
+            register.y = initial.y;
+            register.set_p(initial.p);
+        }
+
+        cpu.on_read(|address, value, _access| bus.borrow_mut().push((address, value, false)));
+        cpu.on_write(|address, value, _access| bus.borrow_mut().push((address, value, true)));
+        cpu.step();
+
+        let register = cpu.register();
+        let actual = [register.pc(), register.s() as u16, register.a as u16, register.x as u16, register.y as u16, register.p() as u16];
+        let wanted = [expected.pc, expected.s as u16, expected.a as u16, expected.x as u16, expected.y as u16, expected.p as u16];
+
+        for (name, (actual, wanted)) in ["pc", "s", "a", "x", "y", "p"].iter().zip(actual.iter().zip(wanted.iter())) {
+            if actual != wanted {
+                errors.push(format!("{}: ${:02x}, expected ${:02x}", name, actual, wanted));
+            }
+        }
+
+        if cpu.cycles != expected_bus.len() {
+            errors.push(format!("cycles: {}, expected {}", cpu.cycles, expected_bus.len()));
+        }
+    }
+
+    for &(address, value) in expected.ram.iter() {
+        if memory[address] != value {
+            errors.push(format!("${:04x}: ${:02x}, expected ${:02x}", address, memory[address], value));
+        }
+    }
+
+    if check_bus && *bus.borrow() != expected_bus {
+        errors.push(format!("bus: {:?}, expected {:?}", bus.borrow(), expected_bus));
+    }
+
+    errors
+}
+
+// Runs the vectors of every implemented opcode with a file in the directory, and returns
+// the opcodes which failed and how many opcodes were tested.
+fn run_directory(directory: &str, check_bus: bool) -> (Vec<u8>, usize) {
+    let mut failed_opcodes = Vec::new();
+    let mut tested = 0;
+
+    assert!(Path::new(directory).is_dir(), "No test vectors in {}", directory);
+
+    for opcode in 0..=255u8 {
+        let path = Path::new(directory).join(format!("{:02x}.json", opcode));
+
+        if !DOCUMENTED.contains(&opcode) {
+            println!("${:02x}: skipped, not implemented", opcode);
+            continue;
+        }
+
+        let file = match fs::read_to_string(&path) {
+            Ok(file) => file,
+            Err(_) => {
+                println!("${:02x}: skipped, no {}", opcode, path.display());
+                continue;
+            }
+        };
+
+        let vectors: Value = serde_json::from_str(&file).unwrap_or_else(|error| panic!("Cannot parse {}: {}", path.display(), error));
+        let vectors = vectors.as_array().unwrap();
+        let mut failures = 0;
+
+        for vector in vectors.iter() {
+            let errors = run(vector, check_bus);
+
+            if !errors.is_empty() {
+                if failures < REPORTED_FAILURES {
+                    println!("${:02x}: {} - {}", opcode, vector["name"], errors.join(", "));
+                }
+                failures += 1;
+            }
+        }
+
+        println!("${:02x}: {} of {} passed", opcode, vectors.len() - failures, vectors.len());
+        tested += 1;
+
+        if failures > 0 {
+            failed_opcodes.push(opcode);
+        }
+    }
+
+    return (failed_opcodes, tested);
+}
+
+#[test]
+fn test_bundled_vectors() {
+    let (failed_opcodes, tested) = run_directory(BUNDLED_DIRECTORY, true);
+
+    assert_eq!(tested, 4);
+    assert!(failed_opcodes.is_empty(), "Failed opcodes: {:02x?}", failed_opcodes);
+}
+
+#[test]
+#[ignore = "needs SingleStepTests vectors, see SINGLE_STEP_TESTS"]
+fn test_single_step() {
+    let directory = env::var("SINGLE_STEP_TESTS").unwrap_or_else(|_| DEFAULT_DIRECTORY.to_string());
+    let check_bus = env::var("SINGLE_STEP_BUS").map(|value| value == "1").unwrap_or(false);
+    let (failed_opcodes, _) = run_directory(&directory, check_bus);
+
+    assert!(failed_opcodes.is_empty(), "Failed opcodes: {:02x?}", failed_opcodes);
+}